@@ -0,0 +1,123 @@
+use anyhow::Result;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Address reported for clients of the Unix domain socket, which have none. With `accept_proxy_protocol`,
+/// the client address passed on by the proxy is used instead
+pub const UNIX_PEER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Where players connect: a TCP port, or a Unix domain socket for a proxy on the same host (`--unix-socket`)
+pub enum ClientListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl ClientListener {
+    /// Binds the Unix domain socket at `path`. A socket file left behind by a previous run is replaced
+    #[cfg(unix)]
+    pub fn bind_unix(path: &Path) -> Result<Self> {
+        use anyhow::Context as _;
+        use std::os::unix::fs::FileTypeExt;
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                anyhow::bail!("{} exists and is not a socket", path.display());
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("Cannot remove the old socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Cannot bind the Unix domain socket {}", path.display()))?;
+        Ok(ClientListener::Unix(listener))
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind_unix(_path: &Path) -> Result<Self> {
+        anyhow::bail!("Unix domain sockets (--unix-socket) are not supported on this platform")
+    }
+
+    /// Accepts the next client along with its address (see [`UNIX_PEER`])
+    pub async fn accept(&self) -> io::Result<(ClientStream, SocketAddr)> {
+        match self {
+            ClientListener::Tcp(listener) => {
+                let (socket, peer) = listener.accept().await?;
+                Ok((ClientStream::Tcp(socket), peer))
+            }
+            #[cfg(unix)]
+            ClientListener::Unix(listener) => {
+                let (socket, _) = listener.accept().await?;
+                Ok((ClientStream::Unix(socket), UNIX_PEER))
+            }
+        }
+    }
+}
+
+/// A client connection accepted by a [`ClientListener`]
+pub enum ClientStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl ClientStream {
+    /// Disables Nagle's algorithm of TCP connections, Unix domain sockets don't have it
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            ClientStream::Tcp(socket) => socket.set_nodelay(nodelay),
+            #[cfg(unix)]
+            ClientStream::Unix(_) => Ok(()),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(socket) => Pin::new(socket).poll_read(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(socket) => Pin::new(socket).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Tcp(socket) => Pin::new(socket).poll_write(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(socket) => Pin::new(socket).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(socket) => Pin::new(socket).poll_flush(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(socket) => Pin::new(socket).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Tcp(socket) => Pin::new(socket).poll_shutdown(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(socket) => Pin::new(socket).poll_shutdown(cx),
+        }
+    }
+}
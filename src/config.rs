@@ -1,26 +1,358 @@
-use anyhow::Result;
+use crate::schedule::{self, TimeWindow};
+use crate::server_log;
+use crate::stop::StopStep;
+use crate::wol;
+use anyhow::{Context, Result, bail};
 use base64::Engine;
 use base64::engine::general_purpose;
 use image::imageops::FilterType;
-use image::{DynamicImage, GenericImageView};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::Write;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv6Addr};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Where the idle watchdog gets the player count from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerCountSource {
+    /// Run the player count command via RCON
+    Rcon,
+    /// Write the player count command to the server's stdin and parse the server output
+    Console,
+}
+
+/// How the server is detected to be ready, so held and new connections are proxied to it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessMode {
+    /// Ready once the idle watchdog reaches the server (RCON, or the console with `player_count_source = "console"`)
+    Rcon,
+    /// Ready once the server prints its `Done (...)! For help, type "help"` line
+    Log,
+}
+
+/// Joins `host` (a hostname or IP address) and `port`. IPv6 addresses are put in brackets
+fn host_port(host: &str, port: u16) -> String {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Port of a `transfer_redirect` that doesn't name one
+const DEFAULT_TRANSFER_PORT: u16 = 25565;
+
+/// The server players are transferred to (`transfer_redirect`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferTarget {
+    /// Hostname or IP address, IPv6 addresses without brackets
+    pub host: String,
+    pub port: u16,
+}
+
+impl TransferTarget {
+    /// Parses `host`, `host:port`, an IPv6 address, or `[IPv6]:port`. The port defaults to 25565
+    pub fn parse(target: &str) -> Result<TransferTarget> {
+        let target = target.trim();
+        let (host, port) = if let Some(rest) = target.strip_prefix('[') {
+            let (host, after) = rest
+                .split_once(']')
+                .context("Missing `]` after the IPv6 address")?;
+            host.parse::<Ipv6Addr>()
+                .with_context(|| format!("Invalid IPv6 address `{}`", host))?;
+            let port = match after {
+                "" => None,
+                _ => Some(
+                    after
+                        .strip_prefix(':')
+                        .context("Expected `:port` after the IPv6 address")?,
+                ),
+            };
+            (host, port)
+        } else if target.parse::<Ipv6Addr>().is_ok() {
+            (target, None)
+        } else {
+            match target.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (target, None),
+            }
+        };
+        if host.is_empty() {
+            bail!("Missing host");
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("Invalid port `{}`", port))?,
+            None => DEFAULT_TRANSFER_PORT,
+        };
+        Ok(TransferTarget {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for TransferTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&host_port(&self.host, self.port))
+    }
+}
+
+/// Larger server-icon.png files are ignored instead of being read into memory
+const MAX_SERVER_ICON_FILE_SIZE: u64 = 5 * 1024 * 1024;
+
+// Missing fields are filled from the defaults, so configs of older versions keep working
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     pub rcon_poll_interval: u64,
     pub rcon_idle_timeout: u64,
+    pub auto_stop_on_idle: bool,
+    pub watchdog_start_delay: u64,
+    pub startup_timeout: u64,
+    pub rcon_startup_grace: u64,
+    pub rcon_connect_timeout: u64,
+    pub rcon_connect_retry_interval: u64,
+    pub rcon_poll_max_errors: u32,
+    /// Each poll interval is shifted by a random amount of up to this many percent either way
+    pub rcon_poll_jitter_percent: u32,
+    pub idle_warning_seconds: u64,
+    pub idle_warning_msg_text: String,
+    /// Announced via `say` once the server is ready, for players who were told it's starting. None sends nothing
+    pub ready_broadcast_message: Option<String>,
+    pub player_count_source: PlayerCountSource,
+    pub readiness_mode: ReadinessMode,
+    pub player_count_command: String,
+    pub player_count_pattern: String,
+    pub player_count_group: usize,
+    pub unknown_count_warn_polls: u32,
+    pub unknown_count_assume_empty_after: u64,
+    /// Players that don't keep the server awake, e.g. map render or camera bots. Matched case-insensitively
+    pub idle_ignore_players: Vec<String>,
     pub motd_text: String,
     pub motd_color: String,
     pub motd_bold: bool,
+    pub motd_text_line2: Option<String>,
+    pub motd_color_line2: Option<String>,
+    pub motd_bold_line2: Option<bool>,
+    pub motd_sample_lines: Option<Vec<String>>,
+    /// Raw JSON text component used as the MOTD instead of `motd_text` and the other simple fields
+    pub motd_component: Option<String>,
+    pub motd_spoof_protocol: bool,
+    /// Version shown in the server browser when the client's version doesn't match, `{protocol}` is replaced
+    /// with the reported protocol number
+    pub motd_version_name: String,
+    pub cache_server_motd: bool,
+    pub cached_motd_suffix: Option<String>,
+    pub motd_cache_interval: u64,
+    pub max_message_length: usize,
     pub server_icon: Option<String>,
     pub connection_msg_text: String,
     pub connection_msg_color: String,
     pub connection_msg_bold: bool,
+    /// Raw JSON text component used as the connection message instead of `connection_msg_text`
+    pub connection_msg_component: Option<String>,
+    /// Longest wait for the client to close the connection after a disconnect message, in milliseconds
+    pub disconnect_flush_delay_ms: u64,
+    pub starting_motd_text: Option<String>,
+    pub reconnect_msg_text: Option<String>,
+    pub backend_unreachable_msg_text: String,
+    pub stopping_msg_text: String,
+    pub wake_cooldown: u64,
+    pub cooldown_msg_text: String,
+    pub start_backoff_base: u64,
+    pub start_backoff_max: u64,
+    pub start_failed_msg_text: String,
+    pub whitelist: Option<Vec<String>>,
+    pub whitelist_msg_text: String,
+    /// Refuse players the server's own whitelist would kick, while `white-list=true` in `server_properties_path`
+    pub use_server_whitelist: bool,
+    pub server_whitelist_path: String,
+    pub server_whitelist_msg_text: String,
+    pub min_protocol_version: Option<i32>,
+    pub outdated_client_msg_text: String,
+    /// Longest handshake packet accepted, in bytes. Connections announcing a longer one are dropped right away
+    pub max_handshake_bytes: usize,
+    pub accept_transfers: bool,
+    pub accept_proxy_protocol: bool,
+    /// `host:port` players are transferred to instead of waking the server
+    pub transfer_redirect: Option<String>,
+    /// `transfer_redirect` parsed by [`Config::validate`]
+    #[serde(skip)]
+    pub transfer_target: Option<TransferTarget>,
+    pub transfer_unsupported_msg_text: String,
+    pub server_host: String,
+    pub rcon_host: String,
+    pub wol_mac: Option<String>,
+    pub wol_broadcast_addr: String,
+    pub wol_wait_port: u16,
+    pub wol_boot_timeout: u64,
+    pub proxy_connect_attempts: u32,
+    pub proxy_connect_retry_delay_ms: u64,
+    pub handoff_listener: bool,
+    pub detect_running_server: bool,
+    pub hold_connections_during_start: bool,
+    pub hold_connections_timeout: u64,
+    pub handshake_stats_log_interval: u64,
+    pub status_rate_limit: u32,
+    pub status_rate_burst: u32,
+    pub max_connections_per_minute: u32,
+    pub allow_cidrs: Vec<IpNet>,
+    pub deny_cidrs: Vec<IpNet>,
+    pub quiet_hours: Vec<String>,
+    pub quiet_hours_msg_text: String,
+    /// No player can wake the server, the server list shows `maintenance_motd_text` instead of the MOTD
+    pub maintenance_mode: bool,
+    pub maintenance_motd_text: String,
+    pub maintenance_msg_text: String,
+    pub scheduled_stop: Option<String>,
+    pub scheduled_stop_warning_seconds: u64,
+    pub scheduled_stop_msg_text: String,
+    pub log_buffer_lines: usize,
+    pub server_log_file: Option<String>,
+    pub server_log_max_size_mb: u64,
+    pub server_log_files: u32,
+    pub metrics_port: u16,
+    pub control_port: u16,
+    pub startup_webhook_url: Option<String>,
+    pub webhook_url: Option<String>,
+    pub wake_webhook_text: String,
+    pub stop_webhook_text: String,
+    pub stop_command_shell: Option<String>,
+    pub stop_timeout: u64,
+    pub shutdown_grace_period: u64,
+    pub stop_steps: Vec<StopStep>,
+    pub pre_start_hook: Option<String>,
+    pub post_stop_hook: Option<String>,
+    pub hook_timeout: u64,
+    pub server_working_dir: Option<String>,
+    /// On Windows, launch the server in a console window of its own. Without, it runs inline, e.g. for a service
+    pub server_window: bool,
+    pub command_path_base: CommandPathBase,
+    pub detect_server_properties: bool,
+    pub server_properties_path: String,
     config_directory_name: String,
+    pub config_conflict_check: bool,
+    pub watch_config: bool,
+    pub timeouts: TimeoutConfig,
+    pub forge_data: Option<ForgeData>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub idle_timeout_schedule: Vec<IdleTimeoutWindow>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerDefinition>,
+}
+
+/// One of the servers run by the `listen-all` subcommand. Unset options fall back to the top-level settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerDefinition {
+    /// Tells the servers apart in the logs and names the file recording the running server
+    pub name: String,
+    /// IP to bind, `0.0.0.0` if unset
+    pub listen_host: Option<String>,
+    pub listen_port: u16,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub working_dir: Option<String>,
+    pub server_port: u16,
+    pub rcon_port: u16,
+    pub rcon_pass: String,
+    pub rcon_idle_timeout: Option<u64>,
+    pub motd_text: Option<String>,
+}
+
+/// Directory a relative server command (e.g. `./start.sh`) is resolved against
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandPathBase {
+    /// The working directory this application was started in
+    CurrentDir,
+    /// The configuration directory (`config_directory_name`)
+    ConfigDir,
+    /// `server_working_dir`, or the current directory if it isn't set
+    ServerDir,
+}
+
+/// Time budgets for the phases of handling a connection, in seconds
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Waiting for the handshake and status request packets of a new connection
+    pub handshake: u64,
+    /// Delivering the MOTD or a disconnect message to the client
+    pub client_write: u64,
+    /// Connecting to the Minecraft server when proxying. 0 uses the operating system's timeout
+    pub backend_connect: u64,
+    /// Closing a proxied session after no data flowed in either direction. 0 disables it
+    pub proxy_idle: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            handshake: 5,
+            client_write: 5,
+            backend_connect: 0,
+            proxy_idle: 0,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn handshake(&self) -> Duration {
+        Duration::from_secs(self.handshake)
+    }
+
+    pub fn client_write(&self) -> Duration {
+        Duration::from_secs(self.client_write)
+    }
+
+    pub fn backend_connect(&self) -> Option<Duration> {
+        (self.backend_connect > 0).then(|| Duration::from_secs(self.backend_connect))
+    }
+
+    pub fn proxy_idle(&self) -> Option<Duration> {
+        (self.proxy_idle > 0).then(|| Duration::from_secs(self.proxy_idle))
+    }
+}
+
+/// Idle timeout applying during a daily time window instead of `rcon_idle_timeout`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IdleTimeoutWindow {
+    /// `HH:MM-HH:MM` in local time, may wrap around midnight
+    pub window: String,
+    /// Seconds
+    pub idle_timeout: u64,
+}
+
+/// Modpack information advertised to Forge clients in the status response
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForgeData {
+    pub fml_network_version: u32,
+    #[serde(default)]
+    pub mods: Vec<ForgeMod>,
+    #[serde(default)]
+    pub channels: Vec<ForgeChannel>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForgeMod {
+    pub mod_id: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForgeChannel {
+    pub res: String,
+    pub version: String,
+    pub required: bool,
 }
 
 impl Default for Config {
@@ -28,20 +360,287 @@ impl Default for Config {
         Config {
             rcon_poll_interval: 60,
             rcon_idle_timeout: 600,
+            auto_stop_on_idle: true,
+            watchdog_start_delay: 0,
+            startup_timeout: 0,
+            rcon_startup_grace: 0,
+            rcon_connect_timeout: 600,
+            rcon_connect_retry_interval: 1,
+            rcon_poll_max_errors: 5,
+            rcon_poll_jitter_percent: 10,
+            idle_warning_seconds: 0,
+            idle_warning_msg_text: "Server will sleep in {seconds}s due to inactivity".to_string(),
+            ready_broadcast_message: None,
+            player_count_source: PlayerCountSource::Rcon,
+            readiness_mode: ReadinessMode::Rcon,
+            player_count_command: "list".to_string(),
+            player_count_pattern: crate::PLAYER_COUNT_PATTERN.to_string(),
+            player_count_group: 1,
+            unknown_count_warn_polls: 5,
+            unknown_count_assume_empty_after: 0,
+            idle_ignore_players: Vec::new(),
             motd_text: "Napping... Join to start server".to_string(),
             motd_color: "aqua".to_string(),
             motd_bold: true,
+            motd_text_line2: None,
+            motd_color_line2: None,
+            motd_bold_line2: None,
+            motd_sample_lines: None,
+            motd_component: None,
+            motd_spoof_protocol: true,
+            motd_version_name: "MCServerNap (1.20.5)".to_string(),
+            cache_server_motd: false,
+            cached_motd_suffix: None,
+            motd_cache_interval: 60,
+            max_message_length: 256,
             server_icon: None,
             connection_msg_text: "Server is now starting up. Please wait and try again shortly..."
                 .to_string(),
             connection_msg_color: "light_purple".to_string(),
             connection_msg_bold: true,
+            connection_msg_component: None,
+            disconnect_flush_delay_ms: 50,
+            starting_motd_text: None,
+            reconnect_msg_text: None,
+            backend_unreachable_msg_text:
+                "Couldn't reach the server. Please try again in a moment.".to_string(),
+            stopping_msg_text: "The server is shutting down. Please try again in a moment."
+                .to_string(),
+            wake_cooldown: 0,
+            cooldown_msg_text:
+                "Server recently stopped. Please wait ~{remaining}s before trying again."
+                    .to_string(),
+            start_backoff_base: 10,
+            start_backoff_max: 600,
+            start_failed_msg_text: "The server failed to start, retrying in {remaining}s"
+                .to_string(),
+            whitelist: None,
+            whitelist_msg_text: "You are not whitelisted on this server.".to_string(),
+            use_server_whitelist: false,
+            server_whitelist_path: "whitelist.json".to_string(),
+            server_whitelist_msg_text: "You are not white-listed on this server!".to_string(),
+            min_protocol_version: None,
+            outdated_client_msg_text: "Please update to 1.20.5 or newer to join this server."
+                .to_string(),
+            max_handshake_bytes: 32 * 1024,
+            accept_transfers: true,
+            accept_proxy_protocol: false,
+            transfer_redirect: None,
+            transfer_target: None,
+            transfer_unsupported_msg_text:
+                "The server is under maintenance. Please try again later.".to_string(),
+            server_host: "127.0.0.1".to_string(),
+            rcon_host: "127.0.0.1".to_string(),
+            wol_mac: None,
+            wol_broadcast_addr: "255.255.255.255:9".to_string(),
+            wol_wait_port: 22,
+            wol_boot_timeout: 300,
+            proxy_connect_attempts: 3,
+            proxy_connect_retry_delay_ms: 500,
+            handoff_listener: false,
+            detect_running_server: true,
+            hold_connections_during_start: false,
+            hold_connections_timeout: 25,
+            handshake_stats_log_interval: 3600,
+            status_rate_limit: 10,
+            status_rate_burst: 20,
+            max_connections_per_minute: 0,
+            allow_cidrs: Vec::new(),
+            deny_cidrs: Vec::new(),
+            quiet_hours: Vec::new(),
+            quiet_hours_msg_text: "The server is asleep until {until}, please come back later"
+                .to_string(),
+            maintenance_mode: false,
+            maintenance_motd_text: "Under maintenance, back soon".to_string(),
+            maintenance_msg_text: "The server is under maintenance. Please try again later."
+                .to_string(),
+            scheduled_stop: None,
+            scheduled_stop_warning_seconds: 300,
+            scheduled_stop_msg_text: "Server is shutting down for the night in {seconds}s"
+                .to_string(),
+            log_buffer_lines: 100,
+            server_log_file: None,
+            server_log_max_size_mb: 10,
+            server_log_files: 3,
+            metrics_port: 0,
+            control_port: 0,
+            startup_webhook_url: None,
+            webhook_url: None,
+            wake_webhook_text: "Server started (woken by {player})".to_string(),
+            stop_webhook_text: "Server stopped ({reason})".to_string(),
+            stop_command_shell: None,
+            stop_timeout: 60,
+            shutdown_grace_period: 5,
+            stop_steps: StopStep::DEFAULT_ORDER.to_vec(),
+            pre_start_hook: None,
+            post_stop_hook: None,
+            hook_timeout: 300,
+            server_working_dir: None,
+            server_window: true,
+            command_path_base: CommandPathBase::CurrentDir,
+            detect_server_properties: false,
+            server_properties_path: "server.properties".to_string(),
             config_directory_name: "config".to_string(),
+            config_conflict_check: true,
+            watch_config: false,
+            timeouts: TimeoutConfig::default(),
+            forge_data: None,
+            idle_timeout_schedule: Vec::new(),
+            servers: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The configuration directory (`config_directory_name`)
+    pub fn config_dir(&self) -> &str {
+        &self.config_directory_name
+    }
+
+    /// The settings for one of the `servers`: these settings with the server's own options applied.
+    /// The control channel only works with a single server, so it's disabled
+    pub fn for_server(&self, server: &ServerDefinition) -> Config {
+        let mut config = self.clone();
+        config.servers = Vec::new();
+        config.control_port = 0;
+        if let Some(working_dir) = &server.working_dir {
+            config.server_working_dir = Some(working_dir.clone());
+        }
+        if let Some(idle_timeout) = server.rcon_idle_timeout {
+            config.rcon_idle_timeout = idle_timeout;
+        }
+        if let Some(log_file) = &self.server_log_file {
+            config.server_log_file = Some(server_log::named_log_file(log_file, &server.name));
+        }
+        if let Some(motd_text) = &server.motd_text {
+            config.motd_text = motd_text.clone();
+        }
+        config
+    }
+
+    /// Address of the Minecraft server on `server_host`
+    pub fn server_addr(&self, port: u16) -> String {
+        host_port(&self.server_host, port)
+    }
+
+    /// Address of the server's RCON on `rcon_host`
+    pub fn rcon_addr(&self, port: u16) -> String {
+        host_port(&self.rcon_host, port)
+    }
+
+    /// Checks the settings that can't be fully checked while parsing, like the time windows of `quiet_hours`,
+    /// and parses `transfer_redirect` into `transfer_target`
+    pub fn validate(&mut self) -> Result<()> {
+        for window in &self.quiet_hours {
+            TimeWindow::parse(window).context("Invalid quiet_hours")?;
+        }
+        if let Some(time) = &self.scheduled_stop {
+            schedule::parse_time(time).context("Invalid scheduled_stop")?;
+        }
+        for entry in &self.idle_timeout_schedule {
+            TimeWindow::parse(&entry.window).context("Invalid idle_timeout_schedule")?;
+        }
+        if self.rcon_poll_jitter_percent > 100 {
+            bail!(
+                "rcon_poll_jitter_percent must be at most 100, not {}",
+                self.rcon_poll_jitter_percent
+            );
+        }
+        if let Some(mac) = &self.wol_mac {
+            wol::parse_mac(mac).context("Invalid wol_mac")?;
+        }
+        self.transfer_target = match &self.transfer_redirect {
+            Some(target) => Some(
+                TransferTarget::parse(target)
+                    .with_context(|| format!("Invalid transfer_redirect `{}`", target))?,
+            ),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Whether the server is launched with captured stdin/stdout, which it needs for the console player count,
+    /// the log readiness mode and `server_log_file`
+    pub fn captures_server_output(&self) -> bool {
+        self.player_count_source == PlayerCountSource::Console
+            || self.readiness_mode == ReadinessMode::Log
+            || self.server_log_file.is_some()
+    }
+
+    /// Enough time for every stop step and the `post_stop_hook` to run out. Zero if `stop_timeout` or
+    /// the `hook_timeout` of a post-stop hook is zero, meaning no limit
+    pub fn max_stop_duration(&self) -> Duration {
+        let hook_timeout = match &self.post_stop_hook {
+            Some(_) if self.hook_timeout == 0 => return Duration::ZERO,
+            Some(_) => self.hook_timeout,
+            None => 0,
+        };
+        if self.stop_timeout == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs(self.stop_timeout * (self.stop_steps.len() as u64 + 1) + hook_timeout)
+    }
+
+    /// Whether `username` may wake the server. Always true without a whitelist, names are matched case-insensitively
+    pub fn is_whitelisted(&self, username: Option<&str>) -> bool {
+        match (&self.whitelist, username) {
+            (None, _) => true,
+            (Some(whitelist), Some(username)) => whitelist
+                .iter()
+                .any(|entry| entry.eq_ignore_ascii_case(username)),
+            (Some(_), None) => false,
         }
     }
+
+    /// Whether `ip` may reach the listener. `deny_cidrs` takes precedence, and an empty `allow_cidrs` allows
+    /// everyone else
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        // Clients connecting via IPv4 to a dual-stack socket show up as IPv4-mapped IPv6 addresses
+        let ip = ip.to_canonical();
+        if self.deny_cidrs.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow_cidrs.is_empty() || self.allow_cidrs.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Resolves the server command according to `command_path_base`. Commands without a path separator
+    /// (e.g. `java`) are looked up in PATH by the operating system and absolute paths are kept as they are
+    pub fn resolve_command(&self, command: &str) -> String {
+        let path = Path::new(command);
+        let has_separator = command.contains('/') || command.contains(std::path::MAIN_SEPARATOR);
+        if !has_separator || path.is_absolute() {
+            return command.to_string();
+        }
+
+        let base = match self.command_path_base {
+            CommandPathBase::CurrentDir => None,
+            CommandPathBase::ConfigDir => Some(self.config_directory_name.as_str()),
+            CommandPathBase::ServerDir => self.server_working_dir.as_deref(),
+        };
+        let joined = match base {
+            Some(base) => Path::new(base).join(path),
+            None => path.to_path_buf(),
+        };
+        let resolved = std::path::absolute(&joined).unwrap_or(joined);
+        if !resolved.exists() {
+            log::warn!("Server command {} doesn't exist", resolved.display());
+        }
+        resolved.to_string_lossy().into_owned()
+    }
 }
 
-pub fn get_config() -> Config {
+/// Loads `cfg.toml` from `config_dir`, creating the directory and the file if needed. Without an explicit
+/// directory, the subdirectories of the working directory are searched for a `cfg.toml` and it's moved to its
+/// `config_directory_name`. Fails if the directory or file can't be created or written
+pub fn get_config(config_dir: Option<&Path>) -> Result<Config> {
+    match config_dir {
+        Some(config_dir) => load_config(Config::default(), Some(config_dir)),
+        None => load_config(discover_config()?, None),
+    }
+}
+
+/// The configuration found in a subdirectory, migrated to its `config_directory_name`. The defaults if none is found
+fn discover_config() -> Result<Config> {
     let mut config = Config::default();
 
     // Search subdirectories for cfg.toml
@@ -53,14 +652,13 @@ pub fn get_config() -> Config {
 
             if path.is_dir() {
                 let config_file = path.join("cfg.toml");
-                if config_file.exists() {
-                    if let Ok(contents) = fs::read_to_string(&config_file) {
-                        if let Ok(parsed_config) = toml::from_str::<Config>(&contents) {
-                            old_config = Some(parsed_config);
-                            old_config_dir = path.to_str().map(|s| s.to_string());
-                            break;
-                        }
-                    }
+                if config_file.exists()
+                    && let Ok(contents) = fs::read_to_string(&config_file)
+                    && let Ok(parsed_config) = toml::from_str::<Config>(&contents)
+                {
+                    old_config = Some(parsed_config);
+                    old_config_dir = path.to_str().map(|s| s.to_string());
+                    break;
                 }
             }
         }
@@ -84,76 +682,356 @@ pub fn get_config() -> Config {
                 new_dir_normalized.display()
             );
 
-            fs::rename(&old_dir_normalized, &new_dir_normalized)
-                .expect("Failed to migrate config directory");
+            match fs::rename(&old_dir_normalized, &new_dir_normalized) {
+                Ok(()) => {}
+                // Keep using the old directory where it is
+                Err(e) if is_read_only(&e) => {
+                    log::warn!(
+                        "Cannot migrate the configuration directory '{}' ({}), using it where it is",
+                        old_dir_normalized.display(),
+                        e
+                    );
+                    return Ok(Config {
+                        config_directory_name: old_dir.clone(),
+                        ..old_cfg.clone()
+                    });
+                }
+                Err(e) => {
+                    return Err(anyhow::Error::new(e).context(format!(
+                        "Failed to migrate the configuration directory '{}' to '{}'",
+                        old_dir_normalized.display(),
+                        new_dir_normalized.display()
+                    )));
+                }
+            }
         }
     }
 
     if let Some(old_cfg) = old_config {
         config = old_cfg;
     }
+    Ok(config)
+}
 
-    let config_dir = config.config_directory_name.as_str();
+/// Reads (or creates) the `cfg.toml` in `explicit_dir`, or else in `config`'s `config_directory_name`
+fn load_config(mut config: Config, explicit_dir: Option<&Path>) -> Result<Config> {
+    let config_dir = match explicit_dir {
+        Some(dir) => dir.to_string_lossy().into_owned(),
+        None => config.config_directory_name.clone(),
+    };
     let config_path = format!("{}/cfg.toml", config_dir);
+    // Set once writing failed because the directory is read-only, nothing is written after that
+    let mut read_only: Option<std::io::Error> = None;
     // Create config directory if it doesn't exist
-    if !Path::new(config_dir).exists() {
+    if !Path::new(&config_dir).exists() {
         log::info!("No configuration directory found. Creating configuration directory.");
-        fs::create_dir(config_dir).expect("Cannot create config directory");
+        match fs::create_dir_all(&config_dir) {
+            Ok(()) => {}
+            Err(e) if is_read_only(&e) => read_only = Some(e),
+            Err(e) => {
+                return Err(anyhow::Error::new(e).context(format!(
+                    "Cannot create the configuration directory '{}'",
+                    config_dir
+                )));
+            }
+        }
     }
 
     match fs::read_to_string(&config_path) {
-        Ok(contents) => config = toml::from_str::<Config>(&contents).unwrap_or_default(),
+        Ok(contents) => match toml::from_str::<Config>(&contents) {
+            Ok(parsed_config) => config = parsed_config,
+            Err(e) => {
+                // Keep the broken file around, it's overwritten with the defaults below
+                let backup_path = format!("{}.bak", config_path);
+                match fs::copy(&config_path, &backup_path) {
+                    Ok(_) => log::error!(
+                        "Invalid configuration file {}: {}\nUsing the default configuration. Your configuration was backed up to {}.",
+                        config_path,
+                        e,
+                        backup_path
+                    ),
+                    // Nothing is overwritten then, no backup needed
+                    Err(copy_error) if is_read_only(&copy_error) => {
+                        log::error!(
+                            "Invalid configuration file {}: {}\nUsing the default configuration.",
+                            config_path,
+                            e
+                        );
+                        read_only = Some(copy_error);
+                    }
+                    Err(copy_error) => {
+                        return Err(anyhow::Error::new(copy_error).context(format!(
+                            "Cannot back up {} to {}",
+                            config_path, backup_path
+                        )));
+                    }
+                }
+                config = Config::default();
+            }
+        },
         Err(_) => {
             log::info!(
                 "No configuration file found. Creating default configuration file at {}.",
                 config_path
             );
-            File::create(&config_path).expect("Cannot create config file");
         }
     };
 
+    // The explicit directory only applies to this run, the file keeps its own config_directory_name
+    let stored_dir_name = explicit_dir
+        .map(|_| std::mem::replace(&mut config.config_directory_name, config_dir.clone()));
+
+    if config.config_conflict_check {
+        warn_on_config_dir_conflict(&config.config_directory_name);
+    }
+
     let icon_path = format!("{}/server-icon.png", config.config_directory_name);
-    match resize_image_to_64x64(&icon_path) {
-        Ok(resized_image) => {
-            // Save resized image back to server-icon.png
-            resized_image
-                .save(&icon_path)
-                .expect("Failed to save resized server-icon.png");
+    config.server_icon = load_server_icon(&icon_path, &config.config_directory_name);
 
-            config.server_icon = Some(convert_servericon_to_base64(&icon_path));
+    let toml_str = match stored_dir_name {
+        Some(stored_dir_name) => toml::to_string_pretty(&Config {
+            config_directory_name: stored_dir_name,
+            ..config.clone()
+        }),
+        None => toml::to_string_pretty(&config),
+    }
+    .context("Cannot serialize the configuration")?;
+    // Rewritten only if something changed, e.g. options added by an update, so the file keeps its timestamp
+    let unchanged = fs::read_to_string(&config_path).is_ok_and(|contents| contents == toml_str);
+    if unchanged {
+        log::debug!("{} is up to date, not rewriting it", config_path);
+    } else if read_only.is_none() {
+        match fs::write(&config_path, &toml_str) {
+            Ok(()) => {}
+            Err(e) if is_read_only(&e) => read_only = Some(e),
+            Err(e) => {
+                return Err(
+                    anyhow::Error::new(e).context(format!("Cannot write to {}", config_path))
+                );
+            }
         }
-        Err(_) => {
-            log::info!(
-                "No server-icon.png found in {}/ directory.",
-                config.config_directory_name
+    }
+    if let Some(e) = read_only {
+        log::warn!(
+            "The configuration directory {} is read-only ({}). Running with the configuration in memory, \
+             nothing is saved to it",
+            config_dir,
+            e
+        );
+    }
+
+    // Truncate after saving so the config file keeps the admin's original text
+    truncate_messages(&mut config);
+    Ok(config)
+}
+
+/// Whether writing failed because the configuration directory (or file) can't be written to
+fn is_read_only(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem
+    )
+}
+
+/// Reads `cfg.toml` in `config_dir` again for `watch_config`. Unlike [`get_config`] nothing is written, and an
+/// invalid file is an error instead of being replaced with the defaults, so the running configuration stays
+pub fn reload_config(config_dir: &str) -> Result<Config> {
+    let config_path = format!("{}/cfg.toml", config_dir);
+    let contents =
+        fs::read_to_string(&config_path).with_context(|| format!("Cannot read {}", config_path))?;
+    let mut config = toml::from_str::<Config>(&contents)
+        .with_context(|| format!("Invalid configuration file {}", config_path))?;
+    config.validate()?;
+    config.config_directory_name = config_dir.to_string();
+
+    let icon_path = format!("{}/server-icon.png", config_dir);
+    config.server_icon = load_server_icon(&icon_path, config_dir);
+    truncate_messages(&mut config);
+    Ok(config)
+}
+
+fn truncate_messages(config: &mut Config) {
+    truncate_message(
+        &mut config.motd_text,
+        "motd_text",
+        config.max_message_length,
+    );
+    if let Some(line2) = config.motd_text_line2.as_mut() {
+        truncate_message(line2, "motd_text_line2", config.max_message_length);
+    }
+    truncate_message(
+        &mut config.connection_msg_text,
+        "connection_msg_text",
+        config.max_message_length,
+    );
+}
+
+/// Warns if the configuration directory looks like it belongs to the Minecraft server, e.g. the `config/` folder
+/// that Forge/Fabric mods use. Sharing it leads to conflicts between both applications' files
+fn warn_on_config_dir_conflict(config_dir: &str) {
+    let dir = Path::new(config_dir);
+    let mut reasons = Vec::new();
+
+    if dir.join("server.properties").exists() {
+        reasons.push("it contains server.properties".to_string());
+    }
+    if dir.join("mods").is_dir() {
+        reasons.push("it contains a mods/ folder".to_string());
+    }
+
+    let parent = dir
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf));
+    let next_to_server = parent.is_some_and(|parent| {
+        parent.join("server.properties").exists() || parent.join("mods").is_dir()
+    });
+    if next_to_server {
+        let foreign_files: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| {
+                name != "cfg.toml"
+                    // The backup of an invalid configuration, see load_config
+                    && name != "cfg.toml.bak"
+                    && name != "server-icon.png"
+                    && !crate::persisted_state::is_state_file_name(name)
+                    && !crate::stats::is_stats_file_name(name)
+            })
+            .collect();
+        if !foreign_files.is_empty() {
+            reasons.push(format!(
+                "it's next to a Minecraft server and contains other files ({})",
+                foreign_files.join(", ")
+            ));
+        }
+    }
+
+    if !reasons.is_empty() {
+        log::warn!(
+            "The configuration directory '{}' looks like it belongs to a Minecraft server: {}. \
+             Move it elsewhere with --config-dir (or MCSERVERNAP_CONFIG_DIR) to avoid conflicts, \
+             or set config_conflict_check = false if this is intended.",
+            config_dir,
+            reasons.join("; ")
+        );
+    }
+}
+
+/// Shortens `text` to at most `max_length` characters, never splitting a multibyte character
+fn truncate_message(text: &mut String, field: &str, max_length: usize) {
+    if let Some((byte_index, _)) = text.char_indices().nth(max_length) {
+        log::warn!(
+            "{} is longer than {} characters ({}), truncating it",
+            field,
+            max_length,
+            text.chars().count()
+        );
+        text.truncate(byte_index);
+    }
+}
+
+/// Loads, resizes and encodes the server icon. Any problem with the file means "no favicon" instead of a crash
+fn load_server_icon(icon_path: &str, config_dir: &str) -> Option<String> {
+    // fs::metadata follows symlinks, so a valid symlink is checked against its target
+    let metadata = match fs::metadata(icon_path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            if fs::symlink_metadata(icon_path).is_ok() {
+                log::warn!(
+                    "{} is a broken symlink. Not using a server icon.",
+                    icon_path
+                );
+            } else {
+                log::info!("No server-icon.png found in {}/ directory.", config_dir);
+            }
+            return None;
+        }
+        Err(e) => {
+            log::warn!(
+                "Cannot access {} ({}). Not using a server icon.",
+                icon_path,
+                e
             );
-            config.server_icon = None;
+            return None;
         }
     };
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(&config_path)
-        .expect("Cannot open config file for writing");
+    if !metadata.is_file() {
+        log::warn!("{} is not a file. Not using a server icon.", icon_path);
+        return None;
+    }
+    if metadata.len() > MAX_SERVER_ICON_FILE_SIZE {
+        log::warn!(
+            "{} is {} bytes, which exceeds the limit of {} bytes. Not using a server icon.",
+            icon_path,
+            metadata.len(),
+            MAX_SERVER_ICON_FILE_SIZE
+        );
+        return None;
+    }
 
-    let toml_str = toml::to_string_pretty(&config).unwrap();
-    file.write_all(toml_str.as_bytes())
-        .expect("Cannot write to config file");
-    return config;
+    let resized_image = match resize_image_to_64x64(icon_path) {
+        Ok(resized_image) => resized_image,
+        Err(e) => {
+            log::warn!(
+                "Cannot load {} ({}). Not using a server icon.",
+                icon_path,
+                e
+            );
+            return None;
+        }
+    };
+
+    match encode_png_base64(&resized_image) {
+        Ok(server_icon) => match crate::preserialized_packets::validate_favicon(&server_icon) {
+            Ok(()) => Some(server_icon),
+            Err(e) => {
+                log::warn!(
+                    "Cannot use {} ({:#}). Not using a server icon.",
+                    icon_path,
+                    e
+                );
+                None
+            }
+        },
+        Err(e) => {
+            log::warn!(
+                "Cannot encode {} ({}). Not using a server icon.",
+                icon_path,
+                e
+            );
+            None
+        }
+    }
 }
 
+/// Opens the icon in any format `image` supports (detected from its content) and scales it to the 64x64 pixels
+/// Minecraft expects. The file itself is left untouched
 fn resize_image_to_64x64(path: &str) -> Result<DynamicImage> {
-    let img = image::open(path)?;
+    let img = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?;
     let (width, height) = img.dimensions();
     if width == 64 && height == 64 {
         return Ok(img); // Return original image if size is already 64x64
     }
-    return Ok(img.resize_exact(64, 64, FilterType::CatmullRom));
+    if width != height {
+        log::warn!(
+            "{} is {}x{} pixels and will be stretched to 64x64. Use a square image to avoid that.",
+            path,
+            width,
+            height
+        );
+    }
+    Ok(img.resize_exact(64, 64, FilterType::CatmullRom))
 }
 
-fn convert_servericon_to_base64(path: &str) -> String {
-    let image_bytes = fs::read(path).expect("Failed to read server-icon.png");
-    let image_base64 = general_purpose::STANDARD.encode(&image_bytes);
-    return image_base64;
+/// The favicon must be a PNG, whatever format the icon file had
+fn encode_png_base64(image: &DynamicImage) -> Result<String> {
+    let mut png = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(general_purpose::STANDARD.encode(&png))
 }
@@ -0,0 +1,242 @@
+use crate::notify::StopReason;
+use crate::server_log::ServerLog;
+use crate::{IdleAction, IdleTimer, PlayerCountQuery, ServerState, UnknownCountTracker};
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use std::sync::{Arc, LazyLock};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin};
+use tokio::sync::{Mutex, broadcast, watch};
+use tokio::time::{Duration, interval, timeout};
+
+/// Gives access to the console (stdin/stdout) of a server process launched with captured stdio.
+/// Every line the server prints is echoed to our own stdout (stderr for its error output), so the server log
+/// stays visible, and written to `server_log_file` if one is given
+pub struct ServerConsole {
+    stdin: Mutex<ChildStdin>,
+    lines: broadcast::Sender<String>,
+}
+
+impl ServerConsole {
+    /// Takes the piped stdin/stdout/stderr of `child` and starts forwarding its output.
+    /// Returns None if the child wasn't launched with captured stdio
+    pub fn attach(child: &mut Child, log: Option<ServerLog>) -> Option<Arc<ServerConsole>> {
+        let stdin = child.stdin.take()?;
+        let stdout = child.stdout.take()?;
+        let (lines, _) = broadcast::channel(256);
+        let log = log.map(|log| Arc::new(std::sync::Mutex::new(log)));
+
+        if let Some(stderr) = child.stderr.take() {
+            let log = log.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = reader.next_line().await {
+                    eprintln!("{}", line);
+                    if let Some(log) = &log {
+                        log.lock().unwrap().write_line(&line);
+                    }
+                }
+            });
+        }
+
+        let sender = lines.clone();
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(stdout).lines();
+            loop {
+                match reader.next_line().await {
+                    Ok(Some(line)) => {
+                        println!("{}", line);
+                        if let Some(log) = &log {
+                            log.lock().unwrap().write_line(&line);
+                        }
+                        // No receivers just means nobody is waiting for a response right now
+                        let _ = sender.send(line);
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::warn!("Failed to read server output: {}", e);
+                        break;
+                    }
+                }
+            }
+            log::debug!("Server output closed");
+        });
+
+        Some(Arc::new(ServerConsole {
+            stdin: Mutex::new(stdin),
+            lines,
+        }))
+    }
+
+    /// Subscribes to the lines printed by the server from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.lines.subscribe()
+    }
+
+    /// Writes a console command to the server's stdin
+    pub async fn send_command(&self, command: &str) -> Result<()> {
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(format!("{}\n", command).as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Runs `query.command` on the console and waits up to `response_timeout` for a line matching the query.
+    /// Returns the player count without `idle_ignore_players` and, if the line contains it, the player limit
+    pub async fn query_player_count(
+        &self,
+        query: &PlayerCountQuery,
+        response_timeout: Duration,
+    ) -> Result<(u32, Option<u32>)> {
+        // Subscribe before sending so the response can't be missed
+        let mut lines = self.subscribe();
+        self.send_command(&query.command).await?;
+
+        let wait_for_count = async {
+            loop {
+                match lines.recv().await {
+                    Ok(line) => {
+                        if let Some(count) = query.parse_counted(&line) {
+                            return Ok((count, query.parse_max(&line)));
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return Err(anyhow!("server output closed"));
+                    }
+                }
+            }
+        };
+
+        match timeout(response_timeout, wait_for_count).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!(
+                "no `{}` response within {:?}",
+                query.command,
+                response_timeout
+            )),
+        }
+    }
+}
+
+/// The line the server prints once the world is loaded, e.g. `Done (12.345s)! For help, type "help"`
+static DONE_LINE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"Done \([^)]*\)! For help, type "help""#).unwrap());
+
+/// Waits up to `max_wait` for the server to print its Done line. Subscribe `lines` right after launching,
+/// before the server can print it
+pub async fn wait_for_done_line(
+    mut lines: broadcast::Receiver<String>,
+    max_wait: Duration,
+) -> Result<()> {
+    let wait_for_line = async {
+        loop {
+            match lines.recv().await {
+                Ok(line) if DONE_LINE_RE.is_match(&line) => return Ok(()),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(anyhow!("server output closed"));
+                }
+            }
+        }
+    };
+
+    match timeout(max_wait, wait_for_line).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("no Done line within {:?}", max_wait)),
+    }
+}
+
+/// Idle watchdog without RCON: writes the player count command to the server's stdin every `poll_interval`
+/// and parses the response from the server output. The server counts as running once the first response arrives.
+/// Returns once `idle_timer` considers the server idle, so it can be stopped (see [`crate::stop::stop_server`])
+pub async fn idle_watchdog_stdio(
+    console: Arc<ServerConsole>,
+    poll_interval: Duration,
+    mut idle_timer: IdleTimer,
+    player_count_query: &PlayerCountQuery,
+    server_state: Arc<Mutex<ServerState>>,
+    ready_signal_sender: watch::Sender<bool>,
+) -> Result<StopReason> {
+    log::info!(
+        "Starting console idle watchdog: running `{}` every {:?}",
+        player_count_query.command,
+        poll_interval
+    );
+
+    let mut ticker = interval(poll_interval);
+    let mut running = false;
+    let mut unknown_counts = UnknownCountTracker::default();
+
+    loop {
+        ticker.tick().await;
+
+        let count = match console
+            .query_player_count(player_count_query, Duration::from_secs(5))
+            .await
+        {
+            Ok((count, max)) => {
+                log::info!(player_count = count; "Console player count: {}", count);
+                idle_timer.record_max_players(max);
+                Some(count)
+            }
+            Err(e) if running => {
+                log::warn!("Console player count poll failed: {}", e);
+                None
+            }
+            Err(e) => {
+                log::debug!("Server console not ready yet: {}", e);
+                continue;
+            }
+        };
+        let count = unknown_counts.resolve(player_count_query, count);
+
+        if !running {
+            running = true;
+            let mut state =
+                match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+                    Ok(guard) => guard,
+                    Err(_) => {
+                        log::error!("Deadlock detected! Failed to acquire state lock");
+                        panic!("State lock timeout - possible deadlock");
+                    }
+                };
+            *state = ServerState::Running;
+            log::debug!("Server state set to Running in idle_watchdog_stdio()");
+            drop(state);
+            let _ = ready_signal_sender.send(true);
+            if let Some(message) = idle_timer.ready_broadcast_message()
+                && let Err(e) = console.send_command(&format!("say {}", message)).await
+            {
+                log::warn!("Failed to announce that the server is ready: {}", e);
+            }
+            idle_timer.start();
+        }
+
+        match idle_timer.update(count) {
+            IdleAction::KeepRunning => {}
+            IdleAction::Warn { message, warning } => {
+                log::info!("Warning players before stopping in {:?}", warning);
+                if let Err(e) = console.send_command(&format!("say {}", message)).await {
+                    log::warn!("Failed to send stop warning: {}", e);
+                }
+                // Check again right when the warning window is over
+                ticker.reset_after(warning);
+            }
+            IdleAction::Stop => {
+                log::info!(
+                    event = "idle_stop",
+                    player_count = 0,
+                    idle_timeout_secs = idle_timer.idle_timeout().as_secs();
+                    "No players for {:?}, stopping server...",
+                    idle_timer.idle_timeout()
+                );
+                return Ok(StopReason::Idle(idle_timer.idle_timeout()));
+            }
+            IdleAction::ScheduledStop => {
+                log::info!(event = "scheduled_stop"; "Scheduled stop time reached, stopping server...");
+                return Ok(StopReason::Scheduled);
+            }
+        }
+    }
+}
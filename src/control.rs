@@ -0,0 +1,129 @@
+use crate::log_buffer::LOG_BUFFER;
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, timeout};
+
+/// How long the control channel waits for a command or a reply
+pub const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Address of the control channel. It only ever listens on loopback, so only local users can reach it
+pub fn control_addr(port: u16) -> String {
+    format!("127.0.0.1:{}", port)
+}
+
+/// Commands that need the main loop of the `listen` instance to act
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Start the server as if a player joined
+    Wake,
+    /// Stop the server through the same path as an idle stop
+    Stop,
+    /// Turn `maintenance_mode` on or off until the configuration is reloaded or this application restarts
+    Maintenance(bool),
+}
+
+/// A [`ControlCommand`] handed to the main loop, which answers it through `reply`
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Answers commands sent to a running `listen` instance by the other subcommands.
+/// The protocol is a single command line per connection, answered with plain text until the connection closes:
+/// * `logs`: the most recent log lines of this application
+/// * `wake`: starts the server, passed on to the main loop through `requests`
+/// * `stop`: stops the server, passed on to the main loop through `requests`. Replies once the server stopped
+/// * `maintenance on` / `maintenance off`: turns maintenance mode on or off, passed on to the main loop
+pub async fn serve_control(listener: TcpListener, requests: mpsc::Sender<ControlRequest>) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_control_connection(socket, requests).await {
+                        log::warn!("Control connection from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to accept control connection: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn handle_control_connection(
+    socket: TcpStream,
+    requests: mpsc::Sender<ControlRequest>,
+) -> Result<()> {
+    let mut reader = BufReader::new(socket);
+    let mut command = String::new();
+    timeout(CONTROL_TIMEOUT, reader.read_line(&mut command))
+        .await
+        .context("Timed out waiting for control command")??;
+    let command = command.trim();
+    log::debug!("Control command received: {}", command);
+
+    let reply = match command {
+        "logs" => LOG_BUFFER.lines().join("\n"),
+        "wake" => forward_to_main_loop(ControlCommand::Wake, &requests).await,
+        "stop" => forward_to_main_loop(ControlCommand::Stop, &requests).await,
+        "maintenance on" => {
+            forward_to_main_loop(ControlCommand::Maintenance(true), &requests).await
+        }
+        "maintenance off" => {
+            forward_to_main_loop(ControlCommand::Maintenance(false), &requests).await
+        }
+        _ => format!("error: unknown command `{}`", command),
+    };
+
+    let mut socket = reader.into_inner();
+    timeout(CONTROL_TIMEOUT, socket.write_all(reply.as_bytes()))
+        .await
+        .context("Timed out sending control reply")??;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+async fn forward_to_main_loop(
+    command: ControlCommand,
+    requests: &mpsc::Sender<ControlRequest>,
+) -> String {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    let request = ControlRequest {
+        command,
+        reply: reply_tx,
+    };
+    if requests.send(request).await.is_err() {
+        return "error: not accepting commands anymore".to_string();
+    }
+    reply_rx
+        .await
+        .unwrap_or_else(|_| "error: command was dropped".to_string())
+}
+
+/// Sends `command` to the `listen` instance with the control channel on `port` and returns its reply.
+/// Waits up to `reply_timeout` for the reply
+pub async fn send_control_command(
+    port: u16,
+    command: &str,
+    reply_timeout: Duration,
+) -> Result<String> {
+    let addr = control_addr(port);
+    let mut socket = timeout(CONTROL_TIMEOUT, TcpStream::connect(&addr))
+        .await
+        .context("Timed out connecting to the control channel")?
+        .with_context(|| format!("No running instance listening on control port {}", port))?;
+    socket
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+
+    let mut reply = String::new();
+    timeout(reply_timeout, socket.read_to_string(&mut reply))
+        .await
+        .context("Timed out waiting for the control reply")??;
+    Ok(reply)
+}
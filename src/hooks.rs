@@ -0,0 +1,46 @@
+use anyhow::{Result, bail};
+use tokio::time::{Duration, timeout};
+
+/// Runs a hook command (`pre_start_hook`, `post_stop_hook`) through the shell and logs its output.
+/// The hook is killed if it runs longer than `max_duration`; a zero `max_duration` waits indefinitely
+pub async fn run_hook(name: &str, command: &str, max_duration: Duration) -> Result<()> {
+    log::info!("Running {}: {}", name, command);
+
+    #[cfg(target_os = "windows")]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    };
+    #[cfg(not(target_os = "windows"))]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    };
+    // Dropping the output future on timeout kills the hook
+    let output = cmd.kill_on_drop(true).output();
+
+    let output = if max_duration.is_zero() {
+        output.await?
+    } else {
+        match timeout(max_duration, output).await {
+            Ok(output) => output?,
+            Err(_) => bail!("{} `{}` timed out after {:?}", name, command, max_duration),
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stdout.trim().is_empty() {
+        log::info!("{} output: {}", name, stdout.trim());
+    }
+    if !stderr.trim().is_empty() {
+        log::warn!("{} error output: {}", name, stderr.trim());
+    }
+
+    if !output.status.success() {
+        bail!("{} `{}` failed with {}", name, command, output.status);
+    }
+    Ok(())
+}
@@ -1,16 +1,48 @@
+pub mod client_stream;
 pub mod config;
+pub mod console;
+pub mod control;
+pub mod hooks;
+pub mod log_buffer;
+pub mod metrics;
+pub mod nap_server;
+pub mod notify;
+pub mod persisted_state;
+pub mod preserialized_packets;
+pub mod proxy_protocol;
+pub mod schedule;
+pub mod server_log;
+pub mod server_properties;
+pub mod server_whitelist;
+pub mod stats;
+pub mod stop;
+pub mod verify;
+pub mod wol;
+
+pub use crate::nap_server::{NapServer, NapServerBuilder, ShutdownHandle};
 
 use crate::config::Config;
-use anyhow::Result;
+use crate::metrics::ServerMetrics;
+use crate::notify::StopReason;
+use crate::preserialized_packets::{
+    PreserializedPackets, serialize_disconnect_message, serialize_login_success,
+    serialize_starting_motd, serialize_status_request, serialize_transfer,
+};
+use anyhow::{Context, Result};
 use rcon::Connection;
 use regex::Regex;
-use serde_json::{Value, json};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
 use std::io::ErrorKind;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, watch};
 use tokio::time::{Duration, Instant, interval, timeout};
 
 /// Basic enum to provide state machine system for server status
@@ -19,28 +51,55 @@ pub enum ServerState {
     Stopped,
     Starting,
     Running,
+    /// The server was told to stop and hasn't exited yet. New players aren't proxied to it anymore
+    Stopping,
 }
 
 /// Read a VarInt (Minecraft format) from the buffer, returning (value, bytes_read). Returns None if malformed
-fn read_varint(buf: &[u8]) -> Option<(i32, usize)> {
-    let mut num_read = 0;
-    let mut result = 0i32;
-    for &byte in buf.iter() {
-        let val = (byte & 0x7F) as i32;
-        result |= val << (7 * num_read);
-        num_read += 1;
+/// or if the value doesn't fit in an i32
+pub fn read_varint(buf: &[u8]) -> Option<(i32, usize)> {
+    let (value, num_read) = read_var(buf, 5)?;
+    // The 5th byte may only carry the 4 remaining bits
+    if num_read == 5 && buf[4] & 0x70 != 0 {
+        return None;
+    }
+    Some((value as u32 as i32, num_read))
+}
+
+/// Read a VarLong (Minecraft format) from the buffer, returning (value, bytes_read). Returns None if malformed
+/// or if the value doesn't fit in an i64
+pub fn read_varlong(buf: &[u8]) -> Option<(i64, usize)> {
+    let (value, num_read) = read_var(buf, 10)?;
+    // The 10th byte may only carry the 1 remaining bit
+    if num_read == 10 && buf[9] & 0x7E != 0 {
+        return None;
+    }
+    Some((value as i64, num_read))
+}
+
+/// Reads the 7 bit groups of a VarInt or VarLong of at most `max_bytes`
+fn read_var(buf: &[u8], max_bytes: usize) -> Option<(u64, usize)> {
+    let mut result = 0u64;
+    for (i, &byte) in buf.iter().take(max_bytes).enumerate() {
+        result |= ((byte & 0x7F) as u64) << (7 * i);
         if byte & 0x80 == 0 {
-            return Some((result, num_read));
-        }
-        if num_read >= 5 {
-            return None;
+            return Some((result, i + 1));
         }
     }
     None
 }
 
-// Write a VarInt (Minecraft format)
-pub fn write_varint(mut val: i32, buf: &mut Vec<u8>) {
+// Write a VarInt (Minecraft format). Negative values take the full 5 bytes
+pub fn write_varint(val: i32, buf: &mut Vec<u8>) {
+    write_var(val as u32 as u64, buf);
+}
+
+// Write a VarLong (Minecraft format). Negative values take the full 10 bytes
+pub fn write_varlong(val: i64, buf: &mut Vec<u8>) {
+    write_var(val as u64, buf);
+}
+
+fn write_var(mut val: u64, buf: &mut Vec<u8>) {
     loop {
         if (val & !0x7F) == 0 {
             buf.push(val as u8);
@@ -52,141 +111,1388 @@ pub fn write_varint(mut val: i32, buf: &mut Vec<u8>) {
     }
 }
 
-// Verifies a full Minecraft handshake on a single TcpStream.
-pub async fn verify_handshake_packet(
-    socket: &mut TcpStream,
+/// Reasons a connection is not treated as a valid handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeFailure {
+    Timeout,
+    ImmediateClose,
+    ConnectionReset,
+    MalformedLength,
+    MalformedVarInt,
+    WrongPacketId,
+    NegativeAddressLength,
+    Truncated,
+    UnknownNextState,
+    /// A valid status ping that was dropped by `status_rate_limit`
+    StatusRateLimited,
+}
+
+impl HandshakeFailure {
+    pub const ALL: [HandshakeFailure; 10] = [
+        HandshakeFailure::Timeout,
+        HandshakeFailure::ImmediateClose,
+        HandshakeFailure::ConnectionReset,
+        HandshakeFailure::MalformedLength,
+        HandshakeFailure::MalformedVarInt,
+        HandshakeFailure::WrongPacketId,
+        HandshakeFailure::NegativeAddressLength,
+        HandshakeFailure::Truncated,
+        HandshakeFailure::UnknownNextState,
+        HandshakeFailure::StatusRateLimited,
+    ];
+
+    /// Short label used in logs and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeFailure::Timeout => "timeout",
+            HandshakeFailure::ImmediateClose => "immediate_close",
+            HandshakeFailure::ConnectionReset => "connection_reset",
+            HandshakeFailure::MalformedLength => "malformed_length",
+            HandshakeFailure::MalformedVarInt => "malformed_varint",
+            HandshakeFailure::WrongPacketId => "wrong_packet_id",
+            HandshakeFailure::NegativeAddressLength => "negative_address_length",
+            HandshakeFailure::Truncated => "truncated",
+            HandshakeFailure::UnknownNextState => "unknown_next_state",
+            HandshakeFailure::StatusRateLimited => "status_rate_limited",
+        }
+    }
+}
+
+// One counter per HandshakeFailure variant, in the order of HandshakeFailure::ALL
+static HANDSHAKE_FAILURE_COUNTS: [AtomicU64; HandshakeFailure::ALL.len()] =
+    [const { AtomicU64::new(0) }; HandshakeFailure::ALL.len()];
+
+fn record_handshake_failure(failure: HandshakeFailure) {
+    HANDSHAKE_FAILURE_COUNTS[failure as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns how often each handshake failure category has occurred since startup
+pub fn handshake_failure_counts() -> Vec<(HandshakeFailure, u64)> {
+    HandshakeFailure::ALL
+        .iter()
+        .map(|&f| {
+            (
+                f,
+                HANDSHAKE_FAILURE_COUNTS[f as usize].load(Ordering::Relaxed),
+            )
+        })
+        .collect()
+}
+
+static STATUS_PINGS_ANSWERED: AtomicU64 = AtomicU64::new(0);
+static LOGIN_HANDSHAKES: AtomicU64 = AtomicU64::new(0);
+
+/// Returns how many status pings were answered and how many login handshakes were received since startup
+pub fn handshake_counts() -> (u64, u64) {
+    (
+        STATUS_PINGS_ANSWERED.load(Ordering::Relaxed),
+        LOGIN_HANDSHAKES.load(Ordering::Relaxed),
+    )
+}
+
+/// Logs the handshake failure counts every `log_interval`. Nothing is logged if no new failures occurred
+pub async fn log_handshake_failures_periodically(log_interval: Duration) {
+    let mut ticker = interval(log_interval);
+    ticker.tick().await; // First tick completes immediately
+    let mut last_total = 0;
+
+    loop {
+        ticker.tick().await;
+        let counts = handshake_failure_counts();
+        let total: u64 = counts.iter().map(|(_, c)| c).sum();
+        if total == last_total {
+            continue;
+        }
+        last_total = total;
+
+        let summary = counts
+            .iter()
+            .filter(|(_, c)| *c > 0)
+            .map(|(f, c)| format!("{}={}", f.as_str(), c))
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::info!("Handshake failures since startup: {}", summary);
+    }
+}
+
+/// Global token bucket for status responses: refills `rate` tokens per second up to `burst`, one token per response
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn try_take(&mut self, rate: u32, burst: u32) -> bool {
+        let now = Instant::now();
+        let burst = f64::from(burst.max(1));
+        self.tokens = match self.last_refill {
+            Some(last) => (self.tokens + last.elapsed().as_secs_f64() * f64::from(rate)).min(burst),
+            None => burst,
+        };
+        self.last_refill = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+static STATUS_RESPONSE_BUCKET: std::sync::Mutex<TokenBucket> = std::sync::Mutex::new(TokenBucket {
+    tokens: 0.0,
+    last_refill: None,
+});
+
+/// Whether another status response may be sent according to `status_rate_limit`. Always true if it's 0
+fn allow_status_response(config: &Config) -> bool {
+    if config.status_rate_limit == 0 {
+        return true;
+    }
+    let mut bucket = STATUS_RESPONSE_BUCKET
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    bucket.try_take(config.status_rate_limit, config.status_rate_burst)
+}
+
+/// Recent connections per source IP, limited to `max_connections_per_minute` so a scanner or a single client
+/// can't keep waking the server or flood the log
+#[derive(Default)]
+pub struct ConnectionRateLimiter {
+    connections: HashMap<IpAddr, VecDeque<Instant>>,
+    last_prune: Option<Instant>,
+}
+
+impl ConnectionRateLimiter {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    /// Records a connection from `ip`. False if `ip` already made `max_per_minute` connections within the last
+    /// minute; refused connections aren't counted. Always true if `max_per_minute` is 0
+    pub fn allow(&mut self, ip: IpAddr, max_per_minute: u32) -> bool {
+        if max_per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        self.prune(now);
+
+        let recent = self.connections.entry(ip.to_canonical()).or_default();
+        while recent
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= Self::WINDOW)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= max_per_minute as usize {
+            return false;
+        }
+        recent.push_back(now);
+        true
+    }
+
+    /// Forgets the connections of `ip`, e.g. of the player who just woke the server and reconnects shortly
+    pub fn forget(&mut self, ip: IpAddr) {
+        self.connections.remove(&ip.to_canonical());
+    }
+
+    /// Drops IPs without connections in the last minute, at most once a minute
+    fn prune(&mut self, now: Instant) {
+        if self
+            .last_prune
+            .is_some_and(|last| now.duration_since(last) < Self::WINDOW)
+        {
+            return;
+        }
+        self.last_prune = Some(now);
+        self.connections.retain(|_, recent| {
+            recent
+                .back()
+                .is_some_and(|at| now.duration_since(*at) < Self::WINDOW)
+        });
+    }
+}
+
+/// Consecutive failed starts (the server exited before it was ready) and the wait they impose on the next start:
+/// `start_backoff_base`, doubled with every further failure up to `start_backoff_max`. Keeps a modpack that
+/// crashes on boot from being relaunched in a tight loop
+#[derive(Debug, Default)]
+pub struct StartBackoff {
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl StartBackoff {
+    /// Returns the number of consecutive failures
+    pub fn record_failure(&mut self) -> u32 {
+        self.failures += 1;
+        self.last_failure = Some(Instant::now());
+        self.failures
+    }
+
+    /// The server started fine, the next failure waits `base` again
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.last_failure = None;
+    }
+
+    /// The wait after the current number of failures. Zero without failures or if `base` is zero
+    pub fn delay(&self, base: Duration, max: Duration) -> Duration {
+        if self.failures == 0 {
+            return Duration::ZERO;
+        }
+        let doublings = (self.failures - 1).min(31);
+        base.checked_mul(1 << doublings).unwrap_or(max).min(max)
+    }
+
+    /// Time left until the server may be started again
+    pub fn remaining(&self, base: Duration, max: Duration) -> Duration {
+        self.last_failure
+            .map(|failed_at| self.delay(base, max).saturating_sub(failed_at.elapsed()))
+            .unwrap_or_default()
+    }
+}
+
+/// Outcome of reading the first packet of a connection
+enum HandshakeResult {
+    Status {
+        protocol_version: i32,
+        host: String,
+    },
+    Login {
+        protocol_version: i32,
+        transfer: bool,
+        host: String,
+    },
+    Failed(HandshakeFailure),
+}
+
+/// Oldest protocol version with transfers (Minecraft 1.20.5)
+pub const TRANSFER_MIN_PROTOCOL_VERSION: i32 = 766;
+
+/// A login handshake read from a client
+pub struct LoginHandshake {
+    /// Bytes read from the socket so far, starting with the handshake packet
+    pub bytes: Vec<u8>,
+    pub protocol_version: i32,
+    /// The client was sent here by a Transfer packet of another server (intent 3) instead of joining directly
+    pub transfer: bool,
+    /// The address the client connected to (virtual host), see [`handshake_host`]
+    pub host: String,
+}
+
+/// Verifies a full Minecraft handshake on a single TcpStream. Returns true for logins and transfers (intent 3,
+/// Minecraft 1.20.5+), which both wake the server. Status pings are answered here and return false
+pub async fn verify_handshake_packet<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
     peer: SocketAddr,
     config: &Config,
+    packets: &PreserializedPackets,
 ) -> Result<bool> {
-    // 1) Read initial data, ignoring resets or immediate closes
-    let mut buf = [0u8; 512];
+    Ok(
+        verify_handshake_packet_buffered(socket, peer, config, packets, None)
+            .await?
+            .is_some(),
+    )
+}
 
-    let n = match timeout(Duration::from_secs(5), socket.read(&mut buf)).await {
-        Ok(Ok(0)) => {
-            log::debug!("Connection closed immediately by {}", peer);
-            return Ok(false);
+/// Like [`verify_handshake_packet`], but returns the login handshake including the bytes read from the socket,
+/// so they can be replayed to the Minecraft server when the connection is proxied later on.
+/// Transfers count as logins if `accept_transfers` is enabled.
+/// `starting_since` is the launch time while the server is starting, used for the `starting_motd_text`
+pub async fn verify_handshake_packet_buffered<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    peer: SocketAddr,
+    config: &Config,
+    packets: &PreserializedPackets,
+    starting_since: Option<Instant>,
+) -> Result<Option<LoginHandshake>> {
+    let (result, handshake_bytes) = read_handshake(
+        socket,
+        peer,
+        config.timeouts.handshake(),
+        config.max_handshake_bytes,
+    )
+    .await?;
+    match result {
+        HandshakeResult::Status { .. } if !allow_status_response(config) => {
+            // Dropped without a response, so a flood of pings doesn't cost the bandwidth of the MOTD and icon
+            record_handshake_failure(HandshakeFailure::StatusRateLimited);
+            log::debug!("Status ping from {} dropped (status_rate_limit)", peer);
+            Ok(None)
         }
-        Ok(Ok(n)) => n,
-        Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => {
-            log::debug!("Connection reset by peer {} (ignoring)", peer);
-            return Ok(false);
+        HandshakeResult::Status {
+            protocol_version,
+            host,
+        } => {
+            log::debug!("Status ping from {} for {}", peer, host);
+            // The status request may have arrived together with the handshake
+            let status_request_buffered = match read_varint(&handshake_bytes) {
+                Some((pkt_len, len_size)) => handshake_bytes.len() > len_size + pkt_len as usize,
+                None => false,
+            };
+            handle_status_ping(
+                socket,
+                config,
+                packets,
+                protocol_version,
+                starting_since,
+                status_request_buffered,
+            )
+            .await?;
+            STATUS_PINGS_ANSWERED.fetch_add(1, Ordering::Relaxed);
+            Ok(None)
         }
-        Ok(Err(e)) => {
-            // Unexpected I/O error, propagate
-            return Err(e.into());
+        HandshakeResult::Login { transfer: true, .. } if !config.accept_transfers => {
+            record_handshake_failure(HandshakeFailure::UnknownNextState);
+            log::debug!(
+                "Transfer from {} rejected (accept_transfers is disabled)",
+                peer
+            );
+            Ok(None)
         }
-        Err(_) => {
-            log::debug!("Timeout waiting for data from {}", peer);
-            return Ok(false);
+        HandshakeResult::Login {
+            protocol_version, ..
+        } if config
+            .min_protocol_version
+            .is_some_and(|min| protocol_version < min) =>
+        {
+            log::info!(
+                "Login from {} rejected, protocol version {} is below min_protocol_version",
+                peer,
+                protocol_version
+            );
+            send_disconnect_message(&mut *socket, config, &config.outdated_client_msg_text).await?;
+            Ok(None)
         }
-    };
+        HandshakeResult::Login {
+            protocol_version,
+            transfer,
+            host,
+        } => {
+            LOGIN_HANDSHAKES.fetch_add(1, Ordering::Relaxed);
+            if transfer {
+                log::info!("Transfer handshake detected from {} for {}", peer, host);
+            } else {
+                log::info!("Login handshake detected from {} for {}", peer, host);
+            }
+            Ok(Some(LoginHandshake {
+                bytes: handshake_bytes,
+                protocol_version,
+                transfer,
+                host,
+            }))
+        }
+        HandshakeResult::Failed(failure) => {
+            record_handshake_failure(failure);
+            log::debug!("Handshake from {} rejected ({})", peer, failure.as_str());
+            Ok(None)
+        }
+    }
+}
 
-    log::debug!("Received {} bytes: {:02X?}", n, &buf[..n]);
+/// Whether `buf` starts with a complete packet. Malformed lengths or ones over `max_length` (`max_handshake_bytes`)
+/// count as complete, so the parser can reject them right away instead of buffering whatever the client announced
+fn is_packet_complete(buf: &[u8], max_length: usize) -> bool {
+    match read_varint(buf) {
+        Some((pkt_len, len_size)) => {
+            pkt_len < 0 || pkt_len as usize > max_length || buf.len() >= len_size + pkt_len as usize
+        }
+        // Incomplete VarInt, unless it's already too long to be one
+        None => buf.len() >= 5,
+    }
+}
 
-    // 2) Parse handshake packet (packet ID = 0, next_state = 2)
+/// Reads the handshake packet. Vanilla handshakes are far shorter than `max_length`, but mod loaders append
+/// markers and proxies forwarding player data append whole profiles to the address
+async fn read_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    peer: SocketAddr,
+    read_timeout: Duration,
+    max_length: usize,
+) -> Result<(HandshakeResult, Vec<u8>)> {
+    // 1) Read until the handshake packet is complete, ignoring resets or immediate closes.
+    // Slow or fragmented connections may deliver it in several pieces
+    let deadline = Instant::now() + read_timeout;
+    let mut received = Vec::new();
+
+    while !is_packet_complete(&received, max_length) {
+        let mut buf = [0u8; 512];
+        match tokio::time::timeout_at(deadline, socket.read(&mut buf)).await {
+            Ok(Ok(0)) if received.is_empty() => {
+                log::debug!("Connection closed immediately by {}", peer);
+                return Ok((
+                    HandshakeResult::Failed(HandshakeFailure::ImmediateClose),
+                    Vec::new(),
+                ));
+            }
+            // Closed mid-packet, the parser reports what's missing
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => received.extend_from_slice(&buf[..n]),
+            Ok(Err(e)) if e.kind() == ErrorKind::ConnectionReset => {
+                log::debug!("Connection reset by peer {} (ignoring)", peer);
+                return Ok((
+                    HandshakeResult::Failed(HandshakeFailure::ConnectionReset),
+                    Vec::new(),
+                ));
+            }
+            Ok(Err(e)) => {
+                // Unexpected I/O error, propagate
+                return Err(e.into());
+            }
+            Err(_) => {
+                log::debug!("Timeout waiting for data from {}", peer);
+                return Ok((
+                    HandshakeResult::Failed(HandshakeFailure::Timeout),
+                    Vec::new(),
+                ));
+            }
+        }
+    }
+
+    log::debug!("Received {} bytes: {:02X?}", received.len(), received);
+
+    Ok((parse_handshake(&received, peer, max_length), received))
+}
+
+/// The host name from the address field of a handshake, i.e. the DNS name or IP the client connected to.
+/// Forge clients append a `\0FML\0`, `\0FML2\0` or `\0FML3\0` marker (and proxies sometimes more), which isn't
+/// part of it. A trailing dot of a fully qualified name is dropped and the name is lowercased
+pub fn handshake_host(address: &str) -> String {
+    let host = address.split('\0').next().unwrap_or_default();
+    host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase()
+}
+
+fn parse_handshake(buf: &[u8], peer: SocketAddr, max_length: usize) -> HandshakeResult {
+    let n = buf.len();
+
+    // 2) Parse handshake packet (packet ID = 0, next_state = 2, or 3 for transfers)
     // More information on the handshake packet structure: https://minecraft.wiki/w/Java_Edition_protocol/Packets#Handshaking
     // Skip packet length VarInt
-    let (_pkt_len, off1) = match read_varint(&buf[..n]) {
+    let (pkt_len, off1) = match read_varint(buf) {
         Some(v) => v,
-        None => return Ok(false),
+        None => return HandshakeResult::Failed(HandshakeFailure::MalformedLength),
     };
+    if pkt_len <= 0 {
+        return HandshakeResult::Failed(HandshakeFailure::MalformedLength);
+    }
+    if pkt_len as usize > max_length {
+        log::debug!(
+            "Handshake from {} announces {} bytes, more than max_handshake_bytes ({})",
+            peer,
+            pkt_len,
+            max_length
+        );
+        return HandshakeResult::Failed(HandshakeFailure::MalformedLength);
+    }
+    // Only look at the handshake itself, the Login Start may follow in the same read
+    let n = n.min(off1 + pkt_len as usize);
     // Packet ID VarInt
     let (pkt_id, off2) = match read_varint(&buf[off1..n]) {
         Some(v) => v,
-        None => return Ok(false),
+        None => return HandshakeResult::Failed(HandshakeFailure::MalformedVarInt),
     };
     if pkt_id != 0 {
         // not a handshake packet
-        return Ok(false);
+        return HandshakeResult::Failed(HandshakeFailure::WrongPacketId);
     }
 
     // Skip protocol version VarInt
     let mut offset = off1 + off2;
-    let (_protocol_version, len) = match read_varint(&buf[offset..n]) {
+    let (protocol_version, len) = match read_varint(&buf[offset..n]) {
         Some(v) => v,
-        None => return Ok(false),
+        None => return HandshakeResult::Failed(HandshakeFailure::MalformedVarInt),
     };
     offset += len;
 
-    // Read address length and skip the address string
+    // Read address length and the address string
     let (addr_len, len) = match read_varint(&buf[offset..n]) {
         Some(v) => v,
-        None => return Ok(false),
+        None => return HandshakeResult::Failed(HandshakeFailure::MalformedVarInt),
     };
     if addr_len < 0 {
-        return Ok(false);
+        return HandshakeResult::Failed(HandshakeFailure::NegativeAddressLength);
+    }
+    offset += len;
+    let addr_end = offset + addr_len as usize;
+    // The address and port must have been received completely before they're read
+    if addr_end + 2 > n {
+        return HandshakeResult::Failed(HandshakeFailure::Truncated);
+    }
+    let address = String::from_utf8_lossy(&buf[offset..addr_end]);
+    let host = handshake_host(&address);
+    if let Some((_, extra)) = address.split_once('\0') {
+        log::debug!(
+            "Handshake from {} for {} carries extra address data ({} bytes, {})",
+            peer,
+            host,
+            extra.len(),
+            if extra.starts_with("FML") {
+                "Forge"
+            } else {
+                "unknown"
+            }
+        );
     }
-    offset += len + addr_len as usize;
 
     // Skip the port (2 bytes)
-    offset += 2;
+    offset = addr_end + 2;
 
     // Read next_state (intent) VarInt
     if offset >= n {
-        return Ok(false);
-    }
-    if let Some((next_state, _)) = read_varint(&buf[offset..n]) {
-        if next_state == 1 {
-            // Status ping
-            handle_status_ping(socket, &config).await?;
-            return Ok(false);
-        } else if next_state == 2 {
-            // Login handshake
-            log::info!("Login handshake detected from {}", peer);
-            return Ok(true);
-        } else {
+        return HandshakeResult::Failed(HandshakeFailure::Truncated);
+    }
+    match read_varint(&buf[offset..n]) {
+        Some((1, _)) => HandshakeResult::Status {
+            protocol_version,
+            host,
+        },
+        Some((2, _)) => HandshakeResult::Login {
+            protocol_version,
+            transfer: false,
+            host,
+        },
+        Some((3, _)) => HandshakeResult::Login {
+            protocol_version,
+            transfer: true,
+            host,
+        },
+        Some(_) => {
             log::debug!("Unknown type of ping from {}, ignoring", peer);
+            HandshakeResult::Failed(HandshakeFailure::UnknownNextState)
         }
+        None => HandshakeResult::Failed(HandshakeFailure::MalformedVarInt),
     }
+}
 
-    Ok(false)
+/// Longest username the vanilla client allows
+const MAX_USERNAME_LENGTH: usize = 16;
+
+enum LoginStartParse {
+    Username(String),
+    Incomplete,
+    Malformed,
+}
+
+/// Reads the Login Start packet that follows a login handshake and returns the player's username.
+/// `buffered` holds the bytes already read from the socket, starting with the handshake packet. Bytes read here
+/// are appended to it, so the connection can still be forwarded to the server unchanged.
+/// Returns None if the packet is malformed, truncated or doesn't arrive within `read_timeout`
+pub async fn read_login_username<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    buffered: &mut Vec<u8>,
+    read_timeout: Duration,
+) -> Result<Option<String>> {
+    let login_start_offset = match read_varint(buffered) {
+        Some((handshake_len, len_size)) if handshake_len >= 0 => len_size + handshake_len as usize,
+        _ => return Ok(None),
+    };
+    let deadline = Instant::now() + read_timeout;
+
+    loop {
+        if buffered.len() > login_start_offset {
+            match parse_login_start(&buffered[login_start_offset..]) {
+                LoginStartParse::Username(name) => return Ok(Some(name)),
+                LoginStartParse::Malformed => return Ok(None),
+                LoginStartParse::Incomplete => {}
+            }
+        }
+
+        let mut buf = [0u8; 128];
+        match tokio::time::timeout_at(deadline, socket.read(&mut buf)).await {
+            Ok(Ok(0)) | Err(_) => return Ok(None),
+            Ok(Ok(n)) => buffered.extend_from_slice(&buf[..n]),
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+// Login Start: packet length, packet ID 0x00, username string (followed by the UUID, which isn't needed)
+fn parse_login_start(buf: &[u8]) -> LoginStartParse {
+    let (pkt_len, off1) = match read_varint(buf) {
+        Some(v) => v,
+        None if buf.len() >= 5 => return LoginStartParse::Malformed,
+        None => return LoginStartParse::Incomplete,
+    };
+    if pkt_len <= 0 || pkt_len > 512 {
+        return LoginStartParse::Malformed;
+    }
+    let packet_end = off1 + pkt_len as usize;
+    if buf.len() < packet_end {
+        return LoginStartParse::Incomplete;
+    }
+    let packet = &buf[off1..packet_end];
+
+    let (pkt_id, off2) = match read_varint(packet) {
+        Some(v) => v,
+        None => return LoginStartParse::Malformed,
+    };
+    if pkt_id != 0 {
+        return LoginStartParse::Malformed;
+    }
+    let (name_len, off3) = match read_varint(&packet[off2..]) {
+        Some(v) => v,
+        None => return LoginStartParse::Malformed,
+    };
+    let name_start = off2 + off3;
+    if name_len <= 0 || name_start + name_len as usize > packet.len() {
+        return LoginStartParse::Malformed;
+    }
+    match std::str::from_utf8(&packet[name_start..name_start + name_len as usize]) {
+        Ok(name) if name.chars().count() <= MAX_USERNAME_LENGTH => {
+            LoginStartParse::Username(name.to_string())
+        }
+        _ => LoginStartParse::Malformed,
+    }
+}
+
+/// Redirects a login to `transfer_redirect` with a Transfer packet instead of waking the server.
+/// Transfers only exist in the configuration state, so the client is logged in first (offline mode, without
+/// encryption) and transferred once it acknowledged the login. The Login Start must already be read.
+/// Clients older than 1.20.5 can't be transferred and are shown `transfer_unsupported_msg_text` instead
+pub async fn redirect_login<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: S,
+    config: &Config,
+    login: &LoginHandshake,
+    username: Option<&str>,
+) -> Result<()> {
+    let target = config
+        .transfer_target
+        .as_ref()
+        .context("transfer_redirect is not set")?;
+    if login.protocol_version < TRANSFER_MIN_PROTOCOL_VERSION {
+        return send_disconnect_message(socket, config, &config.transfer_unsupported_msg_text)
+            .await;
+    }
+
+    let login_success = serialize_login_success(
+        uuid::Uuid::new_v4(),
+        username.unwrap_or("Player"),
+        login.protocol_version,
+    );
+    timeout(
+        config.timeouts.client_write(),
+        socket.write_all(&login_success),
+    )
+    .await
+    .context("Sending login success timed out")??;
+
+    // Other login packets (e.g. plugin responses) may come first, they're skipped
+    let deadline = Instant::now() + config.timeouts.handshake();
+    tokio::time::timeout_at(deadline, read_login_acknowledged(&mut socket))
+        .await
+        .context("Client didn't acknowledge the login in time")??;
+
+    timeout(
+        config.timeouts.client_write(),
+        socket.write_all(&serialize_transfer(&target.host, target.port)),
+    )
+    .await
+    .context("Sending transfer timed out")??;
+    close_after_disconnect(socket, config).await
+}
+
+/// Client packets during the login are small, longer ones are refused instead of being buffered
+const MAX_LOGIN_PACKET_SIZE: usize = 32 * 1024;
+
+/// Reads packets until the Login Acknowledged packet (ID 0x03)
+async fn read_login_acknowledged<S: AsyncRead + Unpin>(socket: &mut S) -> Result<()> {
+    loop {
+        let length = read_varint_from(socket)
+            .await
+            .context("Client closed the connection before acknowledging the login")?;
+        if length <= 0 || length as usize > MAX_LOGIN_PACKET_SIZE {
+            anyhow::bail!("Invalid packet length {} during the login", length);
+        }
+        let mut packet = vec![0u8; length as usize];
+        socket.read_exact(&mut packet).await?;
+        let (packet_id, _) = read_varint(&packet).context("Malformed packet ID")?;
+        if packet_id == 0x03 {
+            return Ok(());
+        }
+    }
 }
 
 /// Launches the Minecraft server process with given command.
-/// On Windows, opens the batch/script in a new terminal window so logs stay visible
-pub fn launch_server(command: &str, args: &[&str]) -> Result<tokio::process::Child> {
+/// On Windows, opens the batch/script in a new terminal window so logs stay visible, unless `new_window` is false.
+/// With `capture_stdio`, the server runs inline with piped stdin/stdout on every platform (see [`console::ServerConsole`]).
+/// `working_dir` is the server's working directory, by default it's inherited
+pub fn launch_server(
+    command: &str,
+    args: &[&str],
+    capture_stdio: bool,
+    new_window: bool,
+    working_dir: Option<&str>,
+) -> Result<tokio::process::Child> {
+    if capture_stdio {
+        let mut cmd = tokio::process::Command::new(command);
+        if let Some(dir) = working_dir {
+            cmd.current_dir(dir);
+        }
+        let child = cmd
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        log::info!(
+            "Launched server with captured console: {} {:?}",
+            command,
+            args
+        );
+        return Ok(child);
+    }
+
     #[cfg(target_os = "windows")]
-    {
-        let mut cmd = tokio::process::Command::new("cmd");
-        cmd.args(&["/C", "start", "", "/WAIT", command]);
-        for &arg in args {
-            cmd.arg(arg);
+    if new_window {
+        // The server gets a console window of its own. Unlike a `cmd /C start /WAIT` wrapper, the child is then
+        // the server process itself (or the cmd running a .bat script), so stopping and killing it reach the server
+        const CREATE_NEW_CONSOLE: u32 = 0x0000_0010;
+        let mut cmd = tokio::process::Command::new(command);
+        if let Some(dir) = working_dir {
+            cmd.current_dir(dir);
         }
-        let child = cmd.spawn()?;
+        let child = cmd.args(args).creation_flags(CREATE_NEW_CONSOLE).spawn()?;
         log::info!("Launched server in new window: {} {:?}", command, args);
-        Ok(child)
+        return Ok(child);
     }
+    // Other platforms never open a window
     #[cfg(not(target_os = "windows"))]
-    {
-        let child = tokio::process::Command::new(command).args(args).spawn()?;
-        log::info!("Launched server: {} {:?}", command, args);
-        Ok(child)
+    let _ = new_window;
+
+    let mut cmd = tokio::process::Command::new(command);
+    if let Some(dir) = working_dir {
+        cmd.current_dir(dir);
+    }
+    let child = cmd.args(args).spawn()?;
+    log::info!("Launched server: {} {:?}", command, args);
+    Ok(child)
+}
+
+/// Default pattern for the vanilla `list` response, e.g. "There are 2 of a max of 20 players online: ..."
+pub const PLAYER_COUNT_PATTERN: &str = r"There are (\d+) of a max";
+
+pub static PLAYER_COUNT_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(PLAYER_COUNT_PATTERN).unwrap());
+
+/// Player limit in the vanilla `list` response
+pub static MAX_PLAYERS_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"of a max of (\d+)").unwrap());
+
+/// `list` responses of other server software, tried if the default pattern doesn't match. The first group is the
+/// player count, the second one the limit if the response contains it
+pub static LIST_FALLBACK_RES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        // Bukkit, older Paper and Spigot versions
+        r"There are (\d+)/(\d+) players online",
+        // EssentialsX and older Bukkit versions
+        r"There are (\d+) out of maximum (\d+) players online",
+        // Responses without the limit
+        r"There are (\d+) players? online",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+
+/// Removes the `§` formatting codes some server software colors its responses with, e.g. `§6There are §c2`
+pub fn strip_color_codes(text: &str) -> Cow<'_, str> {
+    if !text.contains('§') {
+        return Cow::Borrowed(text);
+    }
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+/// Names of the online players in a `list` response, e.g. `There are 2 of a max of 20 players online: Alex, Steve`.
+/// Responses grouping them, like `default: Alex, Steve` on separate lines, are understood as well
+pub fn parse_player_names(response: &str) -> Vec<String> {
+    let response = strip_color_codes(response);
+    // Console lines start with a timestamp, so the names are looked for after the count
+    let Some(start) = response.find("online") else {
+        return Vec::new();
+    };
+    response[start + "online".len()..]
+        .split([',', '\n'])
+        .map(|name| {
+            name.rsplit(':')
+                .next()
+                .unwrap_or(name)
+                .trim_matches(|c: char| c.is_whitespace() || c == '.')
+        })
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Player count and limit last reported by the idle watchdog. Status responses answered by MCServerNap itself
+/// (while the server starts, stops or sleeps) show them; the limit stays known while the server is asleep
+#[derive(Debug, Default)]
+pub struct PlayerCounts {
+    online: AtomicU32,
+    // 0 while unknown
+    max: AtomicU32,
+}
+
+impl PlayerCounts {
+    pub fn record_online(&self, online: u32) {
+        self.online.store(online, Ordering::Relaxed);
+    }
+
+    pub fn record_max(&self, max: u32) {
+        self.max.store(max, Ordering::Relaxed);
+    }
+
+    /// Nobody is online once the server stopped, the limit is kept
+    pub fn server_stopped(&self) {
+        self.online.store(0, Ordering::Relaxed);
+    }
+
+    /// Online players and limit, or None if the server never reported any. An unknown limit is reported as
+    /// the online count, so the server list never shows more players than allowed
+    pub fn get(&self) -> Option<(u32, u32)> {
+        let online = self.online.load(Ordering::Relaxed);
+        let max = self.max.load(Ordering::Relaxed);
+        match (online, max) {
+            (0, 0) => None,
+            (online, 0) => Some((online, online)),
+            (online, max) => Some((online, max)),
+        }
+    }
+}
+
+/// RCON command and extraction pattern used by the idle watchdog to determine the player count
+#[derive(Debug, Clone)]
+pub struct PlayerCountQuery {
+    pub command: String,
+    pub pattern: Regex,
+    pub group: usize,
+    /// Tried in order if `pattern` doesn't match, see [`LIST_FALLBACK_RES`]. Empty with a custom pattern
+    pub fallback_patterns: Vec<Regex>,
+    /// Consecutive polls without a count after which a warning is logged
+    pub unknown_count_warn_polls: u32,
+    /// How long the count may stay unknown before the server is assumed to be empty. None keeps it running
+    pub unknown_count_assume_empty_after: Option<Duration>,
+    /// Names subtracted from the count if they're listed in the response (`idle_ignore_players`)
+    pub ignored_players: Vec<String>,
+}
+
+impl Default for PlayerCountQuery {
+    fn default() -> Self {
+        PlayerCountQuery {
+            command: "list".to_string(),
+            pattern: PLAYER_COUNT_RE.clone(),
+            group: 1,
+            fallback_patterns: LIST_FALLBACK_RES.clone(),
+            unknown_count_warn_polls: 5,
+            unknown_count_assume_empty_after: None,
+            ignored_players: Vec::new(),
+        }
+    }
+}
+
+impl PlayerCountQuery {
+    /// Builds the query from the config. Falls back to the vanilla `list` command if the pattern is invalid
+    pub fn from_config(config: &Config) -> Self {
+        let query = match Regex::new(&config.player_count_pattern) {
+            // The fallbacks only know `list` responses, a custom pattern is taken as it is
+            Ok(_)
+                if config.player_count_pattern == PLAYER_COUNT_PATTERN
+                    && config.player_count_group == 1 =>
+            {
+                PlayerCountQuery {
+                    command: config.player_count_command.clone(),
+                    ..PlayerCountQuery::default()
+                }
+            }
+            Ok(pattern) => PlayerCountQuery {
+                command: config.player_count_command.clone(),
+                pattern,
+                group: config.player_count_group,
+                fallback_patterns: Vec::new(),
+                ..PlayerCountQuery::default()
+            },
+            Err(e) => {
+                log::warn!(
+                    "Invalid player_count_pattern '{}' ({}). Falling back to the `list` command.",
+                    config.player_count_pattern,
+                    e
+                );
+                PlayerCountQuery::default()
+            }
+        };
+        PlayerCountQuery {
+            unknown_count_warn_polls: config.unknown_count_warn_polls,
+            unknown_count_assume_empty_after: match config.unknown_count_assume_empty_after {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            ignored_players: config.idle_ignore_players.clone(),
+            ..query
+        }
+    }
+
+    /// Extracts the player count from a command response, ignoring color codes. Returns None if neither the
+    /// pattern nor a fallback pattern matches
+    pub fn parse(&self, response: &str) -> Option<u32> {
+        let response = strip_color_codes(response);
+        let count = |caps: regex::Captures, group| {
+            caps.get(group)
+                .and_then(|m| m.as_str().trim().parse::<u32>().ok())
+        };
+        self.pattern
+            .captures(&response)
+            .and_then(|caps| count(caps, self.group))
+            .or_else(|| {
+                self.fallback_patterns
+                    .iter()
+                    .find_map(|pattern| pattern.captures(&response).and_then(|caps| count(caps, 1)))
+            })
+    }
+
+    /// Player count the idle watchdog acts on: [`Self::parse`] minus the `ignored_players` the response lists
+    pub fn parse_counted(&self, response: &str) -> Option<u32> {
+        let count = self.parse(response)?;
+        if self.ignored_players.is_empty() {
+            return Some(count);
+        }
+        let ignored = parse_player_names(response)
+            .iter()
+            .filter(|name| {
+                self.ignored_players
+                    .iter()
+                    .any(|ignored| ignored.eq_ignore_ascii_case(name))
+            })
+            .count() as u32;
+        if ignored > 0 {
+            log::debug!("Not counting {} ignored player(s) as online", ignored);
+        }
+        Some(count.saturating_sub(ignored))
+    }
+
+    /// Extracts the player limit from a `list` response. Returns None for other responses
+    pub fn parse_max(&self, response: &str) -> Option<u32> {
+        let response = strip_color_codes(response);
+        MAX_PLAYERS_RE
+            .captures(&response)
+            .and_then(|caps| caps[1].parse().ok())
+            .or_else(|| {
+                self.fallback_patterns.iter().find_map(|pattern| {
+                    pattern
+                        .captures(&response)
+                        .and_then(|caps| caps.get(2)?.as_str().parse().ok())
+                })
+            })
+    }
+}
+
+/// Tracks polls in which the idle watchdog couldn't determine the player count
+#[derive(Debug, Default)]
+pub struct UnknownCountTracker {
+    consecutive: u32,
+    since: Option<Instant>,
+    assuming_empty: bool,
+}
+
+impl UnknownCountTracker {
+    /// Resolves the result of a poll to the count the watchdog acts on. An unknown count (None) keeps the server
+    /// running, unless it stayed unknown for `unknown_count_assume_empty_after`, in which case it's treated as empty
+    pub fn resolve(&mut self, query: &PlayerCountQuery, count: Option<u32>) -> Option<u32> {
+        if let Some(count) = count {
+            if self.consecutive >= query.unknown_count_warn_polls {
+                log::info!(
+                    "Player count can be determined again after {} unknown polls",
+                    self.consecutive
+                );
+            }
+            self.consecutive = 0;
+            self.since = None;
+            self.assuming_empty = false;
+            return Some(count);
+        }
+
+        self.consecutive += 1;
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if self.consecutive == query.unknown_count_warn_polls {
+            log::warn!(
+                "Player count couldn't be determined for {} consecutive polls! Idle detection is effectively \
+                 disabled, check that player_count_command `{}` and player_count_pattern `{}` match your server",
+                self.consecutive,
+                query.command,
+                query.pattern
+            );
+        }
+
+        match query.unknown_count_assume_empty_after {
+            Some(after) if since.elapsed() >= after => {
+                if !self.assuming_empty {
+                    self.assuming_empty = true;
+                    log::warn!(
+                        "Player count unknown for {:?}, assuming the server is empty",
+                        since.elapsed()
+                    );
+                }
+                Some(0)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How often the idle watchdog reports that it's still waiting for RCON
+const RCON_WAITING_LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the server port may be open while RCON still refuses connections before RCON is considered disabled.
+/// The port opens before the world is loaded, RCON only once the server is done
+const RCON_DISABLED_AFTER: Duration = Duration::from_secs(120);
+
+/// How persistently the RCON idle watchdog connects and polls
+#[derive(Debug, Clone)]
+pub struct RconRetry {
+    /// How long to keep trying to connect after the server was launched
+    pub connect_timeout: Duration,
+    pub connect_retry_interval: Duration,
+    /// Consecutive failed polls after which the watchdog gives up
+    pub poll_max_errors: u32,
+    /// See [`jittered`]
+    pub poll_jitter_percent: u32,
+    /// The Minecraft server, to tell a disabled RCON from a server that's still starting
+    pub server_addr: Option<String>,
+}
+
+impl RconRetry {
+    pub fn from_config(config: &Config) -> Self {
+        // Without waiting for the woken machine before launching, the launch includes booting it
+        let boot_timeout = if config.wol_mac.is_some() && config.wol_wait_port == 0 {
+            config.wol_boot_timeout
+        } else {
+            0
+        };
+        RconRetry {
+            connect_timeout: Duration::from_secs(config.rcon_connect_timeout + boot_timeout),
+            connect_retry_interval: Duration::from_secs(config.rcon_connect_retry_interval),
+            poll_max_errors: config.rcon_poll_max_errors,
+            poll_jitter_percent: config.rcon_poll_jitter_percent,
+            server_addr: None,
+        }
+    }
+
+    /// Reports RCON as disabled if the server at `server_addr` is up but RCON keeps refusing connections
+    pub fn with_server_addr(mut self, server_addr: String) -> Self {
+        self.server_addr = Some(server_addr);
+        self
+    }
+}
+
+/// `interval` shifted by a random amount of up to `jitter_percent` percent either way, so the watchdogs of several
+/// servers don't poll at the same moment. On average it's `interval`
+pub fn jittered(interval: Duration, jitter_percent: u32) -> Duration {
+    let jitter_percent = jitter_percent.min(100);
+    if jitter_percent == 0 {
+        return interval;
+    }
+    // Randomly keyed by the standard library, no need for a random number generator
+    let random = std::hash::RandomState::new().build_hasher().finish();
+    // Uniform in [-1, 1]
+    let factor = (random as f64 / u64::MAX as f64) * 2.0 - 1.0;
+    interval.mul_f64(1.0 + factor * jitter_percent as f64 / 100.0)
+}
+
+/// What the idle watchdog does after a poll, see [`IdleTimer::update`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdleAction {
+    KeepRunning,
+    /// Warn the remaining players with this `say` message and poll again after `warning`
+    Warn {
+        message: String,
+        warning: Duration,
+    },
+    Stop,
+    /// The `scheduled_stop` time was reached, players or not
+    ScheduledStop,
+}
+
+/// Tracks how long the server has been empty and decides when the idle watchdog stops it.
+/// Covers the startup grace period (`rcon_startup_grace`) and the warning before a stop (`idle_warning_seconds`),
+/// as well as the daily `scheduled_stop`
+#[derive(Debug, Clone)]
+pub struct IdleTimer {
+    idle_timeout: Duration,
+    /// Idle timeouts replacing `idle_timeout` during their time window (`idle_timeout_schedule`)
+    idle_timeout_schedule: Vec<(schedule::TimeWindow, Duration)>,
+    // Effective idle timeout of the last poll, to log changes
+    current_idle_timeout: Duration,
+    auto_stop: bool,
+    startup_grace: Duration,
+    warning: Duration,
+    warning_msg_text: String,
+    ready_broadcast_message: Option<String>,
+    last_online: Instant,
+    grace_end: Instant,
+    warned_at: Option<Instant>,
+    scheduled_stop: Option<chrono::NaiveTime>,
+    scheduled_warning: Duration,
+    scheduled_warning_msg_text: String,
+    scheduled_stop_at: Option<Instant>,
+    scheduled_warned: bool,
+    metrics: Option<Arc<ServerMetrics>>,
+    player_counts: Option<Arc<PlayerCounts>>,
+}
+
+impl IdleTimer {
+    pub fn from_config(config: &Config) -> Self {
+        IdleTimer {
+            idle_timeout: Duration::from_secs(config.rcon_idle_timeout),
+            idle_timeout_schedule: config
+                .idle_timeout_schedule
+                .iter()
+                .filter_map(|entry| {
+                    let window = schedule::TimeWindow::parse(&entry.window).ok()?;
+                    Some((window, Duration::from_secs(entry.idle_timeout)))
+                })
+                .collect(),
+            current_idle_timeout: Duration::from_secs(config.rcon_idle_timeout),
+            auto_stop: config.auto_stop_on_idle,
+            startup_grace: Duration::from_secs(config.rcon_startup_grace),
+            warning: Duration::from_secs(config.idle_warning_seconds),
+            warning_msg_text: config.idle_warning_msg_text.clone(),
+            ready_broadcast_message: config.ready_broadcast_message.clone(),
+            last_online: Instant::now(),
+            grace_end: Instant::now(),
+            warned_at: None,
+            scheduled_stop: config
+                .scheduled_stop
+                .as_deref()
+                .and_then(|time| schedule::parse_time(time).ok()),
+            scheduled_warning: Duration::from_secs(config.scheduled_stop_warning_seconds),
+            scheduled_warning_msg_text: config.scheduled_stop_msg_text.clone(),
+            scheduled_stop_at: None,
+            scheduled_warned: false,
+            metrics: None,
+            player_counts: None,
+        }
+    }
+
+    /// Message to `say` once the server became ready (`ready_broadcast_message`)
+    pub fn ready_broadcast_message(&self) -> Option<&str> {
+        self.ready_broadcast_message.as_deref()
+    }
+
+    /// Reports the player count of every poll to `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<ServerMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Shares the player count of every poll and the player limit (`record_max_players`) with the MOTD
+    pub fn with_player_counts(mut self, player_counts: Arc<PlayerCounts>) -> Self {
+        self.player_counts = Some(player_counts);
+        self
+    }
+
+    /// Remembers the player limit reported along with the player count
+    pub fn record_max_players(&self, max: Option<u32>) {
+        if let (Some(player_counts), Some(max)) = (&self.player_counts, max) {
+            player_counts.record_max(max);
+        }
+    }
+
+    /// The idle timeout of the first `idle_timeout_schedule` window the local time is in, `rcon_idle_timeout`
+    /// outside of them
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout_schedule
+            .iter()
+            .find(|(window, _)| window.is_active())
+            .map_or(self.idle_timeout, |(_, idle_timeout)| *idle_timeout)
+    }
+
+    /// Starts counting once the server is ready, beginning with the startup grace period
+    pub fn start(&mut self) {
+        if !self.auto_stop {
+            log::info!("auto_stop_on_idle is disabled, the server won't be stopped for being idle");
+        } else if !self.startup_grace.is_zero() {
+            log::info!(
+                "Startup grace period: the server won't be stopped for being idle within the next {:?}",
+                self.startup_grace
+            );
+        }
+        self.last_online = Instant::now();
+        self.grace_end = Instant::now() + self.startup_grace;
+        self.warned_at = None;
+        self.scheduled_stop_at = self.scheduled_stop.map(|time| {
+            let until = schedule::until_next(time);
+            log::info!(
+                "The server will be stopped at {} (in {})",
+                time.format("%H:%M"),
+                humantime::format_duration(Duration::from_secs(until.as_secs()))
+            );
+            Instant::now() + until
+        });
+        self.scheduled_warned = false;
+    }
+
+    /// Decides what to do after a poll found `count` players. The warning is sent once per idle cycle;
+    /// the server is stopped once it stayed empty for the whole warning window
+    pub fn update(&mut self, count: Option<u32>) -> IdleAction {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_player_count(count);
+        }
+        if let (Some(player_counts), Some(count)) = (&self.player_counts, count) {
+            player_counts.record_online(count);
+        }
+        let now = Instant::now();
+
+        // The scheduled stop doesn't care about players, only the warning is sent first
+        if let Some(stop_at) = self.scheduled_stop_at {
+            if now >= stop_at {
+                return IdleAction::ScheduledStop;
+            }
+            if !self.scheduled_warned
+                && !self.scheduled_warning.is_zero()
+                && now + self.scheduled_warning >= stop_at
+            {
+                self.scheduled_warned = true;
+                let remaining = stop_at - now;
+                return IdleAction::Warn {
+                    message: self
+                        .scheduled_warning_msg_text
+                        .replace("{seconds}", &remaining.as_secs().to_string()),
+                    warning: remaining,
+                };
+            }
+        }
+
+        if count != Some(0) {
+            if self.warned_at.take().is_some() {
+                log::info!("Players are online again, not stopping the server");
+            }
+            self.last_online = now;
+            return IdleAction::KeepRunning;
+        }
+        if !self.auto_stop {
+            return IdleAction::KeepRunning;
+        }
+
+        let idle_timeout = self.idle_timeout();
+        if idle_timeout != self.current_idle_timeout {
+            log::info!(
+                "Idle timeout is now {:?} (idle_timeout_schedule)",
+                idle_timeout
+            );
+            self.current_idle_timeout = idle_timeout;
+        }
+        let idle_end = self.last_online + idle_timeout;
+        if now >= idle_end && now < self.grace_end {
+            log::info!(
+                "No players, but the startup grace period lasts another {:?}. Not stopping yet",
+                self.grace_end - now
+            );
+        }
+        let stop_at = idle_end.max(self.grace_end);
+
+        match self.warned_at {
+            _ if self.warning.is_zero() => {
+                if now >= stop_at {
+                    IdleAction::Stop
+                } else {
+                    IdleAction::KeepRunning
+                }
+            }
+            None if now + self.warning >= stop_at => {
+                self.warned_at = Some(now);
+                IdleAction::Warn {
+                    message: self
+                        .warning_msg_text
+                        .replace("{seconds}", &self.warning.as_secs().to_string()),
+                    warning: self.warning,
+                }
+            }
+            Some(warned_at) if now >= stop_at.max(warned_at + self.warning) => IdleAction::Stop,
+            _ => IdleAction::KeepRunning,
+        }
     }
 }
 
-/// Idle watchdog: polls the RCON `list` command every `poll_interval`.
-/// If no players have been online for `timeout`, send `/stop` via RCON and exit
+/// The RCON commands the idle watchdog sends, implemented by [`rcon::Connection`]. Lets tests script the responses
+pub trait RconCmd {
+    fn cmd(&mut self, cmd: &str) -> impl Future<Output = Result<String>> + Send;
+}
+
+impl RconCmd for Connection<TcpStream> {
+    async fn cmd(&mut self, cmd: &str) -> Result<String> {
+        Ok(Connection::cmd(self, cmd).await?)
+    }
+}
+
+/// Idle watchdog: polls the player count command (`list` by default) every `poll_interval`.
+/// Returns once `idle_timer` considers the server idle, so it can be stopped (see [`stop::stop_server`]).
+/// `ready_signal_sender` is set to true once RCON is reachable and the server counts as running
+#[allow(clippy::too_many_arguments)]
 pub async fn idle_watchdog_rcon(
     rcon_addr: &str,
     rcon_pass: &str,
+    retry: RconRetry,
     poll_interval: Duration,
-    timeout: Duration,
+    idle_timer: IdleTimer,
+    player_count_query: &PlayerCountQuery,
     server_state: Arc<Mutex<ServerState>>,
-) -> Result<()> {
+    ready_signal_sender: watch::Sender<bool>,
+) -> Result<StopReason> {
     log::info!(
         "Starting RCON idle watchdog: polling {} every {:?}",
         rcon_addr,
         poll_interval
     );
     let start = Instant::now();
+    let mut failed_attempts = 0u32;
+    let mut last_waiting_log = Instant::now();
+    let mut server_up_since = None;
+    let mut reported_rcon_disabled = false;
 
-    // Wait for RCON to become available
+    // Wait for RCON to become available. Only the first failure is a warning, the server is usually still booting
     let conn = loop {
         match Connection::<TcpStream>::connect(rcon_addr, rcon_pass).await {
             Ok(c) => break c,
-            Err(err) if start.elapsed() <= Duration::from_secs(600) => {
-                log::warn!("RCON connection failed ({}), retrying...", err);
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            Err(err) if start.elapsed() <= retry.connect_timeout => {
+                if let (rcon::Error::Io(e), Some(server_addr)) = (&err, &retry.server_addr)
+                    && e.kind() == ErrorKind::ConnectionRefused
+                    && !reported_rcon_disabled
+                    && is_port_open(server_addr, Duration::from_secs(1)).await
+                {
+                    let up_since = *server_up_since.get_or_insert_with(Instant::now);
+                    if up_since.elapsed() >= RCON_DISABLED_AFTER {
+                        log::error!(
+                            "The server at {} is up, but RCON at {} still refuses connections. Is RCON enabled \
+                             (enable-rcon=true and the rcon.port in server.properties)? Without RCON the server \
+                             isn't stopped when it's idle",
+                            server_addr,
+                            rcon_addr
+                        );
+                        reported_rcon_disabled = true;
+                    }
+                }
+                failed_attempts += 1;
+                if failed_attempts == 1 {
+                    log::warn!("RCON connection failed ({}), retrying...", err);
+                } else {
+                    log::debug!("RCON connection failed ({}), retrying...", err);
+                }
+                if last_waiting_log.elapsed() >= RCON_WAITING_LOG_INTERVAL {
+                    log::info!(
+                        "RCON not ready yet, still waiting ({}s since launch, {} attempts)",
+                        start.elapsed().as_secs(),
+                        failed_attempts
+                    );
+                    last_waiting_log = Instant::now();
+                }
+                tokio::time::sleep(retry.connect_retry_interval).await;
             }
             Err(err) => {
                 {
@@ -210,7 +1516,7 @@ pub async fn idle_watchdog_rcon(
     };
 
     let mut conn = conn;
-    log::info!("Successfully connected to RCON at {}", rcon_addr);
+    log::info!(event = "ready"; "Successfully connected to RCON at {}", rcon_addr);
     {
         let mut state =
             match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
@@ -223,27 +1529,59 @@ pub async fn idle_watchdog_rcon(
         *state = ServerState::Running;
         log::debug!("Server state set to Running in idle_watchdog_rcon()");
     }
+    // Nobody waiting for readiness is fine
+    let _ = ready_signal_sender.send(true);
+    rcon_poll_loop(
+        &mut conn,
+        &retry,
+        poll_interval,
+        idle_timer,
+        player_count_query,
+        &server_state,
+    )
+    .await
+}
 
-    // Polling loop
-    let player_count_re = Regex::new(r"There are (\d+) of a max").unwrap();
-    let mut ticker = interval(poll_interval);
-    let mut last_online = Instant::now();
+/// Polling part of [`idle_watchdog_rcon`] on an established RCON connection: announces that the server is ready,
+/// then polls the player count until `idle_timer` considers the server idle. Too many failed polls in a row set
+/// `server_state` to Stopped and end it with an error
+pub async fn rcon_poll_loop<C: RconCmd>(
+    conn: &mut C,
+    retry: &RconRetry,
+    poll_interval: Duration,
+    mut idle_timer: IdleTimer,
+    player_count_query: &PlayerCountQuery,
+    server_state: &Mutex<ServerState>,
+) -> Result<StopReason> {
+    if let Some(message) = idle_timer.ready_broadcast_message()
+        && let Err(e) = conn.cmd(&format!("say {}", message)).await
+    {
+        log::warn!("Failed to announce that the server is ready: {}", e);
+    }
+    idle_timer.start();
+
+    // Polling loop. The first poll happens right away, the next one after a jittered `poll_interval`
+    let mut next_poll = Instant::now();
     let mut consecutive_errors = 0;
+    let mut unknown_counts = UnknownCountTracker::default();
 
     loop {
-        ticker.tick().await;
+        tokio::time::sleep_until(next_poll).await;
+        next_poll = Instant::now() + jittered(poll_interval, retry.poll_jitter_percent);
         let response = loop {
-            match conn.cmd("list").await {
+            match conn.cmd(&player_count_query.command).await {
                 Ok(r) => {
                     consecutive_errors = 0;
                     break r;
                 }
-                Err(e) if consecutive_errors < 5 => {
+                Err(e) if consecutive_errors < retry.poll_max_errors => {
                     consecutive_errors += 1;
                     log::warn!(
-                        "RCON `list` poll failed: {} \nRetrying... ({}/5)",
+                        "RCON `{}` poll failed: {} \nRetrying... ({}/{})",
+                        player_count_query.command,
                         e,
-                        consecutive_errors
+                        consecutive_errors,
+                        retry.poll_max_errors
                     );
                     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 }
@@ -261,31 +1599,183 @@ pub async fn idle_watchdog_rcon(
                                 }
                             };
                         *state = ServerState::Stopped;
-                        log::debug!("Server state set to Stopped in idle_watchdog_rcon()");
+                        log::debug!("Server state set to Stopped in rcon_poll_loop()");
                     }
-                    return Err(e.into());
+                    return Err(e);
                 }
             };
         };
-        log::info!("RCON list response: {}", response);
-
-        let count = player_count_re
-            .captures(&response)
-            .and_then(|caps| caps.get(1))
-            .and_then(|m| m.as_str().parse::<u32>().ok())
-            .unwrap_or(0);
+        let count = unknown_counts.resolve(
+            player_count_query,
+            player_count_query.parse_counted(&response),
+        );
+        match count {
+            Some(player_count) => log::info!(
+                player_count;
+                "RCON {} response: {}",
+                player_count_query.command,
+                response
+            ),
+            None => log::info!("RCON {} response: {}", player_count_query.command, response),
+        }
+        idle_timer.record_max_players(player_count_query.parse_max(&response));
 
-        if count > 0 {
-            last_online = Instant::now();
-        } else if last_online.elapsed() >= timeout {
-            log::info!("No players for {:?}, stopping server...", timeout);
-            let _ = conn.cmd("stop").await;
-            break;
+        match idle_timer.update(count) {
+            IdleAction::KeepRunning => {}
+            IdleAction::Warn { message, warning } => {
+                log::info!("Warning players before stopping in {:?}", warning);
+                if let Err(e) = conn.cmd(&format!("say {}", message)).await {
+                    log::warn!("Failed to send stop warning: {}", e);
+                }
+                // Check again right when the warning window is over
+                next_poll = Instant::now() + warning;
+            }
+            IdleAction::Stop => {
+                log::info!(
+                    event = "idle_stop",
+                    player_count = 0,
+                    idle_timeout_secs = idle_timer.idle_timeout().as_secs();
+                    "No players for {:?}, stopping server...",
+                    idle_timer.idle_timeout()
+                );
+                return Ok(StopReason::Idle(idle_timer.idle_timeout()));
+            }
+            IdleAction::ScheduledStop => {
+                log::info!(event = "scheduled_stop"; "Scheduled stop time reached, stopping server...");
+                return Ok(StopReason::Scheduled);
+            }
         }
     }
+}
+
+/// One-time summary logged on startup, confirming the watcher is active while the server is napping.
+/// Secrets like the RCON password are redacted
+pub fn napping_summary(
+    listen: &str,
+    backend_addr: &str,
+    rcon_addr: &str,
+    config: &Config,
+) -> String {
+    format!(
+        "MCServerNap is active: listening on {}, backend {}, RCON {} (password: <redacted>), \
+         idle timeout {}, poll interval {}s, player count source {:?}. \
+         Server is currently napping, will wake on join",
+        listen,
+        backend_addr,
+        rcon_addr,
+        if config.auto_stop_on_idle {
+            format!("{}s", config.rcon_idle_timeout)
+        } else {
+            "disabled".to_string()
+        },
+        config.rcon_poll_interval,
+        config.player_count_source
+    )
+}
+
+/// POSTs `message` as JSON (`{"content": ...}`, as understood by e.g. Discord) to `url`
+pub async fn send_webhook(url: &str, message: &str) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    client
+        .post(url)
+        .json(&serde_json::json!({ "content": message }))
+        .send()
+        .await?
+        .error_for_status()?;
     Ok(())
 }
 
+/// State of the Minecraft server as seen through RCON
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerStatus {
+    /// RCON is reachable. The counts are None if they couldn't be read from the response
+    Running {
+        players: Option<u32>,
+        max_players: Option<u32>,
+    },
+    /// RCON refused the connection
+    Stopped,
+}
+
+/// Asks the server for its player count via RCON. A refused connection means the server isn't running
+pub async fn query_server_status(
+    rcon_addr: &str,
+    rcon_pass: &str,
+    player_count_query: &PlayerCountQuery,
+) -> Result<ServerStatus> {
+    let mut conn = match Connection::<TcpStream>::connect(rcon_addr, rcon_pass).await {
+        Ok(conn) => conn,
+        Err(rcon::Error::Io(e)) if e.kind() == ErrorKind::ConnectionRefused => {
+            return Ok(ServerStatus::Stopped);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let response = conn.cmd(&player_count_query.command).await?;
+    Ok(ServerStatus::Running {
+        players: player_count_query.parse(&response),
+        max_players: player_count_query.parse_max(&response),
+    })
+}
+
+/// Status responses are limited to 32767 characters, a favicon included
+const MAX_STATUS_RESPONSE_SIZE: usize = 4 * 32767 + 8;
+
+/// Pings the server at `server_host`:`server_port` like the server list does and returns its status response JSON
+/// (MOTD, favicon, version, players)
+pub async fn query_server_motd(
+    config: &Config,
+    server_port: u16,
+    max_duration: Duration,
+) -> Result<serde_json::Value> {
+    let query = async {
+        let mut socket = TcpStream::connect(config.server_addr(server_port)).await?;
+        socket
+            .write_all(&serialize_status_request(&config.server_host, server_port))
+            .await?;
+
+        let length = read_varint_from(&mut socket).await? as usize;
+        if length > MAX_STATUS_RESPONSE_SIZE {
+            anyhow::bail!("Status response of {} bytes is too large", length);
+        }
+        let mut packet = vec![0u8; length];
+        socket.read_exact(&mut packet).await?;
+
+        let (packet_id, off1) = read_varint(&packet).context("Malformed packet ID")?;
+        if packet_id != 0 {
+            anyhow::bail!("Expected a status response, got packet {}", packet_id);
+        }
+        let (json_len, off2) = read_varint(&packet[off1..]).context("Malformed JSON length")?;
+        if json_len < 0 {
+            anyhow::bail!("Negative JSON length {} in status response", json_len);
+        }
+        let json = packet
+            .get(off1 + off2..off1 + off2 + json_len as usize)
+            .context("Truncated status response")?;
+        Ok(serde_json::from_slice(json)?)
+    };
+
+    match timeout(max_duration, query).await {
+        Ok(result) => result,
+        Err(_) => anyhow::bail!("Status ping timed out after {:?}", max_duration),
+    }
+}
+
+/// Reads a VarInt byte by byte from the socket
+async fn read_varint_from<S: AsyncRead + Unpin>(socket: &mut S) -> Result<i32> {
+    let mut buf = Vec::with_capacity(5);
+    loop {
+        buf.push(socket.read_u8().await?);
+        if buf.last().is_some_and(|byte| byte & 0x80 == 0) || buf.len() == 5 {
+            return read_varint(&buf)
+                .map(|(value, _)| value)
+                .context("Malformed VarInt");
+        }
+    }
+}
+
 /// Sends a single `/stop` command to the server via RCON and exits
 pub async fn send_stop_command(rcon_addr: &str, rcon_pass: &str) -> Result<()> {
     log::info!(
@@ -298,94 +1788,309 @@ pub async fn send_stop_command(rcon_addr: &str, rcon_pass: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn send_starting_message(mut socket: TcpStream, config: &Config) -> Result<()> {
-    let json_msg = json!({
-        "text": config.connection_msg_text,
-        "color": config.connection_msg_color,
-        "bold": config.connection_msg_bold
-    })
-    .to_string();
-    let mut packet_data = Vec::new();
+/// Runs a single RCON command and returns the server's response. Fails with a clear message if the server
+/// isn't running (RCON refused the connection)
+pub async fn send_rcon_command(rcon_addr: &str, rcon_pass: &str, command: &str) -> Result<String> {
+    let mut conn = match Connection::<TcpStream>::connect(rcon_addr, rcon_pass).await {
+        Ok(conn) => conn,
+        Err(rcon::Error::Io(e)) if e.kind() == ErrorKind::ConnectionRefused => {
+            anyhow::bail!(
+                "Server is not running (RCON at {} refused the connection)",
+                rcon_addr
+            );
+        }
+        Err(e) => return Err(e).context(format!("Failed to connect to RCON at {}", rcon_addr)),
+    };
+    Ok(conn.cmd(command).await?)
+}
 
-    //Packet ID 0x00 (login disconnect)
-    write_varint(0, &mut packet_data);
+pub async fn send_starting_message<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: S,
+    config: &Config,
+    packets: &PreserializedPackets,
+) -> Result<()> {
+    send_login_disconnect(socket, config, &packets.starting_message_packet).await
+}
 
-    write_varint(json_msg.len() as i32, &mut packet_data);
-    packet_data.extend_from_slice(json_msg.as_bytes());
+/// Tells a player joining while the server is already starting to reconnect shortly.
+/// Uses `reconnect_msg_text` if configured, otherwise the regular connection message
+pub async fn send_reconnect_message<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: S,
+    config: &Config,
+    packets: &PreserializedPackets,
+    elapsed: Duration,
+) -> Result<()> {
+    match config.reconnect_msg_text.as_ref() {
+        Some(text) => {
+            let text = text.replace("{elapsed}", &elapsed.as_secs().to_string());
+            send_disconnect_message(socket, config, &text).await
+        }
+        None => send_starting_message(socket, config, packets).await,
+    }
+}
 
-    let mut packet = Vec::new();
-    write_varint(packet_data.len() as i32, &mut packet);
-    packet.extend_from_slice(&packet_data);
+/// Sends a login disconnect packet with the given text to the client and closes the connection.
+/// Uses the color and boldness of the connection message
+pub async fn send_disconnect_message<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: S,
+    config: &Config,
+    text: &str,
+) -> Result<()> {
+    let packet = serialize_disconnect_message(
+        text,
+        &config.connection_msg_color,
+        config.connection_msg_bold,
+    );
+    send_login_disconnect(socket, config, &packet).await
+}
 
-    match tokio::time::timeout(std::time::Duration::from_secs(5), socket.write_all(&packet)).await {
+async fn send_login_disconnect<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: S,
+    config: &Config,
+    packet: &[u8],
+) -> Result<()> {
+    match tokio::time::timeout(config.timeouts.client_write(), socket.write_all(packet)).await {
         Ok(Ok(())) => (),
-        Ok(Err(e)) => log::warn!("Sending starting message to client failed: {:?}", e),
-        Err(_) => log::warn!("Sending starting message to client timed out"),
+        Ok(Err(e)) => log::warn!("Sending disconnect message to client failed: {:?}", e),
+        Err(_) => log::warn!("Sending disconnect message to client timed out"),
     }
+    close_after_disconnect(socket, config).await
+}
 
-    // Wait a short moment to let client consume data (required because otherwise client doesn't display json message)
-    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-
+/// Closes the connection once the last packet sent was received. Closing it while the client still sends
+/// something (e.g. the Login Acknowledged) resets the connection, and the client shows a connection error instead
+/// of the message. So after flushing and closing our side, the client's data is drained until it closes the
+/// connection too, for at most `disconnect_flush_delay_ms`
+async fn close_after_disconnect<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: S,
+    config: &Config,
+) -> Result<()> {
+    match tokio::time::timeout(config.timeouts.client_write(), socket.flush()).await {
+        Ok(Ok(())) => (),
+        Ok(Err(e)) => log::debug!("Flushing the connection to the client failed: {:?}", e),
+        Err(_) => log::debug!("Flushing the connection to the client timed out"),
+    }
     socket.shutdown().await?;
+
+    let drained = tokio::time::timeout(
+        Duration::from_millis(config.disconnect_flush_delay_ms),
+        async {
+            let mut buf = [0u8; 256];
+            // Ends on EOF (the client closed the connection) or a read error
+            while matches!(socket.read(&mut buf).await, Ok(n) if n > 0) {}
+        },
+    )
+    .await;
+    if drained.is_err() {
+        log::debug!(
+            "Client didn't close the connection within {} ms",
+            config.disconnect_flush_delay_ms
+        );
+    }
     Ok(())
 }
 
-async fn handle_status_ping(socket: &mut TcpStream, config: &Config) -> Result<()> {
-    // Read and discard the next packet (packet ID 0, status request)
-    let mut buf = [0u8; 512];
-    match tokio::time::timeout(std::time::Duration::from_secs(5), socket.read(&mut buf)).await {
-        Ok(_) => (),
-        Err(_) => log::warn!("Reading TcpStream timed out(handle_status_ping)"),
+/// Parses the host and port to listen on. IPv6 addresses may be given with or without brackets (`::` or `[::]`)
+pub fn listen_socket_addr(host: &str, port: u16) -> Result<SocketAddr> {
+    let ip: IpAddr = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .with_context(|| format!("Invalid host `{}`, expected an IPv4 or IPv6 address", host))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Binds a listener on `addr`. The IPv6 wildcard `::` is bound dual-stack where the platform supports it,
+/// so IPv4 clients can connect as well
+pub fn bind_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.ip() == IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        && let Err(e) = socket.set_only_v6(false)
+    {
+        log::warn!(
+            "Dual-stack isn't supported, only IPv6 clients can connect to {}: {}",
+            addr,
+            e
+        );
     }
+    // Same as tokio's TcpListener::bind, so the port can be bound again right after the server released it
+    #[cfg(not(target_os = "windows"))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
 
-    // Create custom MOTD JSON
-    // Protocol is "an integer used to check for incompatibilities between the player's client and the server
-    // they are trying to connect to.". 766 = Minecraft 1.20.5 (https://minecraft.fandom.com/wiki/Protocol_version)
-    let mut motd_json_obj = json!({
-        "version": {
-            "name": "MCServerNap (1.20.5)",
-            "protocol": 766
-        },
-        "players": {
-            "max": 0,
-            "online": 0,
-            "sample": []
-        },
-        "description": {
-            "text": config.motd_text,
-            "color": config.motd_color,
-            "bold": config.motd_bold
-        }
-    });
-
-    if let Some(server_icon_base64) = config.server_icon.as_ref() {
-        if let Value::Object(ref mut map) = motd_json_obj {
-            map.insert(
-                "favicon".to_string(),
-                Value::String(format!("data:image/png;base64,{}", server_icon_base64)),
-            );
+/// Whether something accepts connections at `addr` within `max_wait`
+pub async fn is_port_open(addr: &str, max_wait: Duration) -> bool {
+    matches!(timeout(max_wait, TcpStream::connect(addr)).await, Ok(Ok(_)))
+}
+
+/// Connects to the Minecraft server, retrying up to `attempts` times with `retry_delay` in between.
+/// The server may be briefly busy right after it became ready
+pub async fn connect_to_backend(
+    server_addr: &str,
+    attempts: u32,
+    retry_delay: Duration,
+    connect_timeout: Option<Duration>,
+) -> Result<TcpStream> {
+    let attempts = attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let result = match connect_timeout {
+            Some(connect_timeout) => {
+                match timeout(connect_timeout, TcpStream::connect(server_addr)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(std::io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("connect timed out after {:?}", connect_timeout),
+                    )),
+                }
+            }
+            None => TcpStream::connect(server_addr).await,
+        };
+        match result {
+            Ok(socket) => return Ok(socket),
+            Err(e) if attempt < attempts => {
+                log::warn!(
+                    "Connecting to Minecraft server at {} failed ({}). Retrying... ({}/{})",
+                    server_addr,
+                    e,
+                    attempt,
+                    attempts
+                );
+                attempt += 1;
+                tokio::time::sleep(retry_delay).await;
+            }
+            Err(e) => return Err(e.into()),
         }
     }
+}
+
+/// Proxies data between client and server until either side closes the connection.
+/// With an `idle_timeout`, the session is also ended once no data flowed in either direction for that long,
+/// which is reported as an error of kind `TimedOut`
+pub async fn proxy_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    client_socket: &mut S,
+    server_socket: &mut TcpStream,
+    idle_timeout: Option<Duration>,
+) -> std::io::Result<(u64, u64)> {
+    let Some(idle_timeout) = idle_timeout else {
+        return tokio::io::copy_bidirectional(client_socket, server_socket).await;
+    };
+
+    let (mut client_read, mut client_write) = tokio::io::split(client_socket);
+    let (mut server_read, mut server_write) = server_socket.split();
+    let mut client_buf = [0u8; 8192];
+    let mut server_buf = [0u8; 8192];
+    let (mut client_to_server, mut server_to_client) = (0u64, 0u64);
 
-    let motd_json = motd_json_obj.to_string();
+    loop {
+        let read = timeout(idle_timeout, async {
+            tokio::select! {
+                r = client_read.read(&mut client_buf) => (true, r),
+                r = server_read.read(&mut server_buf) => (false, r),
+            }
+        })
+        .await;
 
-    // Create status response packet
-    let mut data = Vec::new();
-    // Packet ID = 0 (status response)
-    write_varint(0, &mut data);
-    write_varint(motd_json.len() as i32, &mut data);
-    data.extend_from_slice(motd_json.as_bytes());
+        match read {
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("no data for {:?}", idle_timeout),
+                ));
+            }
+            Ok((_, Ok(0))) => return Ok((client_to_server, server_to_client)),
+            Ok((true, Ok(n))) => {
+                server_write.write_all(&client_buf[..n]).await?;
+                client_to_server += n as u64;
+            }
+            Ok((false, Ok(n))) => {
+                client_write.write_all(&server_buf[..n]).await?;
+                server_to_client += n as u64;
+            }
+            Ok((_, Err(e))) => return Err(e),
+        }
+    }
+}
 
-    let mut packet = Vec::new();
-    write_varint(data.len() as i32, &mut packet);
-    packet.extend_from_slice(&data);
+async fn handle_status_ping<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    config: &Config,
+    packets: &PreserializedPackets,
+    protocol_version: i32,
+    starting_since: Option<Instant>,
+    status_request_buffered: bool,
+) -> Result<()> {
+    // Read and discard the next packet (packet ID 0, status request), unless it was read with the handshake
+    if !status_request_buffered {
+        let mut buf = [0u8; 512];
+        match tokio::time::timeout(config.timeouts.handshake(), socket.read(&mut buf)).await {
+            Ok(_) => (),
+            Err(_) => log::warn!("Reading TcpStream timed out(handle_status_ping)"),
+        }
+    }
+
+    // While starting, show the startup progress instead of the napping MOTD if configured
+    let motd_packet = match starting_since
+        .and_then(|since| serialize_starting_motd(config, protocol_version, since.elapsed()))
+    {
+        Some(packet) => Cow::Owned(packet),
+        None => packets.motd_packet_for(protocol_version),
+    };
 
     // Send to client
-    match tokio::time::timeout(std::time::Duration::from_secs(5), socket.write_all(&packet)).await {
+    match tokio::time::timeout(
+        config.timeouts.client_write(),
+        socket.write_all(&motd_packet),
+    )
+    .await
+    {
         Ok(Ok(())) => (),
         Ok(Err(e)) => log::warn!("Sending MOTD to client failed: {:?}", e),
         Err(_) => log::warn!("Sending MOTD to client timed out"),
     }
+
+    // Answer the ping (packet ID 1) with an identical pong so the client can show the latency
+    match read_ping_payload(socket, config.timeouts.handshake()).await {
+        Some(payload) => {
+            let mut pong = Vec::new();
+            write_varint(9, &mut pong); // Packet ID + 8 byte payload
+            write_varint(1, &mut pong);
+            pong.extend_from_slice(&payload.to_be_bytes());
+            match tokio::time::timeout(config.timeouts.client_write(), socket.write_all(&pong))
+                .await
+            {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => log::warn!("Sending pong to client failed: {:?}", e),
+                Err(_) => log::warn!("Sending pong to client timed out"),
+            }
+        }
+        None => log::debug!("No ping received after status response"),
+    }
+
     socket.shutdown().await?;
     Ok(())
 }
+
+/// Reads a status ping packet (ID 1 with an i64 payload). Returns None if the client closed the connection,
+/// sent something else or nothing arrived within `read_timeout`
+async fn read_ping_payload<S: AsyncRead + Unpin>(
+    socket: &mut S,
+    read_timeout: Duration,
+) -> Option<i64> {
+    let read_packet = async {
+        // Length VarInt (1 byte) + packet ID (1 byte) + payload (8 bytes)
+        let mut buf = [0u8; 10];
+        socket.read_exact(&mut buf).await.ok()?;
+
+        match (read_varint(&buf), read_varint(&buf[1..])) {
+            (Some((9, 1)), Some((1, 1))) => Some(i64::from_be_bytes(buf[2..10].try_into().ok()?)),
+            _ => None,
+        }
+    };
+
+    timeout(read_timeout, read_packet).await.ok().flatten()
+}
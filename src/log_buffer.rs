@@ -0,0 +1,153 @@
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// The most recent log lines of this application, kept in memory so they can be fetched from a running instance
+pub struct LogBuffer {
+    lines: Mutex<VecDeque<String>>,
+    capacity: AtomicUsize,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        LogBuffer {
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: AtomicUsize::new(capacity),
+        }
+    }
+
+    /// Appends a line, dropping the oldest one once the buffer is full. A capacity of 0 keeps nothing
+    pub fn push(&self, line: String) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() >= capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Changes how many lines are kept, dropping the oldest ones if there are too many
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        while lines.len() > capacity {
+            lines.pop_front();
+        }
+    }
+
+    /// The buffered lines, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+}
+
+/// Log lines captured by the logger installed with [`init_logger`]. Holds 100 lines until `log_buffer_lines` is applied
+pub static LOG_BUFFER: LazyLock<LogBuffer> = LazyLock::new(|| LogBuffer::new(100));
+
+/// How log lines are printed to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogFormat {
+    /// env_logger's human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level`, `module`, `message` and the record's structured fields
+    /// (e.g. `peer`, `player`, `player_count`)
+    Json,
+}
+
+/// Logs through env_logger as usual (or as JSON, filtered the same way) and additionally captures every record
+/// it prints into [`LOG_BUFFER`]
+struct CapturingLogger {
+    inner: env_logger::Logger,
+    format: LogFormat,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+        match self.format {
+            LogFormat::Text => self.inner.log(record),
+            LogFormat::Json => {
+                let mut line = json_record(record).to_string();
+                line.push('\n');
+                let _ = std::io::stderr().lock().write_all(line.as_bytes());
+            }
+        }
+        LOG_BUFFER.push(format!(
+            "[{} {} {}] {}",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// A record as a JSON object. Structured fields are added next to the message, numbers and booleans as such
+fn json_record(record: &log::Record) -> Value {
+    let mut object = Map::new();
+    object.insert(
+        "timestamp".to_string(),
+        humantime::format_rfc3339_millis(SystemTime::now())
+            .to_string()
+            .into(),
+    );
+    object.insert("level".to_string(), record.level().as_str().into());
+    object.insert("module".to_string(), record.target().into());
+    object.insert("message".to_string(), record.args().to_string().into());
+    let _ = record.key_values().visit(&mut JsonFields(&mut object));
+    Value::Object(object)
+}
+
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            number.into()
+        } else if let Some(number) = value.to_i64() {
+            number.into()
+        } else if let Some(boolean) = value.to_bool() {
+            boolean.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Installs env_logger with `level` printing in `format`, and captures its output into [`LOG_BUFFER`].
+/// If `RUST_LOG` is set, it takes precedence over `level`
+pub fn init_logger(level: log::LevelFilter, format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if std::env::var_os("RUST_LOG").is_none_or(|filters| filters.is_empty()) {
+        builder.filter_level(level);
+    }
+    let inner = builder.build();
+    let max_level = inner.filter();
+    log::set_boxed_logger(Box::new(CapturingLogger { inner, format }))
+        .expect("Logger must only be initialised once");
+    log::set_max_level(max_level);
+}
@@ -1,16 +1,22 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::task::JoinSet;
 use tokio::time::Duration;
 
 // Import core functions from the library crate
 use mcservernap::config;
+use mcservernap::control::{self, send_control_command};
+use mcservernap::log_buffer::{self, LOG_BUFFER, LogFormat};
+use mcservernap::server_properties::{self, ServerProperties};
+use mcservernap::stats::{self, LifetimeStats};
+use mcservernap::stop::{StopStrategy, stop_server};
+use mcservernap::verify::{self, Outcome};
 use mcservernap::{
-    ServerState, idle_watchdog_rcon, launch_server, send_stop_command, verify_handshake_packet,
+    NapServer, PlayerCountQuery, ServerStatus, listen_socket_addr, query_server_status,
+    send_rcon_command,
 };
 
 /// "Serverless" Minecraft Server Watcher
@@ -19,13 +25,22 @@ use mcservernap::{
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Log debug lines too (e.g. rejected handshakes and received packets). `RUST_LOG` takes precedence
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Directory containing `cfg.toml`. Skips searching the working directory's subdirectories for it
+    #[arg(long, global = true, env = "MCSERVERNAP_CONFIG_DIR")]
+    config_dir: Option<PathBuf>,
+    /// Log line format, `json` for log shippers
+    #[arg(long, global = true, value_enum, env = "RUST_LOG_FORMAT", default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Listen on port and start server on first actual join
     Listen {
-        /// Host/IP to bind
+        /// IP to bind (IPv4 or IPv6, `::` accepts both)
         host: String,
         /// Port to listen on
         port: u16,
@@ -34,36 +49,115 @@ enum Commands {
         /// Arguments for the command (pass all Java/batch args here)
         #[arg(num_args(0..))]
         args: Vec<String>,
-        /// Minecraft server port (use --server-port)
+        /// Minecraft server port (use --server-port). Optional with `detect_server_properties`
         #[arg(long)]
-        server_port: u16,
-        /// RCON port (use --rcon-port)
+        server_port: Option<u16>,
+        /// RCON port (use --rcon-port). Optional with `detect_server_properties`
         #[arg(long)]
-        rcon_port: u16,
-        /// RCON password (use --rcon-pass)
+        rcon_port: Option<u16>,
+        /// RCON password (use --rcon-pass). Optional with `detect_server_properties`
         #[arg(long)]
-        rcon_pass: String,
+        rcon_pass: Option<String>,
+        /// Leave a running server up on Ctrl+C or SIGTERM, so the next instance can adopt it (e.g. to update MCServerNap)
+        #[arg(long)]
+        no_stop_on_exit: bool,
+        /// Run the server inline instead of in a console window of its own on Windows, e.g. as a service
+        #[arg(long)]
+        no_window: bool,
+        /// Listen on this Unix domain socket instead of host and port, e.g. for a proxy on the same host
+        #[arg(long)]
+        unix_socket: Option<PathBuf>,
     },
-    /// Immediately stop the Minecraft server via RCON
+    /// Listen for every server in the `servers` list of the configuration, each on its own port
+    ListenAll {
+        /// Leave running servers up on Ctrl+C or SIGTERM, so the next instance can adopt them
+        #[arg(long)]
+        no_stop_on_exit: bool,
+        /// Run the servers inline instead of in console windows of their own on Windows, e.g. as a service
+        #[arg(long)]
+        no_window: bool,
+    },
+    /// Immediately stop the Minecraft server via RCON (or the configured `stop_steps`)
     Stop {
-        /// RCON port
+        /// RCON port. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_port: Option<u16>,
+        /// RCON password. Optional with `detect_server_properties`
         #[arg(long)]
-        rcon_port: u16,
-        /// RCON password
+        rcon_pass: Option<String>,
+        /// Minecraft server port. If set, waits until the server is stopped (use --server-port)
         #[arg(long)]
-        rcon_pass: String,
+        server_port: Option<u16>,
+    },
+    /// Print whether the Minecraft server is running (exit code 0) or stopped (exit code 3) and its lifetime statistics
+    Status {
+        /// RCON port. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_port: Option<u16>,
+        /// RCON password. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_pass: Option<String>,
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+        /// Also print the most recent log lines of the running `listen` instance (requires `control_port`)
+        #[arg(long)]
+        logs: bool,
+    },
+    /// Start the Minecraft server through the running `listen` instance without joining (requires `control_port`)
+    Wake,
+    /// Turn maintenance mode of the running `listen` instance on or off, so joins don't wake the server
+    /// (requires `control_port`)
+    Maintenance {
+        /// `on` or `off`
+        #[arg(value_parser = ["on", "off"])]
+        mode: String,
+    },
+    /// Run a command on the Minecraft server via RCON and print the response (e.g. `rcon-exec whitelist add Steve`)
+    #[command(name = "rcon-exec")]
+    Exec {
+        /// RCON port. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_port: Option<u16>,
+        /// RCON password. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_pass: Option<String>,
+        /// The command, without a leading slash. Its words are joined with spaces
+        #[arg(required = true, num_args(1..), trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Check the configuration, the listen address and RCON without starting anything. Exits with code 1 if a
+    /// check failed
+    Verify {
+        /// IP `listen` will bind
+        #[arg(long, default_value = "0.0.0.0")]
+        host: String,
+        /// Port `listen` will listen on
+        #[arg(long, default_value_t = 25565)]
+        port: u16,
+        /// Minecraft server port. Optional with `detect_server_properties`
+        #[arg(long)]
+        server_port: Option<u16>,
+        /// RCON port. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_port: Option<u16>,
+        /// RCON password. Optional with `detect_server_properties`
+        #[arg(long)]
+        rcon_pass: Option<String>,
     },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialise logger
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Info) // !!! CHANGE THIS BACK TO INFO BEFORE RELEASE !!!
-        .init();
-
     let cli = Cli::parse();
 
+    let level = if cli.verbose {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    };
+    log_buffer::init_logger(level, cli.log_format);
+
     match cli.command {
         Commands::Listen {
             host,
@@ -73,287 +167,437 @@ async fn main() -> Result<()> {
             server_port,
             rcon_port,
             rcon_pass,
+            no_stop_on_exit,
+            no_window,
+            unix_socket,
         } => {
-            let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
-            let rcon_addr = Arc::new(format!("127.0.0.1:{}", rcon_port));
-            let rcon_pass = Arc::new(rcon_pass);
+            let addr = listen_socket_addr(&host, port)?;
+            let mut app_config: config::Config = config::get_config(cli.config_dir.as_deref())?;
+            app_config.server_window &= !no_window;
+            LOG_BUFFER.set_capacity(app_config.log_buffer_lines);
+            let cmd = app_config.resolve_command(&cmd);
+            log::info!("Server command: {}", cmd);
+            if let Some(dir) = app_config.server_working_dir.as_deref() {
+                log::info!("Server working directory: {}", dir);
+            }
+
+            // Explicit CLI flags take precedence over server.properties
+            let detected = detect_server_properties(&app_config).with_explicit(
+                server_port,
+                rcon_port,
+                rcon_pass,
+            );
+            let server_port = detected
+                .server_port
+                .context("--server-port is required unless it's detected from server.properties")?;
+            let rcon_port = detected
+                .rcon_port
+                .context("--rcon-port is required unless it's detected from server.properties")?;
+            let rcon_pass = detected
+                .rcon_password
+                .context("--rcon-pass is required unless it's detected from server.properties")?;
 
-            let server_state = Arc::new(Mutex::new(ServerState::Stopped));
-            let app_config: config::Config = config::get_config();
-            let listener = TcpListener::bind(addr).await?;
+            let server = NapServer::builder()
+                .listen_addr(addr)
+                .command(cmd, args)
+                .server_port(server_port)
+                .rcon(rcon_port, rcon_pass)
+                .config(app_config)
+                .keep_server_running(no_stop_on_exit)
+                .unix_socket(unix_socket)
+                .build()?;
 
-            log::info!("Listening for login on {}", addr);
+            let shutdown = server.shutdown_handle();
+            tokio::spawn(async move {
+                let signal = shutdown_signal().await;
+                log::info!("Shutdown signal received ({})", signal);
+                shutdown.shutdown();
+            });
 
-            // Clone handles for shutdown handler
-            let rcon_addr_shutdown = rcon_addr.clone();
-            let rcon_pass_shutdown = rcon_pass.clone();
-            let server_state_shutdown = server_state.clone();
+            server.run().await?;
+        }
+        Commands::ListenAll {
+            no_stop_on_exit,
+            no_window,
+        } => {
+            let mut app_config = config::get_config(cli.config_dir.as_deref())?;
+            app_config.server_window &= !no_window;
+            LOG_BUFFER.set_capacity(app_config.log_buffer_lines);
+            if app_config.servers.is_empty() {
+                anyhow::bail!("Add the servers to run as [[servers]] entries to the configuration");
+            }
+            if app_config.control_port != 0 {
+                log::warn!("The control channel only works with `listen`, ignoring control_port");
+            }
 
-            tokio::select! {
-                _ = main_loop(
-                    listener,
-                    cmd,
-                    args,
-                    server_port,
-                    rcon_addr,
-                    rcon_pass,
-                    server_state,
-                    app_config
-                ) => {},
-                _ = tokio::signal::ctrl_c() => {
-                    log::info!("Shutdown signal received (Ctrl+C)");
+            let mut names = HashSet::new();
+            let mut servers = Vec::new();
+            for (i, definition) in app_config.servers.iter().enumerate() {
+                if definition.name.is_empty() || definition.name.contains(['/', '\\']) {
+                    anyhow::bail!(
+                        "Server name {:?} can't be used as part of a file name",
+                        definition.name
+                    );
+                }
+                if !names.insert(definition.name.as_str()) {
+                    anyhow::bail!("Server name {} is used more than once", definition.name);
+                }
+                let mut server_config = app_config.for_server(definition);
+                // The handshake statistics are collected across all servers, so they're only logged once.
+                // Likewise, the first server's metrics endpoint reports every server
+                if i > 0 {
+                    server_config.handshake_stats_log_interval = 0;
+                    server_config.metrics_port = 0;
+                }
+                let host = definition.listen_host.as_deref().unwrap_or("0.0.0.0");
+                let addr = listen_socket_addr(host, definition.listen_port)?;
+                let cmd = server_config.resolve_command(&definition.command);
+                log::info!("Server command of {}: {}", definition.name, cmd);
 
-                    // Check if server is running and send stop command
-                    let state_guard = match tokio::time::timeout(Duration::from_secs(5), server_state_shutdown.lock()).await {
-                        Ok(guard) => guard,
-                        Err(_) => {
-                            log::error!("Deadlock detected! Failed to acquire state lock");
-                            // Panicking here since we can't safely proceed
-                            panic!("State lock timeout - possible deadlock");
-                        }
-                    };
+                let server = NapServer::builder()
+                    .name(definition.name.clone())
+                    .listen_addr(addr)
+                    .command(cmd, definition.args.clone())
+                    .server_port(definition.server_port)
+                    .rcon(definition.rcon_port, definition.rcon_pass.clone())
+                    .config(server_config)
+                    .keep_server_running(no_stop_on_exit)
+                    .build()?;
+                servers.push((definition.name.clone(), server));
+            }
 
-                    if *state_guard == ServerState::Running {
-                        log::info!("Stopping Minecraft server gracefully...");
-                        drop(state_guard); // Release Mutex lock before RCON call
+            let shutdown_handles: Vec<_> = servers
+                .iter()
+                .map(|(_, server)| server.shutdown_handle())
+                .collect();
+            tokio::spawn(async move {
+                let signal = shutdown_signal().await;
+                log::info!("Shutdown signal received ({})", signal);
+                for shutdown in &shutdown_handles {
+                    shutdown.shutdown();
+                }
+            });
 
-                        if let Err(e) = send_stop_command(&rcon_addr_shutdown, &rcon_pass_shutdown).await {
-                            log::error!("Failed to send stop command: {}", e);
-                        } else {
-                            // Give server time to stop
-                            tokio::time::sleep(Duration::from_secs(10)).await;
-                        }
-                    }
+            // A server failing doesn't take the others down
+            let mut running = JoinSet::new();
+            for (name, server) in servers {
+                running.spawn(async move { (name, server.run().await) });
+            }
+            let mut failure = None;
+            while let Some(joined) = running.join_next().await {
+                let (name, result) = joined?;
+                if let Err(e) = result {
+                    log::error!("{} failed: {:#}", name, e);
+                    failure = Some(e);
                 }
             }
+            if let Some(e) = failure {
+                return Err(e);
+            }
         }
         Commands::Stop {
             rcon_port,
             rcon_pass,
+            server_port,
         } => {
-            let rcon_addr = format!("127.0.0.1:{}", rcon_port);
-            send_stop_command(&rcon_addr, &rcon_pass).await?;
-        }
-    }
-
-    Ok(())
-}
-
-async fn main_loop(
-    listener: TcpListener,
-    cmd: String,
-    args: Vec<String>,
-    server_port: u16,
-    rcon_addr: Arc<String>,
-    rcon_pass: Arc<String>,
-    server_state: Arc<Mutex<ServerState>>,
-    app_config: config::Config,
-) -> Result<()> {
-    let arg_slices: Vec<&str> = args.iter().map(String::as_str).collect();
+            let app_config = config::get_config(cli.config_dir.as_deref())?;
 
-    loop {
-        log::info!("Listening...");
-
-        match listener.accept().await {
-            Ok((mut client_socket, peer)) => {
-                client_socket.set_nodelay(true)?;
-                log::info!("Incoming TCP connection from {}", peer);
+            // Prefer the running instance, so the stop is coordinated with its idle watchdog
+            if app_config.control_port != 0 {
+                let max_stop_duration = app_config.max_stop_duration();
+                let reply_timeout = if max_stop_duration.is_zero() {
+                    Duration::MAX
+                } else {
+                    max_stop_duration + control::CONTROL_TIMEOUT
+                };
+                match send_control_command(app_config.control_port, "stop", reply_timeout).await {
+                    Ok(reply) => {
+                        if let Some(error) = reply.strip_prefix("error: ") {
+                            anyhow::bail!("Couldn't stop the server: {}", error);
+                        }
+                        match reply.as_str() {
+                            "not running" => println!("Server is not running"),
+                            _ => println!("Server stopped"),
+                        }
+                        return Ok(());
+                    }
+                    Err(e)
+                        if e.downcast_ref::<std::io::Error>()
+                            .is_some_and(|e| e.kind() == ErrorKind::ConnectionRefused) =>
+                    {
+                        log::info!("No running instance on the control port, stopping directly");
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
 
-                let client_handled = {
-                    // Scoped to hold the Mutex lock only while checking and possibly updating state
+            let detected =
+                detect_server_properties(&app_config).with_explicit(None, rcon_port, rcon_pass);
+            let rcon_port = detected
+                .rcon_port
+                .context("--rcon-port is required unless it's detected from server.properties")?;
+            let rcon_pass = detected
+                .rcon_password
+                .context("--rcon-pass is required unless it's detected from server.properties")?;
 
-                    let mut state_guard =
-                        match tokio::time::timeout(Duration::from_secs(5), server_state.lock())
-                            .await
-                        {
-                            Ok(guard) => guard,
-                            Err(_) => {
-                                log::error!("Deadlock detected! Failed to acquire state lock");
-                                panic!("State lock timeout - possible deadlock");
-                            }
-                        };
+            let rcon_addr = app_config.rcon_addr(rcon_port);
+            let server_addr = server_port.map(|port| app_config.server_addr(port));
+            let stop_strategy =
+                StopStrategy::from_config(&app_config, &rcon_addr, &rcon_pass, server_addr);
+            stop_server(None, &stop_strategy).await?;
+        }
+        Commands::Status {
+            rcon_port,
+            rcon_pass,
+            json,
+            logs,
+        } => {
+            let app_config = config::get_config(cli.config_dir.as_deref())?;
+            let detected =
+                detect_server_properties(&app_config).with_explicit(None, rcon_port, rcon_pass);
+            let rcon_port = detected
+                .rcon_port
+                .context("--rcon-port is required unless it's detected from server.properties")?;
+            let rcon_pass = detected
+                .rcon_password
+                .context("--rcon-pass is required unless it's detected from server.properties")?;
 
-                    match *state_guard {
-                        ServerState::Stopped => {
-                            // Start the server and RCON watchdog
-                            match verify_handshake_packet(&mut client_socket, peer, &app_config)
-                                .await
-                            {
-                                Ok(true) => {
-                                    if let Err(e) = mcservernap::send_starting_message(
-                                        client_socket,
-                                        &app_config,
-                                    )
-                                    .await
-                                    {
-                                        log::warn!("Failed to notify {}: {}", peer, e);
-                                    }
+            let rcon_addr = app_config.rcon_addr(rcon_port);
+            let player_count_query = PlayerCountQuery::from_config(&app_config);
+            let status = query_server_status(&rcon_addr, &rcon_pass, &player_count_query).await?;
+            let stats = LifetimeStats::load(&stats::stats_file(app_config.config_dir(), None))
+                .unwrap_or_else(|e| {
+                    log::warn!("{:#}", e);
+                    LifetimeStats::default()
+                });
+            println!("{}", format_status(status, &stats, json));
 
-                                    // Transition to starting state
-                                    *state_guard = ServerState::Starting;
-                                    log::debug!("Server state set to Starting in main()");
+            if logs {
+                if app_config.control_port == 0 {
+                    log::warn!("Set control_port to fetch the log lines of the running instance");
+                } else {
+                    match send_control_command(
+                        app_config.control_port,
+                        "logs",
+                        control::CONTROL_TIMEOUT,
+                    )
+                    .await
+                    {
+                        Ok(lines) => println!("{}", lines),
+                        Err(e) => log::warn!("Couldn't fetch log lines: {:#}", e),
+                    }
+                }
+            }
 
-                                    let mut child = launch_server(&cmd, &arg_slices)?;
+            if status == ServerStatus::Stopped {
+                // LSB convention for "program is not running"
+                std::process::exit(3);
+            }
+        }
+        Commands::Wake => {
+            let app_config = config::get_config(cli.config_dir.as_deref())?;
+            if app_config.control_port == 0 {
+                anyhow::bail!("Set control_port to wake the server through the running instance");
+            }
+            let reply =
+                send_control_command(app_config.control_port, "wake", control::CONTROL_TIMEOUT)
+                    .await?;
+            if let Some(error) = reply.strip_prefix("error: ") {
+                anyhow::bail!("Couldn't wake the server: {}", error);
+            }
+            match reply.as_str() {
+                "woken" => println!("Server is starting"),
+                "already starting" => println!("Server is already starting"),
+                _ => println!("Server is already running"),
+            }
+        }
+        Commands::Maintenance { mode } => {
+            let app_config = config::get_config(cli.config_dir.as_deref())?;
+            if app_config.control_port == 0 {
+                anyhow::bail!(
+                    "Set control_port to switch maintenance mode of the running instance, or set maintenance_mode \
+                     in the configuration"
+                );
+            }
+            let reply = send_control_command(
+                app_config.control_port,
+                &format!("maintenance {}", mode),
+                control::CONTROL_TIMEOUT,
+            )
+            .await?;
+            if let Some(error) = reply.strip_prefix("error: ") {
+                anyhow::bail!("Couldn't switch maintenance mode: {}", error);
+            }
+            println!("Maintenance mode is {}", mode);
+        }
+        Commands::Exec {
+            rcon_port,
+            rcon_pass,
+            command,
+        } => {
+            let app_config = config::get_config(cli.config_dir.as_deref())?;
+            let detected =
+                detect_server_properties(&app_config).with_explicit(None, rcon_port, rcon_pass);
+            let rcon_port = detected
+                .rcon_port
+                .context("--rcon-port is required unless it's detected from server.properties")?;
+            let rcon_pass = detected
+                .rcon_password
+                .context("--rcon-pass is required unless it's detected from server.properties")?;
 
-                                    let rcon_addr_clone = rcon_addr.clone();
-                                    let rcon_pass_clone = rcon_pass.clone();
-                                    let server_state_for_rcon_watchdog = server_state.clone();
-                                    let rcon_watchdog_handle = tokio::spawn(async move {
-                                        if let Err(e) = idle_watchdog_rcon(
-                                            &rcon_addr_clone,
-                                            &rcon_pass_clone,
-                                            Duration::from_secs(app_config.rcon_poll_interval), // check interval
-                                            Duration::from_secs(app_config.rcon_idle_timeout), // idle timeout
-                                            server_state_for_rcon_watchdog,
-                                        )
-                                        .await
-                                        {
-                                            log::error!("Idle watchdog error: {}", e);
-                                        }
-                                    });
+            let rcon_addr = app_config.rcon_addr(rcon_port);
+            let response = send_rcon_command(&rcon_addr, &rcon_pass, &command.join(" ")).await?;
+            println!("{}", response);
+        }
+        Commands::Verify {
+            host,
+            port,
+            server_port,
+            rcon_port,
+            rcon_pass,
+        } => {
+            // Unlike the other subcommands, the configuration is only read, so a broken file is reported instead
+            // of being replaced with the defaults
+            let config_dir = match &cli.config_dir {
+                Some(dir) => dir.to_string_lossy().into_owned(),
+                None => config::Config::default().config_dir().to_string(),
+            };
+            let (config_check, app_config) = verify::check_config(&config_dir);
+            let mut checks = vec![config_check];
+            // The remaining checks still tell something with the defaults
+            let app_config = app_config.unwrap_or_default();
+            checks.push(verify::check_server_icon(&app_config));
+            checks.push(verify::check_packets(&app_config));
+            checks.push(match listen_socket_addr(&host, port) {
+                Ok(addr) => verify::check_listen_addr(addr).await,
+                Err(e) => verify::Check::new(
+                    format!("Listen address {}", host),
+                    Outcome::Failed,
+                    format!("{:#}", e),
+                ),
+            });
 
-                                    let server_state_for_server_exit = server_state.clone();
-                                    tokio::spawn(async move {
-                                        // Wait for server exit
-                                        match child.wait().await {
-                                            Ok(_) => (),
-                                            Err(e) => {
-                                                log::error!(
-                                                    "Failed to wait for server exit: {:?}",
-                                                    e
-                                                )
-                                            }
-                                        }
+            checks.push(verify::check_rcon_enabled(&app_config));
+            let detected = detect_server_properties(&app_config).with_explicit(
+                server_port,
+                rcon_port,
+                rcon_pass,
+            );
+            match detected.server_port {
+                Some(server_port) => {
+                    checks.push(verify::check_server(&app_config, server_port).await)
+                }
+                None => checks.push(verify::Check::new(
+                    "Minecraft server",
+                    Outcome::Skipped,
+                    "pass --server-port to check it",
+                )),
+            }
+            match (detected.rcon_port, detected.rcon_password) {
+                (Some(rcon_port), Some(rcon_pass)) => {
+                    checks.push(verify::check_rcon(&app_config, rcon_port, &rcon_pass).await)
+                }
+                _ => checks.push(verify::Check::new(
+                    "RCON",
+                    Outcome::Skipped,
+                    "pass --rcon-port and --rcon-pass to check it",
+                )),
+            }
 
-                                        rcon_watchdog_handle.abort();
-                                        log::info!("RCON watchdog aborted");
+            for check in &checks {
+                println!("{}", check);
+            }
+            if checks.iter().any(|check| check.outcome == Outcome::Failed) {
+                std::process::exit(1);
+            }
+        }
+    }
 
-                                        {
-                                            let mut state = match tokio::time::timeout(
-                                                Duration::from_secs(5),
-                                                server_state_for_server_exit.lock(),
-                                            )
-                                            .await
-                                            {
-                                                Ok(guard) => guard,
-                                                Err(_) => {
-                                                    log::error!(
-                                                        "Deadlock detected! Failed to acquire state lock"
-                                                    );
-                                                    panic!(
-                                                        "State lock timeout - possible deadlock"
-                                                    );
-                                                }
-                                            };
-                                            *state = ServerState::Stopped;
-                                        }
-                                        log::debug!(
-                                            "Server state set to Stopped after server exit in main()"
-                                        );
-                                        log::info!("Server stopped.");
-                                    });
+    Ok(())
+}
 
-                                    true
-                                }
-                                Ok(false) => false, // Not a login handshake, ignore
-                                Err(_) => false,    // Wait for next connection
-                            }
-                        }
-                        ServerState::Starting => {
-                            // Keep notifying the player client that the server is starting
-                            match verify_handshake_packet(&mut client_socket, peer, &app_config)
-                                .await
-                            {
-                                Ok(true) => {
-                                    if let Err(e) = mcservernap::send_starting_message(
-                                        client_socket,
-                                        &app_config,
-                                    )
-                                    .await
-                                    {
-                                        log::warn!(
-                                            "Failed to notify {} while starting server: {}",
-                                            peer,
-                                            e
-                                        );
-                                    }
+/// Formats the output of the `status` subcommand, e.g. `running players=3/20` or `stopped`, followed by the
+/// lifetime statistics, e.g. `wakes=12 awake=5h 3m asleep=6days 2h`
+fn format_status(status: ServerStatus, stats: &LifetimeStats, json: bool) -> String {
+    let count = |count: Option<u32>| count.map_or("?".to_string(), |c| c.to_string());
+    let duration = |secs: u64| humantime::format_duration(Duration::from_secs(secs)).to_string();
+    if json {
+        let mut status_json = match status {
+            ServerStatus::Running {
+                players,
+                max_players,
+            } => serde_json::json!({
+                "state": "running",
+                "players": players,
+                "max_players": max_players
+            }),
+            ServerStatus::Stopped => serde_json::json!({ "state": "stopped" }),
+        };
+        status_json["stats"] = serde_json::json!(stats);
+        return status_json.to_string();
+    }
 
-                                    true
-                                }
-                                Ok(false) => false,
-                                Err(_) => false,
-                            }
-                        }
-                        ServerState::Running => {
-                            // Server is running: proxy connection to actual Minecraft server
-                            log::info!("Proxying connection for {}", peer);
-                            tokio::spawn(async move {
-                                let server_addr = format!("127.0.0.1:{}", server_port);
-                                match TcpStream::connect(server_addr).await {
-                                    Ok(mut server_socket) => {
-                                        server_socket.set_nodelay(true).unwrap();
-                                        match tokio::io::copy_bidirectional(
-                                            &mut client_socket,
-                                            &mut server_socket,
-                                        )
-                                        .await
-                                        {
-                                            Ok((read, written)) => {
-                                                log::debug!(
-                                                    "Proxy successful for {}: read {} bytes, wrote {}",
-                                                    peer,
-                                                    read,
-                                                    written
-                                                );
-                                            }
-                                            Err(e) => {
-                                                log::error!("Proxy error for {}: {:?}", peer, e);
-                                            }
-                                        }
+    let state = match status {
+        ServerStatus::Running {
+            players,
+            max_players,
+        } => format!("running players={}/{}", count(players), count(max_players)),
+        ServerStatus::Stopped => "stopped".to_string(),
+    };
+    format!(
+        "{}\nwakes={} awake={} asleep={}",
+        state,
+        stats.wakes,
+        duration(stats.awake_secs),
+        duration(stats.asleep_secs)
+    )
+}
 
-                                        // Attempt graceful shutdown of sockets
-                                        if let Err(e) = client_socket.shutdown().await {
-                                            log::warn!(
-                                                "Failed to shutdown client socket for {}: {:?}",
-                                                peer,
-                                                e
-                                            );
-                                        }
-                                        if let Err(e) = server_socket.shutdown().await {
-                                            log::warn!(
-                                                "Failed to shutdown server socket for {}: {:?}",
-                                                peer,
-                                                e
-                                            );
-                                        }
-                                    }
-                                    Err(e) => {
-                                        log::error!(
-                                            "Failed to connect to Minecraft server for {}: {:?}",
-                                            peer,
-                                            e
-                                        );
-                                    }
-                                }
-                            });
-                            true
-                        }
-                    }
-                };
+/// Reads the Minecraft server's settings from `server_properties_path` if `detect_server_properties` is enabled
+fn detect_server_properties(app_config: &config::Config) -> ServerProperties {
+    if !app_config.detect_server_properties {
+        return ServerProperties::default();
+    }
 
-                if !client_handled {
-                    // Connection ignored, just drop socket and continue accepting
-                    log::debug!(
-                        "Connection from {} ignored (not login handshake or not handled)",
-                        peer
-                    );
-                }
+    match server_properties::parse(&app_config.server_properties_path) {
+        Ok(properties) => {
+            log::info!(
+                "Using {} for settings not passed on the command line",
+                app_config.server_properties_path
+            );
+            if properties.enable_rcon == Some(false) {
+                log::warn!("RCON is disabled in server.properties (enable-rcon=false)");
             }
+            properties
+        }
+        Err(e) => {
+            log::warn!("Couldn't detect server settings: {:#}", e);
+            ServerProperties::default()
+        }
+    }
+}
+
+/// Resolves once this application is asked to shut down: Ctrl+C, or on Unix also SIGTERM (e.g. `systemctl stop`,
+/// `docker stop`). Returns the name of the signal
+async fn shutdown_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => "Ctrl+C",
+                _ = sigterm.recv() => "SIGTERM",
+            },
             Err(e) => {
-                log::error!("Failed to accept connection: {:?}", e);
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                continue;
+                log::warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "Ctrl+C"
             }
         }
     }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
 }
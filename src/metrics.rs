@@ -0,0 +1,299 @@
+use crate::notify::StopReason;
+use crate::{ServerState, handshake_counts, handshake_failure_counts};
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Weak};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant, timeout};
+
+/// How long a metrics request may take to arrive
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Every server of this process, so one metrics endpoint reports all of them
+static REGISTRY: LazyLock<std::sync::Mutex<Vec<Weak<ServerMetrics>>>> =
+    LazyLock::new(|| std::sync::Mutex::new(Vec::new()));
+
+/// Wakes, stops, awake time and the last player count of one server, reported on `metrics_port`
+#[derive(Debug)]
+pub struct ServerMetrics {
+    name: Option<String>,
+    state: Arc<Mutex<ServerState>>,
+    created: Instant,
+    wakes: AtomicU64,
+    stops: std::sync::Mutex<BTreeMap<&'static str, u64>>,
+    /// -1 while unknown
+    last_player_count: AtomicI64,
+    awake: std::sync::Mutex<AwakeTime>,
+}
+
+#[derive(Debug, Default)]
+struct AwakeTime {
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl ServerMetrics {
+    /// Creates the metrics of a server and adds them to the metrics endpoint. `name` labels them if set
+    pub fn register(name: Option<String>, state: Arc<Mutex<ServerState>>) -> Arc<Self> {
+        let metrics = Arc::new(ServerMetrics {
+            name,
+            state,
+            created: Instant::now(),
+            wakes: AtomicU64::new(0),
+            stops: std::sync::Mutex::new(BTreeMap::new()),
+            last_player_count: AtomicI64::new(-1),
+            awake: std::sync::Mutex::new(AwakeTime::default()),
+        });
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        registry.retain(|metrics| metrics.strong_count() > 0);
+        registry.push(Arc::downgrade(&metrics));
+        metrics
+    }
+
+    /// The server was launched because a player (or the control channel) woke it
+    pub fn record_wake(&self) {
+        self.wakes.fetch_add(1, Ordering::Relaxed);
+        self.mark_awake();
+    }
+
+    /// The server runs from now on, e.g. an adopted one
+    pub fn mark_awake(&self) {
+        let mut awake = self.awake.lock().unwrap_or_else(|e| e.into_inner());
+        awake.since.get_or_insert_with(Instant::now);
+    }
+
+    pub fn record_stop(&self, reason: StopReason) {
+        *self
+            .stops
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(reason.as_str())
+            .or_default() += 1;
+        self.last_player_count.store(-1, Ordering::Relaxed);
+        let mut awake = self.awake.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(since) = awake.since.take() {
+            awake.total += since.elapsed();
+        }
+    }
+
+    /// A poll of the idle watchdog found `count` players
+    pub fn record_player_count(&self, count: Option<u32>) {
+        let count = count.map_or(-1, i64::from);
+        self.last_player_count.store(count, Ordering::Relaxed);
+    }
+
+    fn awake_time(&self) -> Duration {
+        let awake = self.awake.lock().unwrap_or_else(|e| e.into_inner());
+        awake.total + awake.since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Serves the metrics in the Prometheus text format on `GET /metrics`
+pub async fn serve_metrics(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = handle_metrics_connection(socket).await {
+                        log::debug!("Metrics request from {} failed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to accept metrics connection: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn handle_metrics_connection(mut socket: TcpStream) -> Result<()> {
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(&mut socket);
+        timeout(REQUEST_TIMEOUT, reader.read_line(&mut request_line)).await??;
+    }
+
+    let response = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", "/metrics", _] => {
+            let body = render_metrics().await;
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+/// Labels of a metric line, `{server="name",...}`. Empty without labels
+fn labels(name: Option<&str>, extra: &[(&str, &str)]) -> String {
+    let labels: Vec<String> = name
+        .map(|name| ("server", name))
+        .into_iter()
+        .chain(extra.iter().copied())
+        .map(|(key, value)| {
+            format!(
+                "{}=\"{}\"",
+                key,
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// All metrics in the Prometheus text format
+pub async fn render_metrics() -> String {
+    let servers: Vec<Arc<ServerMetrics>> = REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_server_state Current state of the server (1 for the current one)"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_server_state gauge");
+    for server in &servers {
+        let current = *server.state.lock().await;
+        for (state, label) in [
+            (ServerState::Stopped, "stopped"),
+            (ServerState::Starting, "starting"),
+            (ServerState::Running, "running"),
+            (ServerState::Stopping, "stopping"),
+        ] {
+            let value = u8::from(current == state);
+            let _ = writeln!(
+                out,
+                "mcservernap_server_state{} {}",
+                labels(server.name.as_deref(), &[("state", label)]),
+                value
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_wakes_total Times the server was woken"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_wakes_total counter");
+    for server in &servers {
+        let _ = writeln!(
+            out,
+            "mcservernap_wakes_total{} {}",
+            labels(server.name.as_deref(), &[]),
+            server.wakes.load(Ordering::Relaxed)
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_stops_total Times the server stopped, by reason"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_stops_total counter");
+    for server in &servers {
+        let stops = server
+            .stops
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for (reason, count) in stops {
+            let _ = writeln!(
+                out,
+                "mcservernap_stops_total{} {}",
+                labels(server.name.as_deref(), &[("reason", reason)]),
+                count
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_awake_seconds_total Time the server was running"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_awake_seconds_total counter");
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_asleep_seconds_total Time the server was napping"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_asleep_seconds_total counter");
+    for server in &servers {
+        let awake = server.awake_time();
+        let asleep = server.created.elapsed().saturating_sub(awake);
+        let labels = labels(server.name.as_deref(), &[]);
+        let _ = writeln!(
+            out,
+            "mcservernap_awake_seconds_total{} {:.3}",
+            labels,
+            awake.as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "mcservernap_asleep_seconds_total{} {:.3}",
+            labels,
+            asleep.as_secs_f64()
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_players Players online at the last poll of the idle watchdog"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_players gauge");
+    for server in &servers {
+        let count = server.last_player_count.load(Ordering::Relaxed);
+        if count >= 0 {
+            let _ = writeln!(
+                out,
+                "mcservernap_players{} {}",
+                labels(server.name.as_deref(), &[]),
+                count
+            );
+        }
+    }
+
+    let (status_pings, logins) = handshake_counts();
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_status_pings_total Status pings answered"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_status_pings_total counter");
+    let _ = writeln!(out, "mcservernap_status_pings_total {}", status_pings);
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_login_handshakes_total Login handshakes received"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_login_handshakes_total counter");
+    let _ = writeln!(out, "mcservernap_login_handshakes_total {}", logins);
+    let _ = writeln!(
+        out,
+        "# HELP mcservernap_handshake_failures_total Connections not treated as a valid handshake, by reason"
+    );
+    let _ = writeln!(out, "# TYPE mcservernap_handshake_failures_total counter");
+    for (failure, count) in handshake_failure_counts() {
+        let _ = writeln!(
+            out,
+            "mcservernap_handshake_failures_total{} {}",
+            labels(None, &[("reason", failure.as_str())]),
+            count
+        );
+    }
+
+    out
+}
@@ -0,0 +1,2006 @@
+use crate::client_stream::{ClientListener, ClientStream};
+use crate::config::{self, Config, PlayerCountSource, ReadinessMode};
+use crate::console::{ServerConsole, idle_watchdog_stdio, wait_for_done_line};
+use crate::control::{self, ControlCommand, ControlRequest};
+use crate::hooks::run_hook;
+use crate::metrics::{ServerMetrics, serve_metrics};
+use crate::notify::{self, StopReason};
+use crate::persisted_state::{self, PersistedServer};
+use crate::preserialized_packets::PreserializedPackets;
+use crate::proxy_protocol::read_proxy_header;
+use crate::schedule;
+use crate::server_log::ServerLog;
+use crate::server_properties;
+use crate::server_whitelist;
+use crate::stats::{self, StatsRecorder};
+use crate::stop::{
+    StopStrategy, kill_process, kill_process_and_wait, kill_server_process, stop_server,
+};
+use crate::wol;
+use crate::{
+    ConnectionRateLimiter, IdleTimer, LoginHandshake, PlayerCountQuery, PlayerCounts, RconRetry,
+    ServerState, StartBackoff, bind_listener, connect_to_backend, idle_watchdog_rcon, is_port_open,
+    launch_server, log_handshake_failures_periodically, napping_summary, proxy_connection,
+    query_server_motd, read_login_username, redirect_login, send_disconnect_message,
+    send_reconnect_message, send_starting_message, send_webhook, verify_handshake_packet,
+    verify_handshake_packet_buffered,
+};
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, Notify, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+/// How long probing a server that may be running already (`detect_running_server`) waits for a connection
+const DETECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often `cfg.toml` is checked for changes (`watch_config`)
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The sleeping proxy: listens for logins, launches the Minecraft server on the first join and stops it again
+/// once it's idle. Created with [`NapServer::builder`]
+pub struct NapServer {
+    name: Option<String>,
+    listen_addr: SocketAddr,
+    unix_socket: Option<PathBuf>,
+    cmd: String,
+    args: Vec<String>,
+    server_port: u16,
+    rcon_port: u16,
+    rcon_pass: String,
+    config: Config,
+    keep_server_running: bool,
+    shutdown: Arc<Notify>,
+}
+
+/// Builds a [`NapServer`]. `listen_addr`, `command`, `server_port` and `rcon` are required
+#[derive(Default)]
+pub struct NapServerBuilder {
+    name: Option<String>,
+    listen_addr: Option<SocketAddr>,
+    unix_socket: Option<PathBuf>,
+    command: Option<(String, Vec<String>)>,
+    server_port: Option<u16>,
+    rcon: Option<(u16, String)>,
+    config: Option<Config>,
+    keep_server_running: bool,
+}
+
+/// Asks a running [`NapServer`] to shut down, see [`NapServer::shutdown_handle`]
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Notify>);
+
+impl ShutdownHandle {
+    /// Makes [`NapServer::run`] stop the Minecraft server and return. Works before `run` was called as well
+    pub fn shutdown(&self) {
+        self.0.notify_one();
+    }
+}
+
+impl NapServerBuilder {
+    /// Tells this server apart from others run in the same process, in the logs and in the name of the file
+    /// recording the running server. Required if several servers share the configuration directory
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Address to listen for logins on
+    pub fn listen_addr(mut self, addr: SocketAddr) -> Self {
+        self.listen_addr = Some(addr);
+        self
+    }
+
+    /// Listen on a Unix domain socket instead of `listen_addr`, e.g. for a proxy on the same host. `listen_addr`
+    /// is still required, its IP is used for `metrics_port`. Not supported on Windows
+    pub fn unix_socket(mut self, path: Option<PathBuf>) -> Self {
+        self.unix_socket = path;
+        self
+    }
+
+    /// Command launching the Minecraft server (e.g. `java` or a start script) and its arguments
+    pub fn command(mut self, cmd: impl Into<String>, args: Vec<String>) -> Self {
+        self.command = Some((cmd.into(), args));
+        self
+    }
+
+    /// Port of the Minecraft server on `server_host`
+    pub fn server_port(mut self, port: u16) -> Self {
+        self.server_port = Some(port);
+        self
+    }
+
+    /// RCON port on `rcon_host` and password
+    pub fn rcon(mut self, port: u16, password: impl Into<String>) -> Self {
+        self.rcon = Some((port, password.into()));
+        self
+    }
+
+    /// Settings otherwise read from the configuration file. Defaults to [`Config::default`]
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Leave a running server up on shutdown, so the next instance can adopt it (`--no-stop-on-exit`)
+    pub fn keep_server_running(mut self, keep: bool) -> Self {
+        self.keep_server_running = keep;
+        self
+    }
+
+    pub fn build(self) -> Result<NapServer> {
+        let listen_addr = self.listen_addr.context("listen_addr is required")?;
+        let (cmd, args) = self.command.context("command is required")?;
+        let server_port = self.server_port.context("server_port is required")?;
+        let (rcon_port, rcon_pass) = self.rcon.context("rcon is required")?;
+        let mut config = self.config.unwrap_or_default();
+        config.validate()?;
+        if self.unix_socket.is_some() && config.handoff_listener {
+            anyhow::bail!("handoff_listener can't be used with a Unix domain socket");
+        }
+        let listen_port = self.unix_socket.is_none().then_some(listen_addr.port());
+        if proxies_to_itself(listen_port, &config, server_port) {
+            anyhow::bail!(
+                "The listen port and --server-port are both {}. MCServerNap must listen on the public port while the \
+                 Minecraft server uses another server-port internally, e.g. 25565 and 25566",
+                server_port
+            );
+        }
+
+        // The server's console is a pipe to this process, so the server can't outlive it
+        let keep_server_running = self.keep_server_running && !config.captures_server_output();
+        if self.keep_server_running && !keep_server_running {
+            log::warn!(
+                "--no-stop-on-exit has no effect with player_count_source = \"console\", readiness_mode = \"log\" or server_log_file"
+            );
+        }
+
+        // The idle watchdog would retry RCON for the whole rcon_connect_timeout after every launch
+        if config.player_count_source == PlayerCountSource::Rcon
+            && server_properties::rcon_disabled(&config.server_properties_path)
+        {
+            log::error!(
+                "RCON is disabled in {} (enable-rcon=false), but the idle watchdog needs it to stop the server \
+                 once it's empty. Set enable-rcon=true and an rcon.password, or use player_count_source = \"console\"",
+                config.server_properties_path
+            );
+        }
+
+        Ok(NapServer {
+            name: self.name,
+            listen_addr,
+            unix_socket: self.unix_socket,
+            cmd,
+            args,
+            server_port,
+            rcon_port,
+            rcon_pass,
+            config,
+            keep_server_running,
+            shutdown: Arc::new(Notify::new()),
+        })
+    }
+}
+
+impl NapServer {
+    pub fn builder() -> NapServerBuilder {
+        NapServerBuilder::default()
+    }
+
+    /// Handle to shut down [`Self::run`] from elsewhere, e.g. on Ctrl+C
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Listens for logins until shut down through a [`ShutdownHandle`]. A running Minecraft server is stopped
+    /// then (killed if it doesn't stop within `max_stop_duration`), unless `keep_server_running` is set
+    pub async fn run(self) -> Result<()> {
+        let NapServer {
+            name,
+            listen_addr,
+            unix_socket,
+            cmd,
+            args,
+            server_port,
+            rcon_port,
+            rcon_pass,
+            config: app_config,
+            keep_server_running,
+            shutdown,
+        } = self;
+
+        let rcon_addr = Arc::new(app_config.rcon_addr(rcon_port));
+        let rcon_pass = Arc::new(rcon_pass);
+
+        let server_state = Arc::new(Mutex::new(ServerState::Stopped));
+        let (listener, listen_description) = match &unix_socket {
+            Some(path) => (ClientListener::bind_unix(path)?, path.display().to_string()),
+            None => (
+                ClientListener::Tcp(
+                    bind_listener(listen_addr).map_err(|e| bind_error(e, listen_addr))?,
+                ),
+                listen_addr.to_string(),
+            ),
+        };
+
+        match &name {
+            Some(name) => log::info!("Listening for login to {} on {}", name, listen_description),
+            None => log::info!("Listening for login on {}", listen_description),
+        }
+
+        let summary = napping_summary(
+            &listen_description,
+            &app_config.server_addr(server_port),
+            &rcon_addr,
+            &app_config,
+        );
+        log::info!("{}", summary);
+        if let Some(url) = app_config.startup_webhook_url.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = send_webhook(&url, &summary).await {
+                    log::warn!("Failed to send startup webhook: {}", e);
+                }
+            });
+        }
+
+        // Tasks serving alongside the main loop, ended with it
+        let mut background_tasks = Vec::new();
+        let (control_tx, control_rx) = mpsc::channel(8);
+        if app_config.control_port != 0 {
+            let control_addr = control::control_addr(app_config.control_port);
+            match TcpListener::bind(&control_addr).await {
+                Ok(control_listener) => {
+                    log::info!("Control channel listening on {}", control_addr);
+                    background_tasks.push(tokio::spawn(control::serve_control(
+                        control_listener,
+                        control_tx,
+                    )));
+                }
+                Err(e) => {
+                    log::error!("Failed to bind control channel on {}: {}", control_addr, e)
+                }
+            }
+        }
+
+        if app_config.metrics_port != 0 {
+            let metrics_addr = SocketAddr::new(listen_addr.ip(), app_config.metrics_port);
+            match TcpListener::bind(metrics_addr).await {
+                Ok(metrics_listener) => {
+                    log::info!("Metrics available on http://{}/metrics", metrics_addr);
+                    background_tasks.push(tokio::spawn(serve_metrics(metrics_listener)));
+                }
+                Err(e) => log::error!("Failed to bind metrics endpoint on {}: {}", metrics_addr, e),
+            }
+        }
+
+        if app_config.handshake_stats_log_interval > 0 {
+            background_tasks.push(tokio::spawn(log_handshake_failures_periodically(
+                Duration::from_secs(app_config.handshake_stats_log_interval),
+            )));
+        }
+
+        // Clone handles for shutdown handler
+        let server_state_shutdown = server_state.clone();
+        let stop_requested = Arc::new(watch::Sender::new(false));
+        let stop_requested_shutdown = stop_requested.clone();
+        let max_stop_duration = app_config.max_stop_duration();
+        let shutdown_grace_period = Duration::from_secs(app_config.shutdown_grace_period);
+        let sessions = ProxySessions::new();
+        let state_file = persisted_state::state_file(app_config.config_dir(), name.as_deref());
+        let stats = Arc::new(StatsRecorder::open(stats::stats_file(
+            app_config.config_dir(),
+            name.as_deref(),
+        )));
+
+        let (config_tx, config_rx) = mpsc::channel(1);
+        if app_config.watch_config {
+            background_tasks.push(tokio::spawn(watch_config_file(
+                app_config.config_dir().to_string(),
+                name.clone(),
+                config_tx,
+            )));
+        }
+        let metrics = ServerMetrics::register(name, server_state.clone());
+
+        let result = tokio::select! {
+            result = main_loop(
+                listener,
+                cmd,
+                args,
+                server_port,
+                rcon_addr,
+                rcon_pass,
+                server_state,
+                app_config,
+                stop_requested,
+                control_rx,
+                config_rx,
+                state_file.clone(),
+                metrics,
+                stats.clone(),
+                sessions.clone()
+            ) => result,
+            _ = shutdown.notified() => {
+                // The main loop and its listener are gone, so no new connections are accepted
+                sessions.close(shutdown_grace_period).await;
+                // Check if server is running and send stop command
+                let state_guard = match tokio::time::timeout(Duration::from_secs(5), server_state_shutdown.lock()).await {
+                    Ok(guard) => guard,
+                    Err(_) => {
+                        log::error!("Deadlock detected! Failed to acquire state lock");
+                        // Panicking here since we can't safely proceed
+                        panic!("State lock timeout - possible deadlock");
+                    }
+                };
+
+                if *state_guard != ServerState::Stopped && keep_server_running {
+                    log::info!(
+                        "Leaving the Minecraft server running (--no-stop-on-exit), the next instance will adopt it"
+                    );
+                } else if *state_guard != ServerState::Stopped {
+                    log::info!("Stopping Minecraft server gracefully...");
+                    drop(state_guard); // Release Mutex lock so the server exit can be recorded
+
+                    // The task waiting for the server process performs the stop
+                    stop_requested_shutdown.send_replace(true);
+                    if !wait_until_server_stopped(&server_state_shutdown, max_stop_duration).await {
+                        log::error!("Server didn't stop within {:?}, killing it", max_stop_duration);
+                        // Don't leave the server running detached once this application exited
+                        if let Some(record) = persisted_state::load(&state_file) {
+                            match kill_process(record.pid).await {
+                                Ok(()) => {
+                                    if !wait_until_server_stopped(&server_state_shutdown, Duration::from_secs(5)).await {
+                                        log::error!("Server process {} is still running", record.pid);
+                                    }
+                                }
+                                Err(e) => log::error!("Failed to kill the server process: {}", e),
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        };
+
+        for task in background_tasks {
+            task.abort();
+        }
+        stats.flush();
+        if let Some(path) = &unix_socket
+            && let Err(e) = fs::remove_file(path)
+        {
+            log::warn!("Failed to remove {}: {}", path.display(), e);
+        }
+        result
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn main_loop(
+    listener: ClientListener,
+    cmd: String,
+    args: Vec<String>,
+    server_port: u16,
+    rcon_addr: Arc<String>,
+    rcon_pass: Arc<String>,
+    server_state: Arc<Mutex<ServerState>>,
+    app_config: Config,
+    stop_requested: Arc<watch::Sender<bool>>,
+    mut control_requests: mpsc::Receiver<ControlRequest>,
+    mut config_updates: mpsc::Receiver<Config>,
+    state_file: PathBuf,
+    metrics: Arc<ServerMetrics>,
+    stats: Arc<StatsRecorder>,
+    sessions: ProxySessions,
+) -> Result<()> {
+    let player_count_query = Arc::new(PlayerCountQuery::from_config(&app_config));
+    let player_counts = Arc::new(PlayerCounts::default());
+    // Replaced as a whole when the configuration is reloaded; tasks keep the ones they were started with
+    let mut packets =
+        Arc::new(PreserializedPackets::new(&app_config).with_player_counts(player_counts.clone()));
+    let mut app_config = Arc::new(app_config);
+    // Only a TCP listener is handed off to the server (`handoff_listener`)
+    let listen_addr = match &listener {
+        ClientListener::Tcp(listener) => listener.local_addr()?,
+        #[cfg(unix)]
+        ClientListener::Unix(_) => crate::client_stream::UNIX_PEER,
+    };
+    let listen_port = match &listener {
+        ClientListener::Tcp(_) => Some(listen_addr.port()),
+        #[cfg(unix)]
+        ClientListener::Unix(_) => None,
+    };
+    if app_config.cache_server_motd && !app_config.maintenance_mode {
+        tokio::spawn(cache_server_motd_periodically(
+            Arc::downgrade(&packets),
+            server_state.clone(),
+            app_config.clone(),
+            server_port,
+        ));
+    }
+    let mut listener = Some(listener);
+    let server_stopped = Arc::new(Notify::new());
+    let last_stop: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+    // Readiness of the current launch, used to forward connections held during startup
+    let mut ready_signal_receiver: Option<watch::Receiver<bool>> = None;
+    // Launch time of the current start, shown in the starting MOTD and reconnect message
+    let mut starting_since: Option<Instant> = None;
+    let mut launcher = Arc::new(ServerLauncher {
+        cmd,
+        args,
+        server_port,
+        rcon_addr,
+        rcon_pass,
+        server_state: server_state.clone(),
+        app_config: app_config.clone(),
+        player_count_query,
+        server_stopped: server_stopped.clone(),
+        last_stop: last_stop.clone(),
+        start_backoff: Arc::new(Mutex::new(StartBackoff::default())),
+        stop_requested,
+        state_file: state_file.clone(),
+        metrics,
+        stats,
+        player_counts,
+    });
+
+    // Take over a server left running by a previous instance (`--no-stop-on-exit`)
+    let mut adopted = false;
+    if let Some(record) = persisted_state::load(&state_file) {
+        if persisted_state::is_process_alive(record.pid).await {
+            adopted = true;
+            log::info!(
+                "Adopting the server left running by a previous instance (PID {})",
+                record.pid
+            );
+            if record.server_port != launcher.server_port {
+                log::warn!(
+                    "The adopted server was launched with server port {}, but {} is configured now",
+                    record.server_port,
+                    launcher.server_port
+                );
+            }
+            let (ready_signal_sender, ready_rx) = watch::channel(false);
+            ready_signal_receiver = Some(ready_rx);
+            *server_state.lock().await = ServerState::Starting;
+            starting_since = Some(Instant::now());
+            launcher.adopt(ServerProcess::Adopted(record.pid), ready_signal_sender);
+        } else {
+            log::info!(
+                "Recorded server process {} isn't running anymore",
+                record.pid
+            );
+            persisted_state::clear(&state_file);
+        }
+    }
+
+    // A server started by hand (or whose record got lost) must not be launched a second time
+    if !adopted && app_config.detect_running_server {
+        let server_addr = app_config.server_addr(server_port);
+        if is_port_open(&server_addr, DETECT_TIMEOUT).await
+            || is_port_open(&launcher.rcon_addr, DETECT_TIMEOUT).await
+        {
+            log::info!(
+                "A Minecraft server is already running at {}, taking it over",
+                server_addr
+            );
+            let (ready_signal_sender, ready_rx) = watch::channel(false);
+            ready_signal_receiver = Some(ready_rx);
+            *server_state.lock().await = ServerState::Starting;
+            starting_since = Some(Instant::now());
+            launcher.adopt(ServerProcess::Detected(server_addr), ready_signal_sender);
+        }
+    }
+
+    let mut rate_limiter = ConnectionRateLimiter::default();
+
+    // Logins read by the connection tasks. They are decided on here, so the state only changes in one place
+    let (login_tx, mut login_rx) = mpsc::channel::<PendingLogin>(64);
+    // Connections with the client address from their PROXY protocol header (`accept_proxy_protocol`)
+    let (proxied_tx, mut proxied_rx) = mpsc::channel::<(ClientStream, SocketAddr)>(64);
+
+    loop {
+        if listener.is_some() {
+            log::info!("Listening...");
+        }
+
+        // Accepted directly, or once its PROXY protocol header was read
+        let mut new_connection = None;
+        tokio::select! {
+            accepted = async { listener.as_ref().unwrap().accept().await }, if listener.is_some() => {
+                let (client_socket, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        log::error!("Failed to accept connection: {:?}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                if app_config.accept_proxy_protocol {
+                    // Read in the background like the handshake, so slow clients don't stall anything
+                    tokio::spawn(read_proxy_header_of(
+                        client_socket,
+                        peer,
+                        app_config.timeouts.handshake(),
+                        proxied_tx.clone(),
+                    ));
+                    continue;
+                }
+                new_connection = Some((client_socket, peer));
+            }
+            Some(proxied) = proxied_rx.recv() => {
+                new_connection = Some(proxied);
+            }
+            _ = server_stopped.notified(), if listener.is_none() => {
+                // Handoff mode: the server owned the port until it exited
+                listener = Some(ClientListener::Tcp(rebind_listener(listen_addr).await));
+                log::info!(
+                    "Server stopped, listening for login on {} again",
+                    listen_addr
+                );
+            }
+            Some(pending) = login_rx.recv() => {
+                let PendingLogin {
+                    socket: client_socket,
+                    peer,
+                    login,
+                    username,
+                    server_whitelisted,
+                } = pending;
+                let player = username.as_deref().unwrap_or("<unknown>");
+
+                // Scoped to hold the Mutex lock only while checking and possibly updating state.
+                // The state may have changed while the handshake was read, so it's checked again here
+                let mut state_guard =
+                    match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+                        Ok(guard) => guard,
+                        Err(_) => {
+                            log::error!("Deadlock detected! Failed to acquire state lock");
+                            panic!("State lock timeout - possible deadlock");
+                        }
+                    };
+
+                match *state_guard {
+                    ServerState::Stopped => {
+                        // Transfer the player to `transfer_redirect` instead of waking the server, also during maintenance
+                        if let Some(target) = &app_config.transfer_target {
+                            log::info!(
+                                "Redirecting player {} ({}) to {}",
+                                player,
+                                peer,
+                                target
+                            );
+                            let app_config = app_config.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = redirect_login(
+                                    client_socket,
+                                    &app_config,
+                                    &login,
+                                    username.as_deref(),
+                                )
+                                .await
+                                {
+                                    log::warn!("Failed to redirect {}: {:#}", peer, e);
+                                }
+                            });
+                            continue;
+                        }
+
+                        if app_config.maintenance_mode {
+                            log::info!(
+                                "Wake attempt by player {} ({}) refused, maintenance mode",
+                                player,
+                                peer
+                            );
+                            spawn_disconnect_message(
+                                client_socket,
+                                peer,
+                                app_config.clone(),
+                                app_config.maintenance_msg_text.clone(),
+                            );
+                            continue;
+                        }
+
+                        // Only approved players may wake the server
+                        if !app_config.is_whitelisted(username.as_deref()) {
+                            log::info!(
+                                "Wake attempt by player {} ({}) refused, not whitelisted",
+                                player,
+                                peer
+                            );
+                            spawn_disconnect_message(
+                                client_socket,
+                                peer,
+                                app_config.clone(),
+                                app_config.whitelist_msg_text.clone(),
+                            );
+                            continue;
+                        }
+
+                        // The server itself would kick the player right away
+                        if !server_whitelisted {
+                            log::info!(
+                                "Wake attempt by player {} ({}) refused, not on the server's whitelist",
+                                player,
+                                peer
+                            );
+                            spawn_disconnect_message(
+                                client_socket,
+                                peer,
+                                app_config.clone(),
+                                app_config.server_whitelist_msg_text.clone(),
+                            );
+                            continue;
+                        }
+
+                        // No wakes during quiet hours, the control channel may still start the server
+                        if let Some(window) = schedule::active_window(&app_config.quiet_hours) {
+                            log::info!(
+                                "Wake attempt by player {} ({}) refused, quiet hours until {}",
+                                player,
+                                peer,
+                                window.end.format("%H:%M")
+                            );
+                            let msg = app_config
+                                .quiet_hours_msg_text
+                                .replace("{until}", &window.end.format("%H:%M").to_string());
+                            spawn_disconnect_message(client_socket, peer, app_config.clone(), msg);
+                            continue;
+                        }
+
+                        // Refuse to wake right after the server stopped
+                        let cooldown = Duration::from_secs(app_config.wake_cooldown);
+                        let remaining = last_stop
+                            .lock()
+                            .await
+                            .map(|stopped_at| cooldown.saturating_sub(stopped_at.elapsed()))
+                            .unwrap_or_default();
+                        if !remaining.is_zero() {
+                            log::info!(
+                                "Wake attempt by player {} ({}) refused, cooldown ends in {:?}",
+                                player,
+                                peer,
+                                remaining
+                            );
+                            let msg = app_config.cooldown_msg_text.replace(
+                                "{remaining}",
+                                &remaining.as_secs_f64().ceil().to_string(),
+                            );
+                            spawn_disconnect_message(client_socket, peer, app_config.clone(), msg);
+                            continue;
+                        }
+
+                        // Back off after failed starts, so a server crashing on boot isn't relaunched in a loop
+                        let remaining = launcher.start_backoff.lock().await.remaining(
+                            Duration::from_secs(app_config.start_backoff_base),
+                            Duration::from_secs(app_config.start_backoff_max),
+                        );
+                        if !remaining.is_zero() {
+                            log::info!(
+                                "Wake attempt by player {} ({}) refused, the last start failed. Retrying in {:?}",
+                                player,
+                                peer,
+                                remaining
+                            );
+                            let msg = app_config.start_failed_msg_text.replace(
+                                "{remaining}",
+                                &remaining.as_secs_f64().ceil().to_string(),
+                            );
+                            spawn_disconnect_message(client_socket, peer, app_config.clone(), msg);
+                            continue;
+                        }
+
+                        log::info!(
+                            event = "wake",
+                            player,
+                            peer:%,
+                            host = login.host.as_str();
+                            "Server woken by player {} ({}) via {}",
+                            player,
+                            peer,
+                            login.host
+                        );
+                        // The player reconnects once the server is up, which mustn't be held against them
+                        rate_limiter.forget(peer.ip());
+
+                        let (ready_signal_sender, ready_rx) = watch::channel(false);
+                        ready_signal_receiver = Some(ready_rx.clone());
+
+                        if app_config.hold_connections_during_start {
+                            tokio::spawn(hold_until_ready(
+                                client_socket,
+                                peer,
+                                login.bytes,
+                                ready_rx,
+                                server_port,
+                                app_config.clone(),
+                                packets.clone(),
+                                sessions.clone(),
+                                server_state.clone(),
+                                launcher.stop_requested.clone(),
+                            ));
+                        } else {
+                            let app_config = app_config.clone();
+                            let packets = packets.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = send_starting_message(
+                                    client_socket,
+                                    &app_config,
+                                    &packets,
+                                )
+                                .await
+                                {
+                                    log::warn!("Failed to notify {}: {}", peer, e);
+                                }
+                            });
+                        }
+
+                        // Transition to starting state
+                        *state_guard = ServerState::Starting;
+                        starting_since = Some(Instant::now());
+                        log::debug!("Server state set to Starting in main()");
+
+                        if app_config.handoff_listener {
+                            // Release the port so the server can bind it directly
+                            listener = None;
+                            log::info!("Handing off {} to the Minecraft server", listen_addr);
+                        }
+
+                        launcher.launch(ready_signal_sender, player);
+                    }
+                    ServerState::Starting => {
+                        // Keep notifying the player client that the server is starting
+                        log::info!(
+                            "Player {} ({}) joined while the server is starting",
+                            player,
+                            peer
+                        );
+
+                        if app_config.hold_connections_during_start
+                            && let Some(ready_rx) = &ready_signal_receiver
+                        {
+                            tokio::spawn(hold_until_ready(
+                                client_socket,
+                                peer,
+                                login.bytes,
+                                ready_rx.clone(),
+                                server_port,
+                                app_config.clone(),
+                                packets.clone(),
+                                sessions.clone(),
+                                server_state.clone(),
+                                launcher.stop_requested.clone(),
+                            ));
+                        } else {
+                            let app_config = app_config.clone();
+                            let packets = packets.clone();
+                            let elapsed = starting_since
+                                .map(|since| since.elapsed())
+                                .unwrap_or_default();
+                            tokio::spawn(async move {
+                                if let Err(e) =
+                                    send_reconnect_message(client_socket, &app_config, &packets, elapsed)
+                                        .await
+                                {
+                                    log::warn!(
+                                        "Failed to notify {} while starting server: {}",
+                                        peer,
+                                        e
+                                    );
+                                }
+                            });
+                        }
+                    }
+                    ServerState::Stopping => {
+                        log::info!(
+                            "Player {} ({}) joined while the server is shutting down",
+                            player,
+                            peer
+                        );
+                        spawn_disconnect_message(
+                            client_socket,
+                            peer,
+                            app_config.clone(),
+                            app_config.stopping_msg_text.clone(),
+                        );
+                    }
+                    ServerState::Running => {
+                        // Became ready while the handshake was read
+                        log::info!(event = "proxy", peer:%; "Proxying connection for {}", peer);
+                        tokio::spawn(proxy_to_backend(
+                            client_socket,
+                            peer,
+                            login.bytes,
+                            server_port,
+                            app_config.clone(),
+                            packets.clone(),
+                            sessions.clone(),
+                            server_state.clone(),
+                            launcher.stop_requested.clone(),
+                        ));
+                    }
+                }
+            }
+            Some(request) = control_requests.recv() => {
+                if let ControlCommand::Maintenance(enabled) = request.command {
+                    let mut config = (*app_config).clone();
+                    config.maintenance_mode = enabled;
+                    apply_config(
+                        config,
+                        &mut app_config,
+                        &mut packets,
+                        &mut launcher,
+                        &server_state,
+                        server_port,
+                    );
+                    log::info!(
+                        "Maintenance mode turned {} via the control channel",
+                        if enabled { "on" } else { "off" }
+                    );
+                    let _ = request
+                        .reply
+                        .send(if enabled { "maintenance on" } else { "maintenance off" }.to_string());
+                    continue;
+                }
+                if request.command == ControlCommand::Stop {
+                    tokio::spawn(handle_stop_request(
+                        request.reply,
+                        server_state.clone(),
+                        launcher.stop_requested.clone(),
+                        app_config.max_stop_duration(),
+                    ));
+                    continue;
+                }
+                let mut state_guard =
+                    match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+                        Ok(guard) => guard,
+                        Err(_) => {
+                            log::error!("Deadlock detected! Failed to acquire state lock");
+                            panic!("State lock timeout - possible deadlock");
+                        }
+                    };
+                let reply = match *state_guard {
+                    ServerState::Running => "already running".to_string(),
+                    ServerState::Starting => "already starting".to_string(),
+                    ServerState::Stopping => "error: server is shutting down".to_string(),
+                    ServerState::Stopped => {
+                        log::info!("Server woken via the control channel");
+                        let (ready_signal_sender, ready_rx) = watch::channel(false);
+                        ready_signal_receiver = Some(ready_rx);
+
+                        *state_guard = ServerState::Starting;
+                        starting_since = Some(Instant::now());
+                        if app_config.handoff_listener {
+                            listener = None;
+                            log::info!("Handing off {} to the Minecraft server", listen_addr);
+                        }
+
+                        launcher.launch(ready_signal_sender, "control channel");
+                        "woken".to_string()
+                    }
+                };
+                let _ = request.reply.send(reply);
+            }
+            Some(reloaded) = config_updates.recv() => {
+                if proxies_to_itself(listen_port, &reloaded, server_port) {
+                    log::error!(
+                        "Ignoring the reloaded configuration: server_host {} with server-port {} is this listener \
+                         itself, keeping the previous one",
+                        reloaded.server_host,
+                        server_port
+                    );
+                    continue;
+                }
+                apply_config(
+                    reloaded,
+                    &mut app_config,
+                    &mut packets,
+                    &mut launcher,
+                    &server_state,
+                    server_port,
+                );
+                log::info!("Configuration reloaded");
+            }
+        }
+
+        let Some((client_socket, peer)) = new_connection else {
+            continue;
+        };
+        if !app_config.is_ip_allowed(peer.ip()) {
+            log::debug!(
+                "Dropped connection from {}, not allowed by allow_cidrs/deny_cidrs",
+                peer
+            );
+            continue;
+        }
+        let state = match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+            Ok(guard) => *guard,
+            Err(_) => {
+                log::error!("Deadlock detected! Failed to acquire state lock");
+                panic!("State lock timeout - possible deadlock");
+            }
+        };
+        // Only connections that could wake the server are limited, players of a running server never are
+        if state != ServerState::Running
+            && !rate_limiter.allow(peer.ip(), app_config.max_connections_per_minute)
+        {
+            log::debug!(
+                "Dropped connection from {}, too many connections per minute",
+                peer
+            );
+            continue;
+        }
+        client_socket.set_nodelay(true)?;
+        log::info!("Incoming connection from {}", peer);
+        if state == ServerState::Running && proxies_to_itself(listen_port, &app_config, server_port)
+        {
+            // Checked on startup and reload already, connecting would accept the connection again and again
+            log::error!(
+                "Refusing to proxy {} to {}, that's this listener itself",
+                peer,
+                app_config.server_addr(server_port)
+            );
+            continue;
+        }
+        if state == ServerState::Running {
+            // Server is running: proxy connection to actual Minecraft server. Nothing has been read from the
+            // socket yet (the PROXY protocol header aside), so the client's handshake reaches the server untouched
+            log::info!(event = "proxy", peer:%; "Proxying connection for {}", peer);
+            tokio::spawn(proxy_to_backend(
+                client_socket,
+                peer,
+                Vec::new(),
+                server_port,
+                app_config.clone(),
+                packets.clone(),
+                sessions.clone(),
+                server_state.clone(),
+                launcher.stop_requested.clone(),
+            ));
+        } else {
+            // The handshake is read without holding the state lock, so slow clients don't stall anything
+            tokio::spawn(read_login(
+                client_socket,
+                peer,
+                app_config.clone(),
+                packets.clone(),
+                starting_since.filter(|_| state == ServerState::Starting),
+                login_tx.clone(),
+            ));
+        }
+    }
+}
+
+/// Replaces the configuration of the main loop, e.g. after `cfg.toml` was reloaded. A running server keeps the
+/// settings it was launched with, e.g. its idle timeout
+fn apply_config(
+    config: Config,
+    app_config: &mut Arc<Config>,
+    packets: &mut Arc<PreserializedPackets>,
+    launcher: &mut Arc<ServerLauncher>,
+    server_state: &Arc<Mutex<ServerState>>,
+    server_port: u16,
+) {
+    let config = Arc::new(config);
+    let new_packets = Arc::new(
+        PreserializedPackets::new(&config).with_player_counts(launcher.player_counts.clone()),
+    );
+    // The server's own MOTD would hide the maintenance MOTD
+    if config.cache_server_motd && !config.maintenance_mode {
+        new_packets.keep_cached_motd(packets);
+        tokio::spawn(cache_server_motd_periodically(
+            Arc::downgrade(&new_packets),
+            server_state.clone(),
+            config.clone(),
+            server_port,
+        ));
+    }
+    *launcher = Arc::new(ServerLauncher {
+        app_config: config.clone(),
+        player_count_query: Arc::new(PlayerCountQuery::from_config(&config)),
+        ..(**launcher).clone()
+    });
+    *packets = new_packets;
+    *app_config = config;
+}
+
+/// Whether proxying to the server would connect back to the TCP listener on `listen_port`: the server on this
+/// host and the listener share the port. With `handoff_listener` they must, the server takes over the port
+fn proxies_to_itself(listen_port: Option<u16>, config: &Config, server_port: u16) -> bool {
+    !config.handoff_listener
+        && listen_port == Some(server_port)
+        && is_local_host(&config.server_host)
+}
+
+/// Whether `host` (`server_host`) is this machine
+fn is_local_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip.is_unspecified())
+}
+
+/// Explains the usual causes of a listen port that can't be bound
+fn bind_error(e: std::io::Error, listen_addr: SocketAddr) -> anyhow::Error {
+    match e.kind() {
+        ErrorKind::AddrInUse => anyhow::anyhow!(
+            "Port {} is already in use ({}). Is the Minecraft server (or another MCServerNap instance) running on \
+             it? MCServerNap must listen on the public port while the Minecraft server uses its server-port \
+             internally, they can't be the same",
+            listen_addr.port(),
+            e
+        ),
+        ErrorKind::PermissionDenied => anyhow::anyhow!(
+            "Not allowed to listen on {} ({}). Ports below 1024 need elevated privileges on most systems",
+            listen_addr,
+            e
+        ),
+        _ => anyhow::Error::new(e).context(format!("Cannot listen on {}", listen_addr)),
+    }
+}
+
+/// Proxied player sessions, so a shutdown can wait for them to end (`shutdown_grace_period`) and close the rest
+#[derive(Clone)]
+struct ProxySessions {
+    active: Arc<watch::Sender<usize>>,
+    closing: Arc<watch::Sender<bool>>,
+}
+
+/// Counts a session as active until dropped, see [`ProxySessions::track`]
+struct SessionGuard(Arc<watch::Sender<usize>>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.send_modify(|active| *active -= 1);
+    }
+}
+
+impl ProxySessions {
+    fn new() -> Self {
+        ProxySessions {
+            active: Arc::new(watch::Sender::new(0)),
+            closing: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    fn track(&self) -> SessionGuard {
+        self.active.send_modify(|active| *active += 1);
+        SessionGuard(self.active.clone())
+    }
+
+    /// Set once the remaining sessions should be closed
+    fn closing(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    /// Waits up to `grace_period` for the active sessions to end, then closes the remaining ones
+    async fn close(&self, grace_period: Duration) {
+        let mut active = self.active.subscribe();
+        let count = *active.borrow();
+        if count == 0 {
+            return;
+        }
+        log::info!(
+            "Waiting up to {:?} for {} proxied session(s) to end",
+            grace_period,
+            count
+        );
+        if tokio::time::timeout(grace_period, active.wait_for(|active| *active == 0))
+            .await
+            .is_err()
+        {
+            log::info!("Closing {} remaining proxied session(s)", *active.borrow());
+            self.closing.send_replace(true);
+            // Closing only takes a moment, unless a client doesn't acknowledge it
+            let _ = tokio::time::timeout(
+                Duration::from_secs(1),
+                active.wait_for(|active| *active == 0),
+            )
+            .await;
+        }
+    }
+}
+
+/// Reads the PROXY protocol header of a new connection and passes the connection on to the main loop with the
+/// client's address in place of the proxy's. Connections without a valid header are dropped
+async fn read_proxy_header_of(
+    mut client_socket: ClientStream,
+    proxy_peer: SocketAddr,
+    max_duration: Duration,
+    connections: mpsc::Sender<(ClientStream, SocketAddr)>,
+) {
+    let peer = match tokio::time::timeout(max_duration, read_proxy_header(&mut client_socket)).await
+    {
+        Ok(Ok(client)) => client.unwrap_or(proxy_peer),
+        Ok(Err(e)) => {
+            log::debug!("Connection from {} dropped: {:#}", proxy_peer, e);
+            return;
+        }
+        Err(_) => {
+            log::debug!(
+                "Connection from {} dropped: no PROXY protocol header in time",
+                proxy_peer
+            );
+            return;
+        }
+    };
+    if connections.send((client_socket, peer)).await.is_err() {
+        log::debug!("Connection from {} dropped, main loop has ended", peer);
+    }
+}
+
+/// A login read by [`read_login`], waiting for the main loop to decide what happens to it
+struct PendingLogin {
+    socket: ClientStream,
+    peer: SocketAddr,
+    login: LoginHandshake,
+    username: Option<String>,
+    /// Whether the server's own whitelist lets the player in, see [`server_whitelist::allows`]
+    server_whitelisted: bool,
+}
+
+/// Reads the handshake and Login Start of a new connection and passes logins on to the main loop.
+/// Status pings are answered right here
+async fn read_login(
+    mut client_socket: ClientStream,
+    peer: SocketAddr,
+    app_config: Arc<Config>,
+    packets: Arc<PreserializedPackets>,
+    starting_since: Option<Instant>,
+    logins: mpsc::Sender<PendingLogin>,
+) {
+    match verify_handshake_packet_buffered(
+        &mut client_socket,
+        peer,
+        &app_config,
+        &packets,
+        starting_since,
+    )
+    .await
+    {
+        Ok(Some(mut login)) => {
+            let username = read_login_username(
+                &mut client_socket,
+                &mut login.bytes,
+                app_config.timeouts.handshake(),
+            )
+            .await
+            .unwrap_or(None);
+            // Reads the server's files, so it's done here on the blocking pool instead of in the main loop
+            let server_whitelisted = {
+                let app_config = app_config.clone();
+                let username = username.clone();
+                tokio::task::spawn_blocking(move || {
+                    server_whitelist::allows(&app_config, username.as_deref())
+                })
+                .await
+                .unwrap_or(true)
+            };
+            let pending = PendingLogin {
+                socket: client_socket,
+                peer,
+                login,
+                username,
+                server_whitelisted,
+            };
+            if logins.send(pending).await.is_err() {
+                log::debug!("Login from {} dropped, main loop has ended", peer);
+            }
+        }
+        // Not a login handshake or not handled, just drop the socket
+        Ok(None) | Err(_) => log::debug!(
+            "Connection from {} ignored (not login handshake or not handled)",
+            peer
+        ),
+    }
+}
+
+/// Sends a disconnect message in the background, so the main loop doesn't wait on the client
+fn spawn_disconnect_message(
+    client_socket: ClientStream,
+    peer: SocketAddr,
+    app_config: Arc<Config>,
+    text: String,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = send_disconnect_message(client_socket, &app_config, &text).await {
+            log::warn!("Failed to notify {}: {}", peer, e);
+        }
+    });
+}
+
+/// Everything needed to launch the Minecraft server along with its idle watchdog
+#[derive(Clone)]
+struct ServerLauncher {
+    cmd: String,
+    args: Vec<String>,
+    server_port: u16,
+    rcon_addr: Arc<String>,
+    rcon_pass: Arc<String>,
+    server_state: Arc<Mutex<ServerState>>,
+    app_config: Arc<Config>,
+    player_count_query: Arc<PlayerCountQuery>,
+    server_stopped: Arc<Notify>,
+    last_stop: Arc<Mutex<Option<Instant>>>,
+    start_backoff: Arc<Mutex<StartBackoff>>,
+    /// Set to stop the current server, reset on every launch
+    stop_requested: Arc<watch::Sender<bool>>,
+    /// Records the launched server, see [`persisted_state`]
+    state_file: PathBuf,
+    metrics: Arc<ServerMetrics>,
+    /// Lifetime statistics persisted in the config directory, see [`crate::stats`]
+    stats: Arc<StatsRecorder>,
+    /// Reported by the idle watchdog and shown in the MOTD
+    player_counts: Arc<PlayerCounts>,
+}
+
+impl ServerLauncher {
+    /// Runs the `pre_start_hook` and launches the server in the background, so connections are still accepted
+    /// meanwhile. The caller sets the state to Starting; it's set back to Stopped once the server exited or
+    /// if it can't be launched. `woken_by` is named in the wake notification
+    fn launch(self: &Arc<Self>, ready_signal_sender: watch::Sender<bool>, woken_by: &str) {
+        let launcher = self.clone();
+        let woken_by = woken_by.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = launcher.power_on().await {
+                log::error!("{:#}", e);
+                launcher.launch_failed().await;
+                return;
+            }
+
+            if let Some(hook) = &launcher.app_config.pre_start_hook {
+                let hook_timeout = Duration::from_secs(launcher.app_config.hook_timeout);
+                if let Err(e) = run_hook("pre-start hook", hook, hook_timeout).await {
+                    log::error!("{:#}", e);
+                }
+            }
+
+            if let Err(e) = launcher.start(ready_signal_sender) {
+                log::error!("Failed to launch server: {:#}", e);
+                launcher.launch_failed().await;
+                return;
+            }
+            launcher.metrics.record_wake();
+            launcher.stats.record_wake();
+            notify::notify_wake(&launcher.app_config, &woken_by);
+        });
+    }
+
+    /// Sends the Wake-on-LAN packet if `wol_mac` is set and waits for the machine to boot (`wol_wait_port`)
+    async fn power_on(&self) -> Result<()> {
+        let app_config = &self.app_config;
+        let Some(mac) = &app_config.wol_mac else {
+            return Ok(());
+        };
+        wol::send_magic_packet(mac, &app_config.wol_broadcast_addr).await?;
+        if app_config.wol_wait_port != 0 {
+            let addr = app_config.server_addr(app_config.wol_wait_port);
+            log::info!("Waiting for {} to boot", addr);
+            wol::wait_until_reachable(&addr, Duration::from_secs(app_config.wol_boot_timeout))
+                .await
+                .context("The woken machine didn't boot")?;
+        }
+        Ok(())
+    }
+
+    /// Counts a launch that failed before the server process existed and sets the state back to Stopped
+    async fn launch_failed(&self) {
+        self.start_backoff.lock().await.record_failure();
+        {
+            let mut state = match tokio::time::timeout(
+                Duration::from_secs(5),
+                self.server_state.lock(),
+            )
+            .await
+            {
+                Ok(guard) => guard,
+                Err(_) => {
+                    log::error!("Deadlock detected! Failed to acquire state lock");
+                    panic!("State lock timeout - possible deadlock");
+                }
+            };
+            *state = ServerState::Stopped;
+        }
+        log::debug!("Server state set to Stopped after failed launch");
+        if self.app_config.handoff_listener {
+            // Take the handed off port back
+            self.server_stopped.notify_one();
+        }
+    }
+
+    /// Launches the server and spawns the idle watchdog and the task waiting for the server to exit
+    fn start(&self, ready_signal_sender: watch::Sender<bool>) -> Result<()> {
+        let app_config = &self.app_config;
+        let arg_slices: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        let capture_stdio = app_config.captures_server_output();
+        let mut child = launch_server(
+            &self.cmd,
+            &arg_slices,
+            capture_stdio,
+            app_config.server_window,
+            app_config.server_working_dir.as_deref(),
+        )?;
+        let console = if capture_stdio {
+            let log = app_config.server_log_file.as_ref().and_then(|path| {
+                ServerLog::open(
+                    Path::new(path),
+                    app_config.server_log_max_size_mb * 1024 * 1024,
+                    app_config.server_log_files,
+                )
+                .inspect_err(|e| log::warn!("Not writing the server log: {:#}", e))
+                .ok()
+            });
+            ServerConsole::attach(&mut child, log)
+        } else {
+            None
+        };
+
+        // Recorded so an instance started after this one exits (see `--no-stop-on-exit`) can adopt the server
+        if let Some(pid) = child.id() {
+            let record = PersistedServer {
+                pid,
+                server_port: self.server_port,
+            };
+            if let Err(e) = persisted_state::save(&self.state_file, &record) {
+                log::warn!("Failed to record the server process: {:#}", e);
+            }
+        }
+
+        self.supervise(ServerProcess::Child(child), console, ready_signal_sender);
+        Ok(())
+    }
+
+    /// Takes over a server this instance didn't launch, see [`Self::launch`]. The caller sets the state
+    /// to Starting; the idle watchdog switches it to Running once RCON is reachable
+    fn adopt(&self, process: ServerProcess, ready_signal_sender: watch::Sender<bool>) {
+        self.metrics.mark_awake();
+        self.stats.mark_awake();
+        self.supervise(process, None, ready_signal_sender);
+    }
+
+    fn supervise(
+        &self,
+        process: ServerProcess,
+        console: Option<Arc<ServerConsole>>,
+        ready_signal_sender: watch::Sender<bool>,
+    ) {
+        let app_config = &self.app_config;
+        let rcon_addr_clone = self.rcon_addr.clone();
+        let rcon_pass_clone = self.rcon_pass.clone();
+        let player_count_query_clone = self.player_count_query.clone();
+        let server_state_for_rcon_watchdog = self.server_state.clone();
+        let watchdog_start_delay = Duration::from_secs(app_config.watchdog_start_delay);
+        let poll_interval = Duration::from_secs(app_config.rcon_poll_interval);
+        let idle_timer = IdleTimer::from_config(app_config)
+            .with_metrics(self.metrics.clone())
+            .with_player_counts(self.player_counts.clone());
+        let rcon_retry = RconRetry::from_config(app_config)
+            .with_server_addr(app_config.server_addr(self.server_port));
+        let startup_timeout = Duration::from_secs(app_config.startup_timeout);
+        let ready = ready_signal_sender.subscribe();
+        let became_ready = ready_signal_sender.subscribe();
+        let mut stop_strategy = StopStrategy::from_config(
+            app_config,
+            &self.rcon_addr,
+            &self.rcon_pass,
+            Some(app_config.server_addr(self.server_port)),
+        );
+        // Subscribed right away, so the Done line can't be missed
+        let done_lines = console
+            .as_ref()
+            .filter(|_| app_config.readiness_mode == ReadinessMode::Log)
+            .map(|console| console.subscribe());
+        let ready_timeout = rcon_retry.connect_timeout;
+        // The console only serves as the player count source (and for stopping) if configured so
+        let console =
+            console.filter(|_| app_config.player_count_source == PlayerCountSource::Console);
+        stop_strategy.console = console.clone();
+        // Resolves to the reason once the server is to be stopped (idle or scheduled)
+        let rcon_watchdog_handle = tokio::spawn(async move {
+            if let Some(lines) = done_lines {
+                match wait_for_done_line(lines, ready_timeout).await {
+                    Ok(()) => {
+                        log::info!(event = "ready"; "Server reported being ready");
+                        {
+                            let mut state = match tokio::time::timeout(
+                                Duration::from_secs(5),
+                                server_state_for_rcon_watchdog.lock(),
+                            )
+                            .await
+                            {
+                                Ok(guard) => guard,
+                                Err(_) => {
+                                    log::error!("Deadlock detected! Failed to acquire state lock");
+                                    panic!("State lock timeout - possible deadlock");
+                                }
+                            };
+                            if *state == ServerState::Starting {
+                                *state = ServerState::Running;
+                                log::debug!("Server state set to Running after the Done line");
+                            }
+                        }
+                        let _ = ready_signal_sender.send(true);
+                    }
+                    Err(e) => log::warn!(
+                        "Server didn't report being ready ({}), waiting for the idle watchdog instead",
+                        e
+                    ),
+                }
+            }
+
+            // Give the server a head start before polling RCON
+            if !watchdog_start_delay.is_zero() {
+                log::info!(
+                    "Delaying RCON idle watchdog start by {:?}",
+                    watchdog_start_delay
+                );
+                tokio::time::sleep(watchdog_start_delay).await;
+            }
+
+            let result = match console {
+                Some(console) => {
+                    idle_watchdog_stdio(
+                        console,
+                        poll_interval,
+                        idle_timer,
+                        &player_count_query_clone,
+                        server_state_for_rcon_watchdog,
+                        ready_signal_sender,
+                    )
+                    .await
+                }
+                None => {
+                    idle_watchdog_rcon(
+                        &rcon_addr_clone,
+                        &rcon_pass_clone,
+                        rcon_retry,
+                        poll_interval,
+                        idle_timer,
+                        &player_count_query_clone,
+                        server_state_for_rcon_watchdog,
+                        ready_signal_sender,
+                    )
+                    .await
+                }
+            };
+            if let Err(e) = &result {
+                log::error!("Idle watchdog error: {}", e);
+            }
+            result.ok()
+        });
+
+        let server_state_for_server_exit = self.server_state.clone();
+        let server_stopped = self.server_stopped.clone();
+        let last_stop = self.last_stop.clone();
+        let start_backoff = self.start_backoff.clone();
+        let state_file = self.state_file.clone();
+        let post_stop_hook = app_config.post_stop_hook.clone();
+        let hook_timeout = Duration::from_secs(app_config.hook_timeout);
+        let app_config_for_server_exit = app_config.clone();
+        let metrics = self.metrics.clone();
+        let stats = self.stats.clone();
+        let player_counts = self.player_counts.clone();
+        // A stop requested for an earlier launch must not stop this one
+        self.stop_requested.send_replace(false);
+        let stop_requested = self.stop_requested.subscribe();
+        tokio::spawn(async move {
+            let reason = await_server_exit(
+                process,
+                rcon_watchdog_handle,
+                stop_requested,
+                stop_strategy,
+                &server_state_for_server_exit,
+                ready,
+                startup_timeout,
+            )
+            .await;
+            persisted_state::clear(&state_file);
+            player_counts.server_stopped();
+
+            let started = *became_ready.borrow();
+            if started {
+                start_backoff.lock().await.record_success();
+            } else if matches!(reason, StopReason::Exited | StopReason::StartupTimedOut) {
+                let failures = start_backoff.lock().await.record_failure();
+                log::warn!(
+                    "Server stopped before it was ready ({} failed start(s) in a row)",
+                    failures
+                );
+            }
+
+            // Stopping while the hook runs, so nobody wakes the server in the middle of it
+            if let Some(hook) = &post_stop_hook {
+                {
+                    let mut state = match tokio::time::timeout(
+                        Duration::from_secs(5),
+                        server_state_for_server_exit.lock(),
+                    )
+                    .await
+                    {
+                        Ok(guard) => guard,
+                        Err(_) => {
+                            log::error!("Deadlock detected! Failed to acquire state lock");
+                            panic!("State lock timeout - possible deadlock");
+                        }
+                    };
+                    *state = ServerState::Stopping;
+                }
+                if let Err(e) = run_hook("post-stop hook", hook, hook_timeout).await {
+                    log::error!("{:#}", e);
+                }
+            }
+
+            {
+                let mut state = match tokio::time::timeout(
+                    Duration::from_secs(5),
+                    server_state_for_server_exit.lock(),
+                )
+                .await
+                {
+                    Ok(guard) => guard,
+                    Err(_) => {
+                        log::error!("Deadlock detected! Failed to acquire state lock");
+                        panic!("State lock timeout - possible deadlock");
+                    }
+                };
+                *state = ServerState::Stopped;
+            }
+            log::debug!("Server state set to Stopped after server exit in main()");
+            log::info!(event = "stop", reason = reason.as_str(); "Server stopped.");
+            metrics.record_stop(reason);
+            stats.record_stop();
+            notify::notify_stop(&app_config_for_server_exit, reason);
+            *last_stop.lock().await = Some(Instant::now());
+            server_stopped.notify_one();
+        });
+    }
+}
+
+/// The server process, either launched by this instance, adopted from a previous one or found running on startup
+enum ServerProcess {
+    Child(tokio::process::Child),
+    Adopted(u32),
+    /// A server of unknown process (`detect_running_server`), only known by its address. It can only be stopped
+    /// via RCON or `stop_command_shell` and counts as exited once its port closes
+    Detected(String),
+}
+
+impl ServerProcess {
+    /// Waits for the server process to exit
+    async fn wait(&mut self) {
+        match self {
+            ServerProcess::Child(child) => {
+                if let Err(e) = child.wait().await {
+                    log::error!("Failed to wait for server exit: {:?}", e);
+                }
+            }
+            ServerProcess::Adopted(pid) => {
+                while persisted_state::is_process_alive(*pid).await {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            ServerProcess::Detected(server_addr) => {
+                while is_port_open(server_addr, DETECT_TIMEOUT).await {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Answers a `stop` sent through the control channel once the server stopped. The stop itself is performed
+/// by the task waiting for the server process, so it never runs twice when the server is also idle
+async fn handle_stop_request(
+    reply: oneshot::Sender<String>,
+    server_state: Arc<Mutex<ServerState>>,
+    stop_requested: Arc<watch::Sender<bool>>,
+    max_stop_duration: Duration,
+) {
+    let state = match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+        Ok(guard) => *guard,
+        Err(_) => {
+            log::error!("Deadlock detected! Failed to acquire state lock");
+            panic!("State lock timeout - possible deadlock");
+        }
+    };
+
+    let reply_text = if state == ServerState::Stopped {
+        "not running".to_string()
+    } else {
+        log::info!("Stop requested via the control channel");
+        stop_requested.send_replace(true);
+        if wait_until_server_stopped(&server_state, max_stop_duration).await {
+            "stopped".to_string()
+        } else {
+            format!("error: server didn't stop within {:?}", max_stop_duration)
+        }
+    };
+    let _ = reply.send(reply_text);
+}
+
+/// Waits until the server state is Stopped. Returns false if that takes longer than `max_duration` (zero waits indefinitely)
+async fn wait_until_server_stopped(
+    server_state: &Mutex<ServerState>,
+    max_duration: Duration,
+) -> bool {
+    let wait_for_stop = async {
+        while *server_state.lock().await != ServerState::Stopped {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    };
+    if max_duration.is_zero() {
+        wait_for_stop.await;
+        return true;
+    }
+    tokio::time::timeout(max_duration, wait_for_stop)
+        .await
+        .is_ok()
+}
+
+/// Waits for the server process to exit. The server is stopped with `stop_strategy` once the idle watchdog
+/// reports it idle or a stop is requested. A stop requested while the server is already stopping is merged into it.
+/// The state is Stopping from then on until the server exited.
+/// A server that isn't `ready` within `startup_timeout` is killed right away
+#[allow(clippy::too_many_arguments)]
+async fn await_server_exit(
+    mut process: ServerProcess,
+    mut watchdog_handle: JoinHandle<Option<StopReason>>,
+    mut stop_requested: watch::Receiver<bool>,
+    stop_strategy: StopStrategy,
+    server_state: &Mutex<ServerState>,
+    ready: watch::Receiver<bool>,
+    startup_timeout: Duration,
+) -> StopReason {
+    let stop = tokio::select! {
+        _ = process.wait() => {
+            watchdog_handle.abort();
+            log::info!("RCON watchdog aborted");
+            return StopReason::Exited;
+        }
+        _ = startup_timed_out(ready, startup_timeout) => {
+            watchdog_handle.abort();
+            Some(StopReason::StartupTimedOut)
+        }
+        reason = &mut watchdog_handle => reason.ok().flatten(),
+        Ok(_) = stop_requested.wait_for(|stop| *stop) => {
+            log::info!("Stop requested, cancelling the idle watchdog");
+            watchdog_handle.abort();
+            Some(StopReason::Requested)
+        }
+    };
+
+    if stop == Some(StopReason::StartupTimedOut) {
+        log::error!(
+            "Server didn't become ready within {:?}, killing it",
+            startup_timeout
+        );
+        let killed = match &mut process {
+            ServerProcess::Child(child) => kill_server_process(child).await,
+            ServerProcess::Adopted(pid) => kill_process_and_wait(*pid).await,
+            ServerProcess::Detected(_) => Err(anyhow::anyhow!(
+                "the process of a detected server is unknown"
+            )),
+        };
+        // It can't be started again while the old one may still hold the port
+        if let Err(e) = killed {
+            log::error!(
+                "Failed to kill the server process: {}. Waiting for it to exit before it can be started again",
+                e
+            );
+        }
+        process.wait().await;
+        return StopReason::StartupTimedOut;
+    }
+
+    if let Some(reason) = stop {
+        {
+            let mut state =
+                match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+                    Ok(guard) => guard,
+                    Err(_) => {
+                        log::error!("Deadlock detected! Failed to acquire state lock");
+                        panic!("State lock timeout - possible deadlock");
+                    }
+                };
+            *state = ServerState::Stopping;
+            log::debug!("Server state set to Stopping in await_server_exit()");
+        }
+
+        // Without the child process of an adopted server, terminate and kill aren't available
+        let child = match &mut process {
+            ServerProcess::Child(child) => Some(child),
+            ServerProcess::Adopted(_) | ServerProcess::Detected(_) => None,
+        };
+        let stopped = stop_server(child, &stop_strategy).await;
+        if let Err(e) = &stopped {
+            log::error!(
+                "Failed to stop server: {}. Waiting for the server process to exit",
+                e
+            );
+        }
+        // A server that couldn't be stopped stays Stopping until it exits, so it's never launched a second time
+        if stopped.is_err() || !matches!(process, ServerProcess::Child(_)) {
+            process.wait().await;
+        }
+        reason
+    } else {
+        process.wait().await;
+        StopReason::Exited
+    }
+}
+
+/// Resolves if the server isn't ready within `startup_timeout`. Never resolves once it's ready or if the timeout is zero
+async fn startup_timed_out(mut ready: watch::Receiver<bool>, startup_timeout: Duration) {
+    if !startup_timeout.is_zero()
+        && tokio::time::timeout(startup_timeout, ready.wait_for(|ready| *ready))
+            .await
+            .is_err()
+    {
+        return;
+    }
+    std::future::pending().await
+}
+
+/// Connects the client to the Minecraft server and proxies the connection until either side closes it.
+/// `handshake_bytes` were already read from the client and are sent to the server first, so nothing the client
+/// sent is lost: empty for connections accepted while the server is running, [`LoginHandshake::bytes`] for
+/// logins read while it wasn't. Nothing else is read from the client before proxying
+#[allow(clippy::too_many_arguments)]
+async fn proxy_to_backend(
+    mut client_socket: ClientStream,
+    peer: SocketAddr,
+    handshake_bytes: Vec<u8>,
+    server_port: u16,
+    app_config: Arc<Config>,
+    packets: Arc<PreserializedPackets>,
+    sessions: ProxySessions,
+    server_state: Arc<Mutex<ServerState>>,
+    stop_requested: Arc<watch::Sender<bool>>,
+) {
+    let _session = sessions.track();
+    let mut closing = sessions.closing();
+    let server_addr = app_config.server_addr(server_port);
+    match connect_to_backend(
+        &server_addr,
+        app_config.proxy_connect_attempts,
+        Duration::from_millis(app_config.proxy_connect_retry_delay_ms),
+        app_config.timeouts.backend_connect(),
+    )
+    .await
+    {
+        Ok(mut server_socket) => {
+            server_socket.set_nodelay(true).unwrap();
+            if let Err(e) = server_socket.write_all(&handshake_bytes).await {
+                log::error!("Failed to forward handshake of {}: {:?}", peer, e);
+                return;
+            }
+
+            let proxied = tokio::select! {
+                proxied = proxy_connection(
+                    &mut client_socket,
+                    &mut server_socket,
+                    app_config.timeouts.proxy_idle(),
+                ) => proxied,
+                _ = closing.wait_for(|closing| *closing) => {
+                    log::info!("Closing proxied session for {}, shutting down", peer);
+                    Ok((0, 0))
+                }
+            };
+            match proxied {
+                Ok((read, written)) => {
+                    log::debug!(
+                        "Proxy successful for {}: read {} bytes, wrote {}",
+                        peer,
+                        read,
+                        written
+                    );
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    log::info!("Closing idle proxied session for {} ({})", peer, e);
+                }
+                Err(e) => {
+                    log::error!("Proxy error for {}: {:?}", peer, e);
+                }
+            }
+
+            // Attempt graceful shutdown of sockets
+            if let Err(e) = client_socket.shutdown().await {
+                log::warn!("Failed to shutdown client socket for {}: {:?}", peer, e);
+            }
+            if let Err(e) = server_socket.shutdown().await {
+                log::warn!("Failed to shutdown server socket for {}: {:?}", peer, e);
+            }
+        }
+        Err(e) => {
+            log::error!(
+                "Failed to connect to Minecraft server for {}: {:?}",
+                peer,
+                e
+            );
+            stop_unreachable_server(&server_state, &stop_requested, &server_addr).await;
+
+            // If nothing has been read from the client yet, answer its handshake ourselves
+            let is_login = !handshake_bytes.is_empty()
+                || matches!(
+                    verify_handshake_packet(&mut client_socket, peer, &app_config, &packets).await,
+                    Ok(true)
+                );
+            if is_login
+                && let Err(e) = send_disconnect_message(
+                    client_socket,
+                    &app_config,
+                    &app_config.backend_unreachable_msg_text,
+                )
+                .await
+            {
+                log::warn!("Failed to notify {}: {}", peer, e);
+            }
+        }
+    }
+}
+
+/// Stops a running server that can't be connected to, e.g. one that hangs. It counts as Stopping until the task
+/// waiting for its process saw it exit, so the next player can't launch a second server while it's still around
+async fn stop_unreachable_server(
+    server_state: &Mutex<ServerState>,
+    stop_requested: &watch::Sender<bool>,
+    server_addr: &str,
+) {
+    let mut state = match tokio::time::timeout(Duration::from_secs(5), server_state.lock()).await {
+        Ok(guard) => guard,
+        Err(_) => {
+            log::error!("Deadlock detected! Failed to acquire state lock");
+            panic!("State lock timeout - possible deadlock");
+        }
+    };
+    if *state == ServerState::Running {
+        *state = ServerState::Stopping;
+        log::error!(
+            "Minecraft server at {} is unreachable although it's running, stopping it so the next player starts it again",
+            server_addr
+        );
+        log::debug!("Server state set to Stopping after failed proxy connection");
+        stop_requested.send_replace(true);
+    }
+}
+
+/// Pings the server every `motd_cache_interval` while it's running and caches its status response, so the
+/// server list shows the real MOTD while it's asleep as well. Ends once the main loop is gone
+async fn cache_server_motd_periodically(
+    packets: Weak<PreserializedPackets>,
+    server_state: Arc<Mutex<ServerState>>,
+    app_config: Arc<Config>,
+    server_port: u16,
+) {
+    let mut ticker =
+        tokio::time::interval(Duration::from_secs(app_config.motd_cache_interval.max(1)));
+    loop {
+        ticker.tick().await;
+        let Some(packets) = packets.upgrade() else {
+            return;
+        };
+        if *server_state.lock().await != ServerState::Running {
+            continue;
+        }
+
+        match query_server_motd(&app_config, server_port, Duration::from_secs(5)).await {
+            Ok(status) => {
+                packets.cache_server_motd(status, app_config.cached_motd_suffix.as_deref());
+                log::debug!("Cached the server's MOTD");
+            }
+            Err(e) => log::debug!("Failed to fetch the server's MOTD: {:#}", e),
+        }
+    }
+}
+
+/// Reloads `cfg.toml` whenever it was modified (`watch_config`) and passes the new configuration to the main loop.
+/// A server of `listen-all` takes its entry of `servers`. Invalid changes are logged and skipped
+async fn watch_config_file(
+    config_dir: String,
+    name: Option<String>,
+    updates: mpsc::Sender<Config>,
+) {
+    let config_path = Path::new(&config_dir).join("cfg.toml");
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(&config_path);
+    let mut ticker = tokio::time::interval(CONFIG_POLL_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let current = modified(&config_path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        let reloaded = match config::reload_config(&config_dir) {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                log::warn!("Keeping the current configuration: {:#}", e);
+                continue;
+            }
+        };
+        let reloaded = match &name {
+            None => reloaded,
+            Some(name) => match reloaded.servers.iter().find(|server| &server.name == name) {
+                Some(definition) => reloaded.for_server(definition),
+                None => {
+                    log::warn!(
+                        "{} isn't in the servers list anymore, keeping its configuration",
+                        name
+                    );
+                    continue;
+                }
+            },
+        };
+        if updates.send(reloaded).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Keeps a login connection open while the server is starting and forwards it once the server is ready.
+/// Falls back to the connection message if the server isn't ready within `hold_connections_timeout`
+#[allow(clippy::too_many_arguments)]
+async fn hold_until_ready(
+    client_socket: ClientStream,
+    peer: SocketAddr,
+    handshake_bytes: Vec<u8>,
+    mut ready_rx: watch::Receiver<bool>,
+    server_port: u16,
+    app_config: Arc<Config>,
+    packets: Arc<PreserializedPackets>,
+    sessions: ProxySessions,
+    server_state: Arc<Mutex<ServerState>>,
+    stop_requested: Arc<watch::Sender<bool>>,
+) {
+    log::info!("Holding connection from {} until the server is ready", peer);
+    let hold_timeout = Duration::from_secs(app_config.hold_connections_timeout);
+
+    let ready = matches!(
+        tokio::time::timeout(hold_timeout, ready_rx.wait_for(|ready| *ready)).await,
+        Ok(Ok(_))
+    );
+    if ready {
+        log::info!("Server is ready, forwarding held connection from {}", peer);
+        proxy_to_backend(
+            client_socket,
+            peer,
+            handshake_bytes,
+            server_port,
+            app_config,
+            packets,
+            sessions,
+            server_state,
+            stop_requested,
+        )
+        .await;
+    } else {
+        log::info!(
+            "Server wasn't ready in time for held connection from {}",
+            peer
+        );
+        if let Err(e) = send_starting_message(client_socket, &app_config, &packets).await {
+            log::warn!("Failed to notify {}: {}", peer, e);
+        }
+    }
+}
+
+/// Binds the listener again after a handoff. The server may still hold the port for a moment after exiting
+async fn rebind_listener(addr: SocketAddr) -> TcpListener {
+    loop {
+        match bind_listener(addr) {
+            Ok(listener) => return listener,
+            Err(e) => {
+                log::warn!("Failed to bind {} again ({}), retrying...", addr, e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::config::Config;
+use crate::send_webhook;
+use tokio::time::Duration;
+
+/// Why the server stopped, filled into `{reason}` of `stop_webhook_text`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// No players were online for the idle timeout
+    Idle(Duration),
+    /// Stopped via Ctrl+C or the `stop` subcommand
+    Requested,
+    /// The server process exited by itself
+    Exited,
+    /// The server didn't become ready within `startup_timeout` and was killed
+    StartupTimedOut,
+    /// The `scheduled_stop` time was reached
+    Scheduled,
+}
+
+impl StopReason {
+    pub fn describe(&self) -> String {
+        match self {
+            StopReason::Idle(idle) => format!("idle {}", humantime::format_duration(*idle)),
+            StopReason::Requested => "stop requested".to_string(),
+            StopReason::Exited => "server exited".to_string(),
+            StopReason::StartupTimedOut => "startup timed out".to_string(),
+            StopReason::Scheduled => "scheduled stop".to_string(),
+        }
+    }
+
+    /// Short label used in metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopReason::Idle(_) => "idle",
+            StopReason::Requested => "requested",
+            StopReason::Exited => "exited",
+            StopReason::StartupTimedOut => "startup_timed_out",
+            StopReason::Scheduled => "scheduled",
+        }
+    }
+}
+
+/// Posts `wake_webhook_text` to `webhook_url` once the server was launched. `{player}` is replaced with
+/// whoever woke it
+pub fn notify_wake(config: &Config, player: &str) {
+    let message = config.wake_webhook_text.replace("{player}", player);
+    notify(config, message);
+}
+
+/// Posts `stop_webhook_text` to `webhook_url` once the server stopped. `{reason}` is replaced with `reason`
+pub fn notify_stop(config: &Config, reason: StopReason) {
+    let message = config
+        .stop_webhook_text
+        .replace("{reason}", &reason.describe());
+    notify(config, message);
+}
+
+/// Sends the notification in the background, so it never holds up starting or stopping the server.
+/// Failures are only logged
+fn notify(config: &Config, message: String) {
+    let Some(url) = config.webhook_url.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        if let Err(e) = send_webhook(&url, &message).await {
+            log::warn!("Failed to send webhook notification: {}", e);
+        }
+    });
+}
@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File in the configuration directory recording the server launched by this application
+pub const STATE_FILE_NAME: &str = "server_state.toml";
+
+/// Whether `file_name` is a file written by this module, i.e. `server_state.toml` or `server_state.<name>.toml`
+pub fn is_state_file_name(file_name: &str) -> bool {
+    file_name == STATE_FILE_NAME
+        || (file_name.starts_with("server_state.") && file_name.ends_with(".toml"))
+}
+
+/// A launched server, persisted so a restarted instance can adopt it instead of launching a second one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PersistedServer {
+    pub pid: u32,
+    pub server_port: u16,
+}
+
+/// The state file in `config_dir`. Each of several servers run by one instance gets its own, named after it
+pub fn state_file(config_dir: &str, server_name: Option<&str>) -> PathBuf {
+    match server_name {
+        Some(name) => Path::new(config_dir).join(format!("server_state.{}.toml", name)),
+        None => Path::new(config_dir).join(STATE_FILE_NAME),
+    }
+}
+
+/// Records the running server in the state file `path`
+pub fn save(path: &Path, server: &PersistedServer) -> Result<()> {
+    let contents = toml::to_string(server)?;
+    fs::write(path, contents).with_context(|| format!("Cannot write {}", path.display()))
+}
+
+/// Reads the recorded server. None if nothing is recorded or the file can't be read
+pub fn load(path: &Path) -> Option<PersistedServer> {
+    let contents = fs::read_to_string(path).ok()?;
+    match toml::from_str(&contents) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::warn!("Ignoring invalid {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Removes the record once the server exited
+pub fn clear(path: &Path) {
+    if let Err(e) = fs::remove_file(path)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        log::warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
+
+/// Whether a process with `pid` exists
+pub async fn is_process_alive(pid: u32) -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        match tokio::process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .await
+        {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()),
+            Err(_) => false,
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        // Signal 0 only checks whether the process exists
+        match tokio::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .await
+        {
+            Ok(status) => status.success(),
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use crate::config::{Config, ForgeData};
+use crate::{PlayerCounts, write_varint};
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose;
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Protocol reported without `motd_spoof_protocol`
+const MOTD_PROTOCOL: i32 = 766;
+
+/// Longest favicon attached to the status response. Clients reject status responses over 32767 characters as
+/// a whole, so this leaves room for the MOTD and the rest
+pub const MAX_FAVICON_LEN: usize = 30_000;
+
+/// Start of every PNG file
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Color used when the configured one is invalid
+const FALLBACK_COLOR: &str = "white";
+
+/// The 16 named colors of text components
+const NAMED_COLORS: [&str; 16] = [
+    "black",
+    "dark_blue",
+    "dark_green",
+    "dark_aqua",
+    "dark_red",
+    "dark_purple",
+    "gold",
+    "gray",
+    "dark_gray",
+    "blue",
+    "green",
+    "aqua",
+    "red",
+    "light_purple",
+    "yellow",
+    "white",
+];
+
+/// Packets that only depend on the config. They are serialized once at startup instead of on every connection
+pub struct PreserializedPackets {
+    pub motd_packet: Vec<u8>,
+    pub starting_message_packet: Vec<u8>,
+    // MOTD JSON without the version object and closing brace, used to splice in the client's protocol
+    motd_json_without_version: Option<String>,
+    /// The server's own status response, shown instead of `motd_packet` once known (`cache_server_motd`)
+    cached_motd: RwLock<Option<Value>>,
+    // Full MOTD JSON, for responses that show `player_counts`
+    motd_json: Value,
+    /// Player count and limit of the server, shown instead of 0 once reported
+    player_counts: Arc<PlayerCounts>,
+    /// `motd_version_name`, expanded for each client's protocol
+    version_name: String,
+}
+
+impl PreserializedPackets {
+    pub fn new(config: &Config) -> Self {
+        let motd_json_without_version = if config.motd_spoof_protocol {
+            let mut motd_json_obj = build_motd_json(config);
+            if let Value::Object(ref mut map) = motd_json_obj {
+                map.remove("version");
+            }
+            let mut motd_json = motd_json_obj.to_string();
+            motd_json.pop(); // Remove closing brace
+            Some(motd_json)
+        } else {
+            None
+        };
+
+        PreserializedPackets {
+            motd_packet: serialize_motd(config),
+            starting_message_packet: serialize_starting_message(config),
+            motd_json_without_version,
+            cached_motd: RwLock::new(None),
+            motd_json: build_motd_json(config),
+            player_counts: Arc::default(),
+            version_name: config.motd_version_name.clone(),
+        }
+    }
+
+    /// Shows the player counts reported by the idle watchdog (see [`crate::IdleTimer::with_player_counts`])
+    pub fn with_player_counts(mut self, player_counts: Arc<PlayerCounts>) -> Self {
+        self.player_counts = player_counts;
+        self
+    }
+
+    /// Takes over the status response cached by `previous`, e.g. after the configuration was reloaded
+    pub fn keep_cached_motd(&self, previous: &PreserializedPackets) {
+        *self.cached_motd.write().unwrap() = previous.cached_motd.read().unwrap().clone();
+    }
+
+    /// Remembers the status response of the running server, so it's shown while the server is asleep.
+    /// Nobody is online then, and `suffix` is appended to the description
+    pub fn cache_server_motd(&self, mut status: Value, suffix: Option<&str>) {
+        status["players"]["online"] = json!(0);
+        if let Value::Object(players) = &mut status["players"] {
+            players.remove("sample");
+        }
+        if let Some(suffix) = suffix {
+            match &mut status["description"] {
+                Value::String(text) => text.push_str(suffix),
+                description @ Value::Object(_) => match &mut description["extra"] {
+                    Value::Array(extra) => extra.push(json!(suffix)),
+                    extra => *extra = json!([suffix]),
+                },
+                description => *description = json!(suffix),
+            }
+        }
+        *self.cached_motd.write().unwrap() = Some(status);
+    }
+
+    /// Status response for a client using `protocol_version`. With `motd_spoof_protocol` enabled the client's
+    /// own protocol is reported, so the client never shows the server as outdated
+    pub fn motd_packet_for(&self, protocol_version: i32) -> Cow<'_, [u8]> {
+        let player_counts = self.player_counts.get();
+        let status = match (self.cached_motd.read().unwrap().as_ref(), player_counts) {
+            (Some(cached), _) => Some(cached.clone()),
+            (None, Some(_)) => {
+                let mut status = self.motd_json.clone();
+                if self.motd_json_without_version.is_some() {
+                    status["version"]["name"] =
+                        json!(expand_version_name(&self.version_name, protocol_version));
+                }
+                Some(status)
+            }
+            (None, None) => None,
+        };
+        if let Some(mut status) = status {
+            if self.motd_json_without_version.is_some() {
+                status["version"]["protocol"] = json!(protocol_version);
+            }
+            if let Some((online, max)) = player_counts {
+                status["players"]["online"] = json!(online);
+                status["players"]["max"] = json!(max);
+            }
+            return Cow::Owned(serialize_packet(0, &status.to_string()));
+        }
+
+        match &self.motd_json_without_version {
+            Some(motd_json) => {
+                let version = json!({
+                    "name": expand_version_name(&self.version_name, protocol_version),
+                    "protocol": protocol_version
+                });
+                Cow::Owned(serialize_packet(
+                    0,
+                    &format!("{},\"version\":{}}}", motd_json, version),
+                ))
+            }
+            None => Cow::Borrowed(&self.motd_packet),
+        }
+    }
+}
+
+/// Builds the status response packet shown in the server browser
+pub fn serialize_motd(config: &Config) -> Vec<u8> {
+    // Packet ID = 0 (status response)
+    serialize_packet(0, &build_motd_json(config).to_string())
+}
+
+fn build_motd_json(config: &Config) -> Value {
+    // Create custom MOTD JSON
+    // Protocol is "an integer used to check for incompatibilities between the player's client and the server
+    // they are trying to connect to.". 766 = Minecraft 1.20.5 (https://minecraft.fandom.com/wiki/Protocol_version)
+    let mut motd_json_obj = json!({
+        "version": {
+            "name": expand_version_name(&config.motd_version_name, MOTD_PROTOCOL),
+            "protocol": MOTD_PROTOCOL
+        },
+        "players": {
+            "max": 0,
+            "online": 0,
+            "sample": serialize_sample_lines(config)
+        },
+        "description": {
+            "text": config.motd_text,
+            "color": color_or_fallback(&config.motd_color),
+            "bold": config.motd_bold
+        }
+    });
+
+    if config.maintenance_mode {
+        // Replaces the whole description, whatever the MOTD is made of
+        motd_json_obj["description"]["text"] = json!(config.maintenance_motd_text);
+    } else if let Some(component) = config
+        .motd_component
+        .as_deref()
+        .and_then(|raw| parse_text_component(raw, "motd_component"))
+    {
+        motd_json_obj["description"] = component;
+    } else if let Some(line2) = config.motd_text_line2.as_ref() {
+        // The server list renders two lines, the second one is a child component after a line break
+        let color = config
+            .motd_color_line2
+            .as_ref()
+            .unwrap_or(&config.motd_color);
+        motd_json_obj["description"]["extra"] = json!([
+            "\n",
+            {
+                "text": line2,
+                "color": color_or_fallback(color),
+                "bold": config.motd_bold_line2.unwrap_or(config.motd_bold)
+            }
+        ]);
+    }
+
+    if let Value::Object(ref mut map) = motd_json_obj {
+        if let Some(server_icon_base64) = config.server_icon.as_ref() {
+            match validate_favicon(server_icon_base64) {
+                Ok(()) => {
+                    map.insert(
+                        "favicon".to_string(),
+                        Value::String(format!("data:image/png;base64,{}", server_icon_base64)),
+                    );
+                }
+                Err(e) => log::warn!("Not using the server icon: {:#}", e),
+            }
+        }
+
+        if let Some(forge_data) = config.forge_data.as_ref() {
+            match serialize_forge_data(forge_data) {
+                Ok(forge_data_json) => {
+                    map.insert("forgeData".to_string(), forge_data_json);
+                }
+                Err(e) => log::warn!("Invalid forge_data in config, not advertising it: {}", e),
+            }
+        }
+    }
+
+    motd_json_obj
+}
+
+/// Builds the status response shown while the server is starting, with `{elapsed}` in `starting_motd_text`
+/// replaced by the seconds since launch. Returns None if `starting_motd_text` isn't set
+pub fn serialize_starting_motd(
+    config: &Config,
+    protocol_version: i32,
+    elapsed: Duration,
+) -> Option<Vec<u8>> {
+    let text = config.starting_motd_text.as_ref()?;
+    let mut motd_json_obj = build_motd_json(config);
+    motd_json_obj["description"] = json!({
+        "text": text.replace("{elapsed}", &elapsed.as_secs().to_string()),
+        "color": color_or_fallback(&config.motd_color),
+        "bold": config.motd_bold
+    });
+    if config.motd_spoof_protocol {
+        motd_json_obj["version"] = json!({
+            "name": expand_version_name(&config.motd_version_name, protocol_version),
+            "protocol": protocol_version
+        });
+    }
+    Some(serialize_packet(0, &motd_json_obj.to_string()))
+}
+
+/// `motd_version_name` with `{protocol}` replaced by the protocol number reported to the client
+pub fn expand_version_name(template: &str, protocol_version: i32) -> String {
+    template.replace("{protocol}", &protocol_version.to_string())
+}
+
+/// Player sample shown when hovering the player count. Each configured line becomes a fake player with a random UUID
+fn serialize_sample_lines(config: &Config) -> Value {
+    let sample: Vec<Value> = config
+        .motd_sample_lines
+        .iter()
+        .flatten()
+        .map(|line| json!({ "name": line, "id": Uuid::new_v4().to_string() }))
+        .collect();
+    Value::Array(sample)
+}
+
+/// Builds the login disconnect packet shown to players while the server is starting
+pub fn serialize_starting_message(config: &Config) -> Vec<u8> {
+    if let Some(component) = config
+        .connection_msg_component
+        .as_deref()
+        .and_then(|raw| parse_text_component(raw, "connection_msg_component"))
+    {
+        // Packet ID 0x00 (login disconnect)
+        return serialize_packet(0, &component.to_string());
+    }
+    serialize_disconnect_message(
+        &config.connection_msg_text,
+        &config.connection_msg_color,
+        config.connection_msg_bold,
+    )
+}
+
+/// Builds a login disconnect packet with the given text
+pub fn serialize_disconnect_message(text: &str, color: &str, bold: bool) -> Vec<u8> {
+    let json_msg = json!({
+        "text": text,
+        "color": color_or_fallback(color),
+        "bold": bold
+    })
+    .to_string();
+
+    // Packet ID 0x00 (login disconnect)
+    serialize_packet(0, &json_msg)
+}
+
+/// Parses a raw JSON text component from the config option `name`. Only objects and arrays are accepted,
+/// anything else is logged and None is returned, so the simple text fields are used instead
+pub fn parse_text_component(raw: &str, name: &str) -> Option<Value> {
+    match serde_json::from_str::<Value>(raw) {
+        Ok(component @ (Value::Object(_) | Value::Array(_))) => Some(component),
+        Ok(_) => {
+            log::warn!(
+                "{} must be a JSON object or array, using the simple text fields instead",
+                name
+            );
+            None
+        }
+        Err(e) => {
+            log::warn!(
+                "{} isn't valid JSON ({}), using the simple text fields instead",
+                name,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Returns the color as used in text components if it's a named color or a `#RRGGBB` hex color (1.16+ clients)
+pub fn validate_color(color: &str) -> Option<String> {
+    let color = color.trim();
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Some(format!("#{}", hex.to_ascii_uppercase()));
+        }
+        return None;
+    }
+    let named = color.to_ascii_lowercase();
+    NAMED_COLORS.contains(&named.as_str()).then_some(named)
+}
+
+fn color_or_fallback(color: &str) -> String {
+    validate_color(color).unwrap_or_else(|| {
+        log::warn!(
+            "Invalid color '{}' in config, using '{}' instead. Use a named color or #RRGGBB",
+            color,
+            FALLBACK_COLOR
+        );
+        FALLBACK_COLOR.to_string()
+    })
+}
+
+/// Builds the `forgeData` object of the status response (uncompressed FML network version 2 layout).
+/// Forge clients compare the listed mods and channels against their own installation
+fn serialize_forge_data(forge_data: &ForgeData) -> Result<Value> {
+    if forge_data.fml_network_version == 0 {
+        bail!("fml_network_version must be at least 1");
+    }
+
+    let mut mods = Vec::new();
+    for m in &forge_data.mods {
+        if m.mod_id.is_empty() {
+            bail!("mod entry with empty mod_id");
+        }
+        mods.push(json!({ "modId": m.mod_id, "modmarker": m.version }));
+    }
+
+    let mut channels = Vec::new();
+    for c in &forge_data.channels {
+        // Channel names are resource locations ("namespace:path")
+        if !c.res.contains(':') {
+            bail!("channel '{}' is not of the form namespace:path", c.res);
+        }
+        channels.push(json!({ "res": c.res, "version": c.version, "required": c.required }));
+    }
+
+    Ok(json!({
+        "channels": channels,
+        "mods": mods,
+        "fmlNetworkVersion": forge_data.fml_network_version,
+        "truncated": false
+    }))
+}
+
+/// Builds the Login Success packet (ID 0x02) of an offline mode login without profile properties.
+/// 1.20.5 and 1.21 clients (protocol 766 and 767) also expect the strict error handling flag
+pub fn serialize_login_success(uuid: Uuid, username: &str, protocol_version: i32) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(uuid.as_bytes());
+    write_varint(username.len() as i32, &mut data);
+    data.extend_from_slice(username.as_bytes());
+    write_varint(0, &mut data); // No properties
+    if protocol_version < 768 {
+        data.push(0); // Strict error handling disabled
+    }
+    frame_packet(0x02, &data)
+}
+
+/// Builds the Transfer packet of the configuration state (ID 0x0B), telling the client to connect to `host:port`
+pub fn serialize_transfer(host: &str, port: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_varint(host.len() as i32, &mut data);
+    data.extend_from_slice(host.as_bytes());
+    write_varint(port as i32, &mut data);
+    frame_packet(0x0B, &data)
+}
+
+/// Builds a handshake with the next state status (1) followed by the Status Request packet, asking a server
+/// for its status response
+pub fn serialize_status_request(host: &str, port: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_varint(766, &mut data); // Protocol version, any is answered
+    write_varint(host.len() as i32, &mut data);
+    data.extend_from_slice(host.as_bytes());
+    data.extend_from_slice(&port.to_be_bytes());
+    write_varint(1, &mut data);
+
+    let mut packets = frame_packet(0x00, &data);
+    packets.extend_from_slice(&frame_packet(0x00, &[]));
+    packets
+}
+
+/// Prefixes a string payload with its packet ID and the packet length
+fn serialize_packet(packet_id: i32, payload: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_varint(payload.len() as i32, &mut data);
+    data.extend_from_slice(payload.as_bytes());
+    frame_packet(packet_id, &data)
+}
+
+/// Prefixes the packet data with its packet ID and the packet length
+fn frame_packet(packet_id: i32, data: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_varint(packet_id, &mut body);
+    body.extend_from_slice(data);
+
+    let mut packet = Vec::new();
+    write_varint(body.len() as i32, &mut packet);
+    packet.extend_from_slice(&body);
+    packet
+}
+
+/// Checks that `server_icon_base64` is a 64x64 PNG short enough for the status response, which clients
+/// would otherwise reject as a whole
+pub fn validate_favicon(server_icon_base64: &str) -> Result<()> {
+    if server_icon_base64.len() > MAX_FAVICON_LEN {
+        bail!(
+            "the encoded icon is {} characters, more than the limit of {}",
+            server_icon_base64.len(),
+            MAX_FAVICON_LEN
+        );
+    }
+    let png = general_purpose::STANDARD
+        .decode(server_icon_base64)
+        .context("not valid base64")?;
+    // The IHDR chunk always comes first: its length and type, then the width and height
+    if png.len() < 24 || png[..8] != PNG_SIGNATURE || &png[12..16] != b"IHDR" {
+        bail!("not a PNG image");
+    }
+    let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+    if (width, height) != (64, 64) {
+        bail!("the icon is {}x{} instead of 64x64", width, height);
+    }
+    Ok(())
+}
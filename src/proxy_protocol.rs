@@ -0,0 +1,90 @@
+use anyhow::{Context, Result, bail};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Signature starting a PROXY protocol v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest v1 header, including the trailing CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+/// Larger v2 headers (only possible with lots of TLVs) are refused
+const V2_MAX_ADDRESS_LENGTH: usize = 4096;
+
+/// Reads a PROXY protocol v1 or v2 header (`accept_proxy_protocol`) off the front of the stream, leaving the
+/// Minecraft handshake behind it unread. Returns the client's address, or None if the proxy didn't pass one on
+/// (v1 `UNKNOWN`, v2 `LOCAL` or a non-TCP address family). Fails if the connection doesn't start with a header
+pub async fn read_proxy_header<S: AsyncRead + Unpin>(socket: &mut S) -> Result<Option<SocketAddr>> {
+    match socket.read_u8().await? {
+        b'P' => read_v1(socket).await,
+        b'\r' => read_v2(socket).await,
+        byte => bail!("No PROXY protocol header (first byte {:#04x})", byte),
+    }
+}
+
+/// `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n`, the `P` already read
+async fn read_v1<S: AsyncRead + Unpin>(socket: &mut S) -> Result<Option<SocketAddr>> {
+    let mut line = vec![b'P'];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            bail!("PROXY protocol v1 header is too long");
+        }
+        line.push(socket.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2]).context("Invalid PROXY header")?;
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source.parse().context("Invalid source address")?;
+            let port: u16 = source_port.parse().context("Invalid source port")?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("Invalid PROXY protocol v1 header: {}", line),
+    }
+}
+
+/// Binary header: signature, version and command, address family, address length and addresses.
+/// The first byte of the signature is already read
+async fn read_v2<S: AsyncRead + Unpin>(socket: &mut S) -> Result<Option<SocketAddr>> {
+    let mut header = [0u8; 15];
+    socket.read_exact(&mut header).await?;
+    if header[..11] != V2_SIGNATURE[1..] {
+        bail!("No PROXY protocol header (invalid v2 signature)");
+    }
+    let version_command = header[11];
+    let family = header[12];
+    let length = u16::from_be_bytes([header[13], header[14]]) as usize;
+    if version_command >> 4 != 2 {
+        bail!(
+            "Unsupported PROXY protocol version {}",
+            version_command >> 4
+        );
+    }
+    if length > V2_MAX_ADDRESS_LENGTH {
+        bail!("PROXY protocol v2 header is too long");
+    }
+    let mut addresses = vec![0u8; length];
+    socket.read_exact(&mut addresses).await?;
+
+    // LOCAL connections (e.g. health checks of the proxy) carry no client address
+    if version_command & 0x0F == 0 {
+        return Ok(None);
+    }
+    match family {
+        // TCP over IPv4: source, destination, source port, destination port
+        0x11 if length >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // TCP over IPv6
+        0x21 if length >= 36 => {
+            let octets: [u8; 16] = addresses[..16].try_into()?;
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        _ => Ok(None),
+    }
+}
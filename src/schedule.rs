@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveTime};
+use std::time::Duration;
+
+/// A daily range of local time, e.g. `02:00-08:00`. Ranges wrapping around midnight (`22:00-06:00`) are supported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    /// Parses `HH:MM-HH:MM`
+    pub fn parse(window: &str) -> Result<Self> {
+        let (start, end) = window
+            .split_once('-')
+            .with_context(|| format!("Time window `{}` isn't in the form HH:MM-HH:MM", window))?;
+        Ok(TimeWindow {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    /// Whether the current local time is in the window
+    pub fn is_active(&self) -> bool {
+        self.contains(Local::now().time())
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// Parses a local time of day, `HH:MM`
+pub fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .with_context(|| format!("`{}` isn't a time of day (HH:MM)", time.trim()))
+}
+
+/// The first of `windows` (see [`TimeWindow::parse`]) the current local time is in. Invalid windows are skipped
+pub fn active_window(windows: &[String]) -> Option<TimeWindow> {
+    windows
+        .iter()
+        .filter_map(|window| TimeWindow::parse(window).ok())
+        .find(TimeWindow::is_active)
+}
+
+/// Time from now until the local time of day `time` is reached next, today or tomorrow
+pub fn until_next(time: NaiveTime) -> Duration {
+    let now = Local::now().time();
+    let until = time.signed_duration_since(now);
+    let until = if until <= chrono::TimeDelta::zero() {
+        until + chrono::TimeDelta::days(1)
+    } else {
+        until
+    };
+    until.to_std().unwrap_or_default()
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The server's console output, appended to `server_log_file`. Once a file reaches its share of the size limit,
+/// it's rotated to `<file>.1`, the previous `<file>.1` to `<file>.2` and so on, and the oldest one is deleted
+pub struct ServerLog {
+    path: PathBuf,
+    /// Size limit of each of the files
+    max_file_size: u64,
+    /// Number of files kept, including the current one
+    files: u32,
+    file: File,
+    size: u64,
+}
+
+impl ServerLog {
+    /// Opens `path` for appending. `max_total_size` is split evenly across the `files` kept
+    pub fn open(path: &Path, max_total_size: u64, files: u32) -> Result<Self> {
+        let files = files.max(1);
+        let file = open_append(path)?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(ServerLog {
+            path: path.to_path_buf(),
+            max_file_size: max_total_size / files as u64,
+            files,
+            file,
+            size,
+        })
+    }
+
+    /// Appends a line of server output. Failures are logged, the server keeps running either way
+    pub fn write_line(&mut self, line: &str) {
+        if self.size > 0
+            && self.size + line.len() as u64 + 1 > self.max_file_size
+            && let Err(e) = self.rotate()
+        {
+            log::warn!("Failed to rotate {}: {:#}", self.path.display(), e);
+        }
+        match writeln!(self.file, "{}", line) {
+            Ok(()) => self.size += line.len() as u64 + 1,
+            Err(e) => log::warn!("Failed to write to {}: {}", self.path.display(), e),
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        // With a single file, the current one is started over
+        for index in (1..self.files).rev() {
+            let from = if index == 1 {
+                self.path.clone()
+            } else {
+                rotated_path(&self.path, index - 1)
+            };
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index))?;
+            }
+        }
+        if self.files == 1 {
+            fs::remove_file(&self.path)?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Cannot open {}", path.display()))
+}
+
+/// `<path>.<index>`, e.g. `server.log.1`
+pub fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// The log file of one of several servers, named after it like the state file, e.g. `server.survival.log`
+pub fn named_log_file(path: &str, server_name: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, server_name, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, server_name),
+    };
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Settings read from the Minecraft server's `server.properties`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerProperties {
+    pub server_port: Option<u16>,
+    pub rcon_port: Option<u16>,
+    pub rcon_password: Option<String>,
+    pub enable_rcon: Option<bool>,
+    pub white_list: Option<bool>,
+}
+
+impl ServerProperties {
+    /// Replaces the detected values with the ones given explicitly, e.g. `--server-port`, which take precedence
+    pub fn with_explicit(
+        self,
+        server_port: Option<u16>,
+        rcon_port: Option<u16>,
+        rcon_password: Option<String>,
+    ) -> Self {
+        ServerProperties {
+            server_port: server_port.or(self.server_port),
+            rcon_port: rcon_port.or(self.rcon_port),
+            rcon_password: rcon_password.or(self.rcon_password),
+            ..self
+        }
+    }
+}
+
+/// Reads `server-port`, `rcon.port`, `rcon.password`, `enable-rcon` and `white-list` from a `server.properties` file.
+/// Missing or invalid values are None
+pub fn parse(path: impl AsRef<Path>) -> Result<ServerProperties> {
+    let path = path.as_ref();
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    Ok(parse_str(&contents))
+}
+
+/// Parses the contents of a `server.properties` file, see [`parse`]
+pub fn parse_str(contents: &str) -> ServerProperties {
+    let properties = parse_properties(contents);
+    ServerProperties {
+        server_port: properties.get("server-port").and_then(|v| v.parse().ok()),
+        rcon_port: properties.get("rcon.port").and_then(|v| v.parse().ok()),
+        rcon_password: properties
+            .get("rcon.password")
+            .filter(|v| !v.is_empty())
+            .cloned(),
+        enable_rcon: properties.get("enable-rcon").and_then(|v| v.parse().ok()),
+        white_list: properties.get("white-list").and_then(|v| v.parse().ok()),
+    }
+}
+
+// Java properties format as written by the server: `key=value` lines, `#`/`!` comments and backslash escapes
+fn parse_properties(contents: &str) -> HashMap<String, String> {
+    let mut properties = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+
+        let mut key = String::new();
+        let mut value = String::new();
+        let mut in_value = false;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            let c = match c {
+                '\\' => match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(escaped) => escaped,
+                    None => break,
+                },
+                '=' | ':' if !in_value => {
+                    in_value = true;
+                    continue;
+                }
+                c => c,
+            };
+            if in_value {
+                value.push(c);
+            } else {
+                key.push(c);
+            }
+        }
+        properties.insert(key.trim().to_string(), value.trim().to_string());
+    }
+    properties
+}
+
+/// Whether the `server.properties` at `path` disables RCON (`enable-rcon=false`). False if it can't be read
+pub fn rcon_disabled(path: impl AsRef<Path>) -> bool {
+    parse(path).is_ok_and(|properties| properties.enable_rcon == Some(false))
+}
@@ -0,0 +1,54 @@
+use crate::config::Config;
+use crate::server_properties;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// An entry of `whitelist.json` or `ops.json`. The UUID isn't needed, usernames are compared
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+}
+
+/// Usernames listed in a `whitelist.json` (or `ops.json`) file
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<String>> {
+    let path = path.as_ref();
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+    let entries: Vec<Entry> = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid whitelist {}", path.display()))?;
+    Ok(entries.into_iter().map(|entry| entry.name).collect())
+}
+
+/// Whether the server would let `username` join as far as its own whitelist is concerned (`use_server_whitelist`).
+/// True unless `white-list=true` is set in `server_properties_path`. Operators in the `ops.json` next to
+/// `server_whitelist_path` may always join, like on the server. The files are read on every call, so players
+/// added while the server is asleep aren't refused. If they can't be read, everyone is let through
+pub fn allows(config: &Config, username: Option<&str>) -> bool {
+    if !config.use_server_whitelist {
+        return true;
+    }
+    let enabled = server_properties::parse(&config.server_properties_path)
+        .is_ok_and(|properties| properties.white_list == Some(true));
+    if !enabled {
+        return true;
+    }
+    let Some(username) = username else {
+        return false;
+    };
+
+    let whitelist_path = Path::new(&config.server_whitelist_path);
+    let mut names = match load(whitelist_path) {
+        Ok(names) => names,
+        Err(e) => {
+            log::warn!("{:#}, not checking the server's whitelist", e);
+            return true;
+        }
+    };
+    // ops.json may not exist yet, that's fine
+    if let Ok(ops) = load(whitelist_path.with_file_name("ops.json")) {
+        names.extend(ops);
+    }
+    names.iter().any(|name| name.eq_ignore_ascii_case(username))
+}
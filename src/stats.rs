@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// File in the configuration directory keeping the lifetime statistics of the server
+pub const STATS_FILE_NAME: &str = "stats.json";
+
+/// Whether `file_name` is a file written by this module, i.e. `stats.json` or `stats.<name>.json`, or the
+/// temporary file one of them is saved to
+pub fn is_stats_file_name(file_name: &str) -> bool {
+    let file_name = file_name.strip_suffix(".tmp").unwrap_or(file_name);
+    file_name == STATS_FILE_NAME
+        || (file_name.starts_with("stats.") && file_name.ends_with(".json"))
+}
+
+/// The stats file in `config_dir`. Each of several servers run by one instance gets its own, named after it
+pub fn stats_file(config_dir: &str, server_name: Option<&str>) -> PathBuf {
+    match server_name {
+        Some(name) => Path::new(config_dir).join(format!("stats.{}.json", name)),
+        None => Path::new(config_dir).join(STATS_FILE_NAME),
+    }
+}
+
+/// How often and how long the server was awake, summed up over all runs of this application
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct LifetimeStats {
+    pub wakes: u64,
+    pub awake_secs: u64,
+    /// Only counted while this application runs
+    pub asleep_secs: u64,
+    /// RFC 3339 timestamps
+    pub last_wake: Option<String>,
+    pub last_stop: Option<String>,
+}
+
+impl LifetimeStats {
+    /// Reads the stats file. Nothing recorded yet counts as empty stats
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid stats file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(LifetimeStats::default()),
+            Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+        }
+    }
+
+    /// Writes to a temporary file first and renames it, so a crash mid-write leaves the old file intact
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Cannot write {}", temp_path.display()))?;
+        fs::rename(&temp_path, path).with_context(|| format!("Cannot write {}", path.display()))
+    }
+}
+
+/// Updates the stats file on every wake and stop, and once more on shutdown
+#[derive(Debug)]
+pub struct StatsRecorder {
+    path: PathBuf,
+    inner: Mutex<Recorded>,
+}
+
+#[derive(Debug)]
+struct Recorded {
+    stats: LifetimeStats,
+    awake: bool,
+    // Start of the current awake or asleep period, not counted into `stats` yet
+    since: Instant,
+}
+
+impl StatsRecorder {
+    /// Continues the stats in `path`. Invalid stats are logged and started over
+    pub fn open(path: PathBuf) -> Self {
+        let stats = LifetimeStats::load(&path).unwrap_or_else(|e| {
+            log::warn!("{:#}, starting the statistics over", e);
+            LifetimeStats::default()
+        });
+        StatsRecorder {
+            path,
+            inner: Mutex::new(Recorded {
+                stats,
+                awake: false,
+                since: Instant::now(),
+            }),
+        }
+    }
+
+    /// The server was launched because a player (or the control channel) woke it
+    pub fn record_wake(&self) {
+        self.update(|stats| {
+            stats.wakes += 1;
+            stats.last_wake = Some(now());
+        });
+        self.set_awake(true);
+    }
+
+    /// The server runs from now on without being woken, e.g. an adopted one
+    pub fn mark_awake(&self) {
+        self.set_awake(true);
+    }
+
+    pub fn record_stop(&self) {
+        self.update(|stats| stats.last_stop = Some(now()));
+        self.set_awake(false);
+    }
+
+    /// Counts the current period and writes the stats, e.g. on shutdown
+    pub fn flush(&self) {
+        let awake = self.lock().awake;
+        self.set_awake(awake);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Recorded> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, update: impl FnOnce(&mut LifetimeStats)) {
+        update(&mut self.lock().stats);
+    }
+
+    /// Ends the current period, starts one that's `awake` or not and writes the stats
+    fn set_awake(&self, awake: bool) {
+        let mut recorded = self.lock();
+        let elapsed = recorded.since.elapsed().as_secs();
+        if recorded.awake {
+            recorded.stats.awake_secs += elapsed;
+        } else {
+            recorded.stats.asleep_secs += elapsed;
+        }
+        // Whole seconds are counted, the rest carries over to the next period
+        recorded.since += Duration::from_secs(elapsed);
+        recorded.awake = awake;
+        if let Err(e) = recorded.stats.save(&self.path) {
+            log::warn!("Failed to save the statistics: {:#}", e);
+        }
+    }
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
@@ -0,0 +1,293 @@
+use crate::config::Config;
+use crate::console::ServerConsole;
+use crate::persisted_state;
+use crate::send_stop_command;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::process::Child;
+use tokio::time::{Duration, Instant, timeout};
+
+/// How long a killed process may take to exit before the kill counts as failed
+const KILL_CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One step of stopping the server. [`stop_server`] escalates through the configured steps until the server is gone
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StopStep {
+    /// Run `stop_command_shell`
+    Shell,
+    /// Write `stop` to the server console (only with a captured console)
+    Console,
+    /// Send `stop` via RCON
+    Rcon,
+    /// Ask the operating system to terminate the server process (SIGTERM / `taskkill`)
+    Terminate,
+    /// Forcefully kill the server process (SIGKILL / `taskkill /F`)
+    Kill,
+}
+
+impl StopStep {
+    pub const DEFAULT_ORDER: [StopStep; 5] = [
+        StopStep::Shell,
+        StopStep::Console,
+        StopStep::Rcon,
+        StopStep::Terminate,
+        StopStep::Kill,
+    ];
+}
+
+/// Everything needed to stop the server: the escalation steps in order and the means to perform them
+pub struct StopStrategy {
+    pub steps: Vec<StopStep>,
+    /// How long to wait for the server to exit after each step. Zero waits indefinitely after the first step
+    pub step_timeout: Duration,
+    pub rcon_addr: String,
+    pub rcon_pass: String,
+    pub stop_command_shell: Option<String>,
+    pub console: Option<Arc<ServerConsole>>,
+    /// Once nothing accepts connections on this address anymore, the server counts as stopped
+    pub server_addr: Option<String>,
+}
+
+impl StopStrategy {
+    pub fn from_config(
+        config: &Config,
+        rcon_addr: &str,
+        rcon_pass: &str,
+        server_addr: Option<String>,
+    ) -> Self {
+        StopStrategy {
+            steps: config.stop_steps.clone(),
+            step_timeout: Duration::from_secs(config.stop_timeout),
+            rcon_addr: rcon_addr.to_string(),
+            rcon_pass: rcon_pass.to_string(),
+            stop_command_shell: config.stop_command_shell.clone(),
+            console: None,
+            server_addr,
+        }
+    }
+}
+
+/// Stops the server by going through the steps of `strategy` in order. After each step the server gets
+/// `step_timeout` to exit before escalating to the next one. Steps that aren't available (e.g. `kill` without
+/// a `child` process or `shell` without `stop_command_shell`) are skipped.
+/// Without a `child` and `server_addr`, there's no way to tell if the server exited, so the first step that succeeds ends it
+pub async fn stop_server(mut child: Option<&mut Child>, strategy: &StopStrategy) -> Result<()> {
+    if let Some(child) = child.as_deref_mut()
+        && child.try_wait()?.is_some()
+    {
+        log::info!("Server process already exited");
+        return Ok(());
+    }
+
+    for &step in &strategy.steps {
+        match run_step(step, child.as_deref_mut(), strategy).await {
+            Ok(true) => log::info!("Stopping server: {:?} step done", step),
+            Ok(false) => {
+                log::debug!("Stop step {:?} not available, skipping", step);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Stop step {:?} failed: {}", step, e);
+                continue;
+            }
+        }
+
+        if wait_until_stopped(child.as_deref_mut(), strategy).await {
+            log::info!("Server stopped after the {:?} step", step);
+            return Ok(());
+        }
+        log::warn!(
+            "Server still running {:?} after the {:?} step, escalating",
+            strategy.step_timeout,
+            step
+        );
+    }
+
+    bail!("Server is still running after all stop steps")
+}
+
+/// Performs a single step. Returns false if the step isn't available
+async fn run_step(
+    step: StopStep,
+    child: Option<&mut Child>,
+    strategy: &StopStrategy,
+) -> Result<bool> {
+    match step {
+        StopStep::Shell => match strategy.stop_command_shell.as_deref() {
+            Some(command) => run_stop_shell_command(command).await.map(|_| true),
+            None => Ok(false),
+        },
+        StopStep::Console => match strategy.console.as_ref() {
+            Some(console) => console.send_command("stop").await.map(|_| true),
+            None => Ok(false),
+        },
+        StopStep::Rcon => send_stop_command(&strategy.rcon_addr, &strategy.rcon_pass)
+            .await
+            .map(|_| true),
+        StopStep::Terminate => match child {
+            Some(child) => terminate_server_process(child).await.map(|_| true),
+            None => Ok(false),
+        },
+        StopStep::Kill => match child {
+            Some(child) => kill_server_process(child).await.map(|_| true),
+            None => Ok(false),
+        },
+    }
+}
+
+/// Waits up to `step_timeout` for the server process to exit and its port to close
+async fn wait_until_stopped(child: Option<&mut Child>, strategy: &StopStrategy) -> bool {
+    if child.is_none() && strategy.server_addr.is_none() {
+        return true;
+    }
+
+    let stopped = async {
+        if let Some(child) = child
+            && let Err(e) = child.wait().await
+        {
+            log::error!("Failed to wait for server exit: {:?}", e);
+        }
+        if let Some(server_addr) = strategy.server_addr.as_deref() {
+            while TcpStream::connect(server_addr).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        }
+    };
+
+    if strategy.step_timeout.is_zero() {
+        stopped.await;
+        return true;
+    }
+    timeout(strategy.step_timeout, stopped).await.is_ok()
+}
+
+/// Runs `command` through the system shell to stop the server, e.g. `./stop.sh` or `systemctl stop myserver`.
+/// Its output is logged; a non-zero exit code counts as a failed stop
+pub async fn run_stop_shell_command(command: &str) -> Result<()> {
+    log::info!("Running stop command: {}", command);
+
+    #[cfg(target_os = "windows")]
+    let output = tokio::process::Command::new("cmd")
+        .args(["/C", command])
+        .output()
+        .await?;
+    #[cfg(not(target_os = "windows"))]
+    let output = tokio::process::Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .await?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stdout.trim().is_empty() {
+        log::info!("Stop command output: {}", stdout.trim());
+    }
+    if !stderr.trim().is_empty() {
+        log::warn!("Stop command error output: {}", stderr.trim());
+    }
+
+    if !output.status.success() {
+        bail!("Stop command `{}` failed with {}", command, output.status);
+    }
+    Ok(())
+}
+
+/// Asks the operating system to terminate the server process, giving it a chance to shut down cleanly
+pub async fn terminate_server_process(child: &mut Child) -> Result<()> {
+    let Some(pid) = child.id() else {
+        // Already exited
+        return Ok(());
+    };
+
+    #[cfg(target_os = "windows")]
+    let status = tokio::process::Command::new("taskkill")
+        .args(["/T", "/PID", &pid.to_string()])
+        .status()
+        .await?;
+    #[cfg(not(target_os = "windows"))]
+    let status = tokio::process::Command::new("kill")
+        .args(["-TERM", &pid.to_string()])
+        .status()
+        .await?;
+
+    if !status.success() {
+        bail!("Terminating server process {} failed with {}", pid, status);
+    }
+    Ok(())
+}
+
+/// Forcefully terminates the process `pid` (on Windows along with its process tree) without waiting for it.
+/// Used on shutdown, when the server process is owned by a task that's still busy stopping it
+pub async fn kill_process(pid: u32) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    let status = tokio::process::Command::new("taskkill")
+        .args(["/T", "/F", "/PID", &pid.to_string()])
+        .status()
+        .await?;
+    #[cfg(not(target_os = "windows"))]
+    let status = tokio::process::Command::new("kill")
+        .args(["-KILL", &pid.to_string()])
+        .status()
+        .await?;
+
+    if !status.success() {
+        bail!("Killing process {} failed with {}", pid, status);
+    }
+    Ok(())
+}
+
+/// Forcefully terminates the process `pid` like [`kill_process`] and waits up to [`KILL_CONFIRM_TIMEOUT`] for it
+/// to disappear. For processes that aren't children of this one, like an adopted server
+pub async fn kill_process_and_wait(pid: u32) -> Result<()> {
+    kill_process(pid).await?;
+    let deadline = Instant::now() + KILL_CONFIRM_TIMEOUT;
+    while persisted_state::is_process_alive(pid).await {
+        if Instant::now() >= deadline {
+            bail!(
+                "Process {} is still running {:?} after being killed",
+                pid,
+                KILL_CONFIRM_TIMEOUT
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    log::info!("Server process {} killed", pid);
+    Ok(())
+}
+
+/// Forcefully terminates the server process and waits up to [`KILL_CONFIRM_TIMEOUT`] for it to exit, so its port
+/// is free once this returns Ok. On Windows the whole process tree is killed, so a server started by a script
+/// (e.g. Java launched by a .bat) dies too
+pub async fn kill_server_process(child: &mut Child) -> Result<()> {
+    #[cfg(target_os = "windows")]
+    if let Some(pid) = child.id() {
+        let status = tokio::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .status()
+            .await?;
+        if status.success() {
+            return confirm_exit(child).await;
+        }
+        log::warn!("taskkill for server process {} failed with {}", pid, status);
+    }
+
+    child.start_kill()?;
+    confirm_exit(child).await
+}
+
+async fn confirm_exit(child: &mut Child) -> Result<()> {
+    match timeout(KILL_CONFIRM_TIMEOUT, child.wait()).await {
+        Ok(Ok(status)) => {
+            log::info!("Server process killed ({})", status);
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => bail!(
+            "Server process is still running {:?} after being killed",
+            KILL_CONFIRM_TIMEOUT
+        ),
+    }
+}
@@ -0,0 +1,190 @@
+use crate::config::{self, Config, PlayerCountSource};
+use crate::preserialized_packets::{PreserializedPackets, parse_text_component};
+use crate::server_properties;
+use rcon::Connection;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Duration, timeout};
+
+/// How long the `verify` subcommand waits for the server and its RCON to answer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Result of one check of the `verify` subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// Works, but probably not as intended
+    Warning,
+    /// Not checked, e.g. RCON while the server isn't running
+    Skipped,
+    /// `listen` would fail or misbehave
+    Failed,
+}
+
+/// One line of the `verify` checklist, e.g. `[ OK ] Listen address 0.0.0.0:25565: bindable`
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: impl Into<String>, outcome: Outcome, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            outcome,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.outcome {
+            Outcome::Passed => " OK ",
+            Outcome::Warning => "WARN",
+            Outcome::Skipped => "SKIP",
+            Outcome::Failed => "FAIL",
+        };
+        write!(f, "[{}] {}: {}", label, self.name, self.detail)
+    }
+}
+
+/// Parses and validates `cfg.toml` in `config_dir` without creating or rewriting it, unlike [`config::get_config`]
+pub fn check_config(config_dir: &str) -> (Check, Option<Config>) {
+    let config_path = format!("{}/cfg.toml", config_dir);
+    let name = format!("Configuration {}", config_path);
+    if !Path::new(&config_path).exists() {
+        let detail = "doesn't exist yet, `listen` creates it with the defaults";
+        return (Check::new(name, Outcome::Warning, detail), None);
+    }
+    match config::reload_config(config_dir) {
+        Ok(config) => (Check::new(name, Outcome::Passed, "valid"), Some(config)),
+        Err(e) => (Check::new(name, Outcome::Failed, format!("{:#}", e)), None),
+    }
+}
+
+/// Whether `server-icon.png` loads. A missing icon is fine, an unusable one is shown as no icon at all
+pub fn check_server_icon(config: &Config) -> Check {
+    let icon_path = format!("{}/server-icon.png", config.config_dir());
+    let name = format!("Server icon {}", icon_path);
+    if config.server_icon.is_some() {
+        Check::new(name, Outcome::Passed, "loaded and resized to 64x64")
+    } else if Path::new(&icon_path).exists() {
+        Check::new(name, Outcome::Warning, "can't be used, see the log above")
+    } else {
+        Check::new(name, Outcome::Skipped, "no icon")
+    }
+}
+
+/// Serializes the MOTD and the connection message like `listen` does on startup
+pub fn check_packets(config: &Config) -> Check {
+    let packets = PreserializedPackets::new(config);
+    let name = "MOTD and connection message";
+    let invalid_components: Vec<_> = [
+        ("motd_component", &config.motd_component),
+        ("connection_msg_component", &config.connection_msg_component),
+    ]
+    .into_iter()
+    .filter(|(field, raw)| {
+        raw.as_deref()
+            .is_some_and(|raw| parse_text_component(raw, field).is_none())
+    })
+    .map(|(field, _)| field)
+    .collect();
+    if invalid_components.is_empty() {
+        Check::new(
+            name,
+            Outcome::Passed,
+            format!(
+                "{} and {} bytes",
+                packets.motd_packet.len(),
+                packets.starting_message_packet.len()
+            ),
+        )
+    } else {
+        Check::new(
+            name,
+            Outcome::Warning,
+            format!(
+                "{} invalid, the simple text fields are used instead",
+                invalid_components.join(" and ")
+            ),
+        )
+    }
+}
+
+/// Whether `server.properties` leaves RCON enabled, which the idle watchdog needs unless it uses the console
+pub fn check_rcon_enabled(config: &Config) -> Check {
+    let name = format!("RCON in {}", config.server_properties_path);
+    match server_properties::parse(&config.server_properties_path) {
+        Err(_) => Check::new(name, Outcome::Skipped, "server.properties not found"),
+        Ok(properties) if properties.enable_rcon == Some(false) => {
+            if config.player_count_source == PlayerCountSource::Rcon {
+                Check::new(
+                    name,
+                    Outcome::Failed,
+                    "disabled (enable-rcon=false), the server is never stopped when idle",
+                )
+            } else {
+                Check::new(
+                    name,
+                    Outcome::Passed,
+                    "disabled, the console is used instead",
+                )
+            }
+        }
+        Ok(_) => Check::new(name, Outcome::Passed, "enabled"),
+    }
+}
+
+/// Binds `addr` and releases it right away
+pub async fn check_listen_addr(addr: SocketAddr) -> Check {
+    let name = format!("Listen address {}", addr);
+    match TcpListener::bind(addr).await {
+        Ok(_) => Check::new(name, Outcome::Passed, "bindable"),
+        Err(e) if e.kind() == ErrorKind::AddrInUse => Check::new(
+            name,
+            Outcome::Failed,
+            "already in use, e.g. by a running `listen` instance or the server itself",
+        ),
+        Err(e) => Check::new(name, Outcome::Failed, e.to_string()),
+    }
+}
+
+/// Whether the Minecraft server is up right now. It being down is expected, it's just napping
+pub async fn check_server(config: &Config, server_port: u16) -> Check {
+    let server_addr = config.server_addr(server_port);
+    let name = format!("Minecraft server {}", server_addr);
+    match timeout(CONNECT_TIMEOUT, TcpStream::connect(&server_addr)).await {
+        Ok(Ok(_)) => Check::new(name, Outcome::Passed, "running"),
+        Ok(Err(e)) => Check::new(name, Outcome::Skipped, format!("not running ({})", e)),
+        Err(_) => Check::new(name, Outcome::Skipped, "not running (timed out)"),
+    }
+}
+
+/// Logs in to RCON if the server is running. Wrong credentials fail, a stopped server is skipped
+pub async fn check_rcon(config: &Config, rcon_port: u16, rcon_pass: &str) -> Check {
+    let rcon_addr = config.rcon_addr(rcon_port);
+    let name = format!("RCON {}", rcon_addr);
+    match timeout(
+        CONNECT_TIMEOUT,
+        Connection::<TcpStream>::connect(&rcon_addr, rcon_pass),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Check::new(name, Outcome::Passed, "logged in"),
+        Ok(Err(rcon::Error::Auth)) => Check::new(name, Outcome::Failed, "wrong password"),
+        Ok(Err(rcon::Error::Io(e))) if e.kind() == ErrorKind::ConnectionRefused => Check::new(
+            name,
+            Outcome::Skipped,
+            "server is not running, start it once to check the credentials",
+        ),
+        Ok(Err(e)) => Check::new(name, Outcome::Failed, e.to_string()),
+        Err(_) => Check::new(name, Outcome::Failed, "timed out"),
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{Context, Result, bail};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{Duration, Instant, sleep, timeout};
+
+/// Parses a MAC address like `00:11:22:aa:bb:cc`. Dashes are accepted as separators too
+pub fn parse_mac(mac: &str) -> Result<[u8; 6]> {
+    let parts: Vec<&str> = mac.trim().split([':', '-']).collect();
+    if parts.len() != 6 {
+        bail!("`{}` is not a MAC address like 00:11:22:aa:bb:cc", mac);
+    }
+    let mut bytes = [0u8; 6];
+    for (byte, part) in bytes.iter_mut().zip(parts) {
+        if part.len() != 2 {
+            bail!("`{}` is not a MAC address like 00:11:22:aa:bb:cc", mac);
+        }
+        *byte = u8::from_str_radix(part, 16)
+            .with_context(|| format!("`{}` is not a MAC address like 00:11:22:aa:bb:cc", mac))?;
+    }
+    Ok(bytes)
+}
+
+/// The Wake-on-LAN magic packet: 6 times 0xFF followed by the MAC address 16 times
+pub fn magic_packet(mac: [u8; 6]) -> [u8; 102] {
+    let mut packet = [0xFF; 102];
+    for chunk in packet[6..].chunks_exact_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Broadcasts the magic packet for `mac` to `broadcast_addr`, e.g. `255.255.255.255:9`
+pub async fn send_magic_packet(mac: &str, broadcast_addr: &str) -> Result<()> {
+    let packet = magic_packet(parse_mac(mac)?);
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .context("Cannot open a UDP socket for Wake-on-LAN")?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&packet, broadcast_addr)
+        .await
+        .with_context(|| format!("Cannot send the Wake-on-LAN packet to {}", broadcast_addr))?;
+    log::info!("Sent Wake-on-LAN packet for {} to {}", mac, broadcast_addr);
+    Ok(())
+}
+
+/// Waits until `addr` accepts TCP connections, e.g. SSH once the woken machine booted
+pub async fn wait_until_reachable(addr: &str, max_duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + max_duration;
+    loop {
+        if let Ok(Ok(_)) = timeout(Duration::from_secs(2), TcpStream::connect(addr)).await {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            bail!("{} wasn't reachable within {:?}", addr, max_duration);
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
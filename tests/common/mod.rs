@@ -0,0 +1,99 @@
+//! Helpers shared by the integration tests: a mock Minecraft client speaking just enough of the protocol,
+//! and temporary directories
+#![allow(dead_code)]
+
+use mcservernap::{read_varint, write_varint};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Protocol version of Minecraft 1.21 used by the mock client
+pub const PROTOCOL_VERSION: i32 = 767;
+
+/// A connected pair of sockets: the mock client's end and the accepted end with the client's address
+pub async fn connected_pair() -> (TcpStream, TcpStream, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (server, peer) = listener.accept().await.unwrap();
+    (client, server, peer)
+}
+
+/// Prefixes `id` and `payload` with the packet length
+pub fn packet(id: i32, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_varint(id, &mut body);
+    body.extend_from_slice(payload);
+    let mut packet = Vec::new();
+    write_varint(body.len() as i32, &mut packet);
+    packet.extend_from_slice(&body);
+    packet
+}
+
+pub fn write_string(value: &str, buf: &mut Vec<u8>) {
+    write_varint(value.len() as i32, buf);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Handshake packet with `next_state` 1 (status), 2 (login) or 3 (transfer)
+pub fn handshake(protocol_version: i32, host: &str, port: u16, next_state: i32) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_varint(protocol_version, &mut payload);
+    write_string(host, &mut payload);
+    payload.extend_from_slice(&port.to_be_bytes());
+    write_varint(next_state, &mut payload);
+    packet(0x00, &payload)
+}
+
+pub fn status_request() -> Vec<u8> {
+    packet(0x00, &[])
+}
+
+pub fn ping_request(payload: i64) -> Vec<u8> {
+    packet(0x01, &payload.to_be_bytes())
+}
+
+/// Login start packet of 1.20.2+: the username and the player's UUID
+pub fn login_start(username: &str) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_string(username, &mut payload);
+    payload.extend_from_slice(&[0u8; 16]);
+    packet(0x00, &payload)
+}
+
+/// Reads one packet and returns its ID and payload
+pub async fn read_packet(socket: &mut TcpStream) -> (i32, Vec<u8>) {
+    let mut length = Vec::new();
+    loop {
+        length.push(socket.read_u8().await.unwrap());
+        if let Some((length, _)) = read_varint(&length) {
+            let mut body = vec![0u8; length as usize];
+            socket.read_exact(&mut body).await.unwrap();
+            let (id, id_size) = read_varint(&body).unwrap();
+            return (id, body[id_size..].to_vec());
+        }
+    }
+}
+
+/// Reads a packet holding a single string, like the status response, and returns the string
+pub async fn read_string_packet(socket: &mut TcpStream) -> (i32, String) {
+    let (id, payload) = read_packet(socket).await;
+    let (length, length_size) = read_varint(&payload).unwrap();
+    let text = &payload[length_size..length_size + length as usize];
+    (id, String::from_utf8(text.to_vec()).unwrap())
+}
+
+/// Sends `bytes` from the mock client
+pub async fn send(socket: &mut TcpStream, bytes: &[u8]) {
+    socket.write_all(bytes).await.unwrap();
+}
+
+/// An empty directory for one test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mcservernap-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,110 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::NapServer;
+use mcservernap::config::{self, ReadinessMode};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+fn launches(path: &Path) -> usize {
+    std::fs::read_to_string(path)
+        .map(|launches| launches.lines().count())
+        .unwrap_or(0)
+}
+
+fn login(port: u16) -> Vec<u8> {
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", port, 2);
+    bytes.extend(login_start("Steve"));
+    bytes
+}
+
+#[tokio::test]
+async fn slow_handshakes_stall_neither_status_pings_nor_the_launch() {
+    let dir = temp_dir("concurrent-handshakes");
+    let launches_path = dir.join("launches");
+    let mut config = config::get_config(Some(&dir)).unwrap();
+    // Stays starting, it never reports being ready
+    config.readiness_mode = ReadinessMode::Log;
+    config.status_rate_limit = 0;
+    config.timeouts.handshake = 10;
+    config.detect_running_server = false;
+
+    let listen_port = {
+        let free = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        free.local_addr().unwrap().port()
+    };
+    let server = NapServer::builder()
+        .listen_addr(format!("127.0.0.1:{}", listen_port).parse().unwrap())
+        .command(
+            "/bin/sh",
+            vec![
+                "-c".to_string(),
+                format!("echo launched >> {}; sleep 10", launches_path.display()),
+            ],
+        )
+        .server_port(25566)
+        .rcon(25575, "secret")
+        .config(config)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+    sleep(Duration::from_millis(300)).await;
+
+    // Clients that send only the start of their login and then stall
+    let mut slow_clients = Vec::new();
+    for _ in 0..50 {
+        let mut client = TcpStream::connect(("127.0.0.1", listen_port))
+            .await
+            .unwrap();
+        send(&mut client, &login(listen_port)[..3]).await;
+        slow_clients.push(client);
+    }
+
+    // Status pings are still answered right away
+    for _ in 0..5 {
+        let mut client = TcpStream::connect(("127.0.0.1", listen_port))
+            .await
+            .unwrap();
+        send(
+            &mut client,
+            &handshake(PROTOCOL_VERSION, "localhost", listen_port, 1),
+        )
+        .await;
+        send(&mut client, &status_request()).await;
+        let (id, json) = timeout(Duration::from_secs(2), read_string_packet(&mut client))
+            .await
+            .expect("status ping stalled by slow handshakes");
+        assert_eq!(id, 0x00);
+        assert!(json.contains("description"));
+    }
+
+    // Many players joining at once wake the server once
+    let joins = (0..20).map(|_| async move {
+        let mut client = TcpStream::connect(("127.0.0.1", listen_port))
+            .await
+            .unwrap();
+        send(&mut client, &login(listen_port)).await;
+        let _ = timeout(Duration::from_secs(5), client.read_to_end(&mut Vec::new())).await;
+    });
+    join_all(joins).await;
+
+    // The stalled clients finish their logins while the server is starting
+    for client in &mut slow_clients {
+        send(client, &login(listen_port)[3..]).await;
+    }
+    sleep(Duration::from_millis(500)).await;
+    assert_eq!(launches(&launches_path), 1);
+}
+
+/// Runs the futures concurrently and waits for all of them
+async fn join_all<F: Future<Output = ()> + Send + 'static>(futures: impl Iterator<Item = F>) {
+    let handles: Vec<_> = futures.map(tokio::spawn).collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
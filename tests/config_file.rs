@@ -0,0 +1,34 @@
+mod common;
+
+use common::*;
+use mcservernap::config;
+use std::time::Duration;
+
+#[test]
+fn unchanged_config_is_not_rewritten() {
+    let dir = temp_dir("config-unchanged");
+    let cfg_path = dir.join("cfg.toml");
+    config::get_config(Some(&dir)).unwrap();
+    let written = std::fs::metadata(&cfg_path).unwrap().modified().unwrap();
+
+    // Coarse file system timestamps would hide a rewrite otherwise
+    std::thread::sleep(Duration::from_millis(1100));
+    config::get_config(Some(&dir)).unwrap();
+    assert_eq!(
+        std::fs::metadata(&cfg_path).unwrap().modified().unwrap(),
+        written
+    );
+}
+
+#[test]
+fn missing_options_are_added() {
+    let dir = temp_dir("config-missing-options");
+    let cfg_path = dir.join("cfg.toml");
+    std::fs::write(&cfg_path, "rcon_idle_timeout = 600\n").unwrap();
+
+    let loaded = config::get_config(Some(&dir)).unwrap();
+    assert_eq!(loaded.rcon_idle_timeout, 600);
+    let contents = std::fs::read_to_string(&cfg_path).unwrap();
+    assert!(contents.contains("rcon_idle_timeout = 600"));
+    assert!(contents.contains("motd_text"));
+}
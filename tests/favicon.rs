@@ -0,0 +1,95 @@
+mod common;
+
+use base64::Engine;
+use base64::engine::general_purpose;
+use common::*;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use mcservernap::config::{self, Config};
+use mcservernap::preserialized_packets::{PreserializedPackets, validate_favicon};
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = DynamicImage::ImageRgba8(RgbaImage::new(width, height));
+    let mut png = Vec::new();
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+fn png_base64(width: u32, height: u32) -> String {
+    general_purpose::STANDARD.encode(png(width, height))
+}
+
+/// Loads the configuration of a directory holding a `server-icon.png` of the given size, returning the icon and
+/// whether the file is still the same
+fn load_icon(name: &str, width: u32, height: u32) -> (Option<String>, bool) {
+    let dir = temp_dir(name);
+    let icon_path = dir.join("server-icon.png");
+    let original = png(width, height);
+    std::fs::write(&icon_path, &original).unwrap();
+    let loaded = config::get_config(Some(&dir)).unwrap();
+    (
+        loaded.server_icon,
+        std::fs::read(&icon_path).unwrap() == original,
+    )
+}
+
+fn motd_contains_favicon(server_icon: String) -> bool {
+    let mut config = Config::default();
+    config.server_icon = Some(server_icon);
+    let packets = PreserializedPackets::new(&config);
+    packets
+        .motd_packet
+        .windows(b"favicon".len())
+        .any(|window| window == b"favicon")
+}
+
+#[test]
+fn icon_of_64x64_is_valid() {
+    assert!(validate_favicon(&png_base64(64, 64)).is_ok());
+    assert!(motd_contains_favicon(png_base64(64, 64)));
+}
+
+#[test]
+fn icon_of_another_size_is_omitted() {
+    let error = validate_favicon(&png_base64(128, 64)).unwrap_err();
+    assert!(error.to_string().contains("128x64"), "{}", error);
+    assert!(!motd_contains_favicon(png_base64(128, 64)));
+}
+
+#[test]
+fn non_png_icon_is_omitted() {
+    assert!(validate_favicon("not base64!").is_err());
+    assert!(
+        validate_favicon(&general_purpose::STANDARD.encode(b"GIF89a and so on..........")).is_err()
+    );
+    assert!(!motd_contains_favicon("aGVsbG8=".to_string()));
+}
+
+#[test]
+fn oversized_icon_is_omitted() {
+    let mut server_icon = png_base64(64, 64);
+    server_icon.push_str(&"A".repeat(40_000));
+    assert!(validate_favicon(&server_icon).is_err());
+}
+
+#[test]
+fn larger_icon_is_scaled_in_memory_only() {
+    let (server_icon, unchanged) = load_icon("icon-larger", 128, 128);
+    assert!(validate_favicon(&server_icon.unwrap()).is_ok());
+    assert!(unchanged);
+}
+
+#[test]
+fn non_square_icon_is_stretched_to_64x64() {
+    let (server_icon, unchanged) = load_icon("icon-non-square", 200, 50);
+    assert!(validate_favicon(&server_icon.unwrap()).is_ok());
+    assert!(unchanged);
+}
+
+#[test]
+fn icon_of_64x64_is_left_as_it_is() {
+    let (server_icon, unchanged) = load_icon("icon-64x64", 64, 64);
+    assert!(validate_favicon(&server_icon.unwrap()).is_ok());
+    assert!(unchanged);
+}
@@ -0,0 +1,279 @@
+mod common;
+
+use common::*;
+use mcservernap::config::Config;
+use mcservernap::preserialized_packets::PreserializedPackets;
+use mcservernap::{handshake_host, verify_handshake_packet, verify_handshake_packet_buffered};
+use std::time::Duration;
+
+fn test_config() -> Config {
+    let mut config = Config::default();
+    config.motd_text = "Napping test server".to_string();
+    config
+}
+
+#[tokio::test]
+async fn status_ping_returns_motd_json_and_pong() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let server_side = verify_handshake_packet(&mut server, peer, &config, &packets);
+    let client_side = async {
+        send(
+            &mut client,
+            &handshake(PROTOCOL_VERSION, "localhost", 25565, 1),
+        )
+        .await;
+        send(&mut client, &status_request()).await;
+        let (id, json) = read_string_packet(&mut client).await;
+        send(&mut client, &ping_request(42)).await;
+        let pong = read_packet(&mut client).await;
+        (id, json, pong)
+    };
+    let (woken, (id, json, pong)) = tokio::join!(server_side, client_side);
+
+    assert!(!woken.unwrap());
+    assert_eq!(id, 0x00);
+    let status: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert!(
+        status["description"]
+            .to_string()
+            .contains("Napping test server")
+    );
+    assert!(status["players"]["max"].is_number());
+    assert_eq!(pong, (0x01, 42i64.to_be_bytes().to_vec()));
+}
+
+#[tokio::test]
+async fn status_request_sent_with_the_handshake_is_answered() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", 25565, 1);
+    bytes.extend(status_request());
+    let server_side = verify_handshake_packet(&mut server, peer, &config, &packets);
+    let client_side = async {
+        send(&mut client, &bytes).await;
+        let (_, json) = read_string_packet(&mut client).await;
+        send(&mut client, &ping_request(1)).await;
+        read_packet(&mut client).await;
+        json
+    };
+    let (woken, json) = tokio::join!(server_side, client_side);
+
+    assert!(!woken.unwrap());
+    assert!(serde_json::from_str::<serde_json::Value>(&json).is_ok());
+}
+
+#[tokio::test]
+async fn login_handshake_wakes() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", 25565, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(woken.unwrap());
+}
+
+#[tokio::test]
+async fn handshake_split_across_reads_wakes() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let bytes = handshake(PROTOCOL_VERSION, "localhost", 25565, 2);
+    let (first, second) = bytes.split_at(3);
+    let server_side = verify_handshake_packet(&mut server, peer, &config, &packets);
+    let client_side = async {
+        send(&mut client, first).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        send(&mut client, second).await;
+    };
+    let (woken, ()) = tokio::join!(server_side, client_side);
+
+    assert!(woken.unwrap());
+}
+
+#[tokio::test]
+async fn unknown_next_state_is_rejected() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    send(
+        &mut client,
+        &handshake(PROTOCOL_VERSION, "localhost", 25565, 7),
+    )
+    .await;
+
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(!woken.unwrap());
+}
+
+#[tokio::test]
+async fn malformed_packet_is_rejected() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    // Packet ID 0x05 instead of a handshake, followed by garbage
+    send(&mut client, &packet(0x05, b"not a handshake")).await;
+
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(!woken.unwrap());
+}
+
+#[tokio::test]
+async fn oversized_length_is_rejected() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    // A length prefix of 2 MiB, far beyond any handshake
+    send(&mut client, &[0x80, 0x80, 0x80, 0x01, 0x00]).await;
+
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(!woken.unwrap());
+}
+
+#[tokio::test]
+async fn connection_closed_before_handshake_is_rejected() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (client, mut server, peer) = connected_pair().await;
+
+    drop(client);
+
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(!woken.unwrap_or(false));
+}
+
+#[tokio::test]
+async fn login_below_min_protocol_version_is_rejected_with_a_message() {
+    let mut config = test_config();
+    config.min_protocol_version = Some(PROTOCOL_VERSION + 1);
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", 25565, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+
+    let server_side = verify_handshake_packet(&mut server, peer, &config, &packets);
+    let (woken, (id, json)) = tokio::join!(server_side, read_string_packet(&mut client));
+    assert!(!woken.unwrap());
+    assert_eq!(id, 0x00);
+    assert!(json.contains(&config.outdated_client_msg_text));
+}
+
+#[tokio::test]
+async fn login_at_min_protocol_version_wakes() {
+    let mut config = test_config();
+    config.min_protocol_version = Some(PROTOCOL_VERSION);
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    send(
+        &mut client,
+        &handshake(PROTOCOL_VERSION, "localhost", 25565, 2),
+    )
+    .await;
+
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(woken.unwrap());
+}
+
+#[tokio::test]
+async fn length_over_max_handshake_bytes_is_rejected_right_away() {
+    let mut config = test_config();
+    config.max_handshake_bytes = 1024;
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    // A length prefix of 4096 bytes, followed by only the start of the packet. The connection stays open, so
+    // waiting for the rest would only end with the handshake timeout
+    send(&mut client, &[0x80, 0x20, 0x00, 0x00]).await;
+
+    let woken = tokio::time::timeout(
+        Duration::from_secs(1),
+        verify_handshake_packet(&mut server, peer, &config, &packets),
+    )
+    .await
+    .expect("the handshake wasn't rejected right away");
+    assert!(!woken.unwrap());
+}
+
+#[tokio::test]
+async fn long_handshake_within_max_handshake_bytes_wakes() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    // Proxies forwarding player data append it to the address
+    let address = format!("localhost\0{}", "a".repeat(10_000));
+    let bytes = handshake(PROTOCOL_VERSION, &address, 25565, 2);
+    let server_side = verify_handshake_packet(&mut server, peer, &config, &packets);
+    let client_side = send(&mut client, &bytes);
+    let (woken, ()) = tokio::join!(server_side, client_side);
+
+    assert!(woken.unwrap());
+}
+
+#[test]
+fn handshake_host_strips_forge_marker_and_trailing_dot() {
+    assert_eq!(
+        handshake_host("survival.example.com"),
+        "survival.example.com"
+    );
+    assert_eq!(
+        handshake_host("Survival.Example.com.\0FML3\0"),
+        "survival.example.com"
+    );
+    assert_eq!(handshake_host("127.0.0.1\0FML\0"), "127.0.0.1");
+    assert_eq!(handshake_host(""), "");
+}
+
+#[tokio::test]
+async fn login_handshake_returns_the_requested_host() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let mut bytes = handshake(PROTOCOL_VERSION, "creative.example.com\0FML2\0", 25565, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+
+    let login = verify_handshake_packet_buffered(&mut server, peer, &config, &packets, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(login.host, "creative.example.com");
+}
+
+#[tokio::test]
+async fn forge_login_over_the_old_size_limit_wakes_with_the_marker_stripped() {
+    let config = test_config();
+    let packets = PreserializedPackets::new(&config);
+    for marker in ["FML", "FML2", "FML3"] {
+        let (mut client, mut server, peer) = connected_pair().await;
+        // Forge marker followed by data a proxy forwards, past the former 4096 byte limit
+        let address = format!("Play.Example.com\0{}\0{}", marker, "x".repeat(5000));
+        let mut bytes = handshake(PROTOCOL_VERSION, &address, 25565, 2);
+        assert!(bytes.len() > 4096);
+        bytes.extend(login_start("Steve"));
+
+        let server_side =
+            verify_handshake_packet_buffered(&mut server, peer, &config, &packets, None);
+        let client_side = send(&mut client, &bytes);
+        let (login, ()) = tokio::join!(server_side, client_side);
+
+        let login = login.unwrap().expect(marker);
+        assert_eq!(login.host, "play.example.com");
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::{Local, TimeDelta};
+use mcservernap::IdleTimer;
+use mcservernap::config::{Config, IdleTimeoutWindow};
+use std::time::Duration;
+
+/// A time window from `start` to `end` hours relative to now, e.g. `-1, 1` for the two hours around now
+fn window_around_now(start: i64, end: i64) -> String {
+    let now = Local::now().time();
+    let format = |hours| (now + TimeDelta::hours(hours)).format("%H:%M").to_string();
+    format!("{}-{}", format(start), format(end))
+}
+
+fn config_with_schedule(schedule: Vec<IdleTimeoutWindow>) -> Config {
+    let mut config = Config::default();
+    config.rcon_idle_timeout = 300;
+    config.idle_timeout_schedule = schedule;
+    config
+}
+
+#[test]
+fn no_schedule_uses_rcon_idle_timeout() {
+    let timer = IdleTimer::from_config(&config_with_schedule(Vec::new()));
+    assert_eq!(timer.idle_timeout(), Duration::from_secs(300));
+}
+
+#[test]
+fn active_window_overrides_the_idle_timeout() {
+    let timer = IdleTimer::from_config(&config_with_schedule(vec![
+        IdleTimeoutWindow {
+            window: window_around_now(2, 3),
+            idle_timeout: 60,
+        },
+        IdleTimeoutWindow {
+            window: window_around_now(-1, 1),
+            idle_timeout: 1800,
+        },
+    ]));
+    assert_eq!(timer.idle_timeout(), Duration::from_secs(1800));
+}
+
+#[test]
+fn inactive_windows_fall_back_to_rcon_idle_timeout() {
+    let timer = IdleTimer::from_config(&config_with_schedule(vec![IdleTimeoutWindow {
+        window: window_around_now(2, 3),
+        idle_timeout: 1800,
+    }]));
+    assert_eq!(timer.idle_timeout(), Duration::from_secs(300));
+}
+
+#[test]
+fn invalid_window_is_rejected() {
+    let mut config = config_with_schedule(vec![IdleTimeoutWindow {
+        window: "evenings".to_string(),
+        idle_timeout: 1800,
+    }]);
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,53 @@
+use mcservernap::config::Config;
+use std::net::IpAddr;
+
+fn config_with_cidrs(allow: &[&str], deny: &[&str]) -> Config {
+    let mut config = Config::default();
+    config.allow_cidrs = allow.iter().map(|net| net.parse().unwrap()).collect();
+    config.deny_cidrs = deny.iter().map(|net| net.parse().unwrap()).collect();
+    config
+}
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+#[test]
+fn empty_lists_allow_everyone() {
+    let config = config_with_cidrs(&[], &[]);
+    assert!(config.is_ip_allowed(ip("203.0.113.7")));
+    assert!(config.is_ip_allowed(ip("2001:db8::1")));
+}
+
+#[test]
+fn allow_cidrs_restrict_to_the_listed_ranges() {
+    let config = config_with_cidrs(&["192.168.1.0/24", "2001:db8::/32"], &[]);
+    assert!(config.is_ip_allowed(ip("192.168.1.42")));
+    assert!(config.is_ip_allowed(ip("2001:db8::1")));
+    assert!(!config.is_ip_allowed(ip("192.168.2.1")));
+    assert!(!config.is_ip_allowed(ip("2001:db9::1")));
+}
+
+#[test]
+fn deny_cidrs_take_precedence() {
+    let config = config_with_cidrs(&["10.0.0.0/8"], &["10.1.0.0/16"]);
+    assert!(config.is_ip_allowed(ip("10.2.3.4")));
+    assert!(!config.is_ip_allowed(ip("10.1.2.3")));
+
+    let config = config_with_cidrs(&[], &["198.51.100.0/24"]);
+    assert!(!config.is_ip_allowed(ip("198.51.100.9")));
+    assert!(config.is_ip_allowed(ip("198.51.101.9")));
+}
+
+#[test]
+fn ipv4_mapped_addresses_match_ipv4_ranges() {
+    let config = config_with_cidrs(&["192.168.1.0/24"], &[]);
+    assert!(config.is_ip_allowed(ip("::ffff:192.168.1.42")));
+}
+
+#[test]
+fn malformed_cidrs_are_rejected_when_parsing() {
+    assert!(toml::from_str::<Config>(r#"allow_cidrs = ["192.168.1.0/24"]"#).is_ok());
+    assert!(toml::from_str::<Config>(r#"allow_cidrs = ["192.168.1.0/33"]"#).is_err());
+    assert!(toml::from_str::<Config>(r#"deny_cidrs = ["scanner"]"#).is_err());
+}
@@ -0,0 +1,13 @@
+#![cfg(unix)]
+
+use mcservernap::stop::kill_server_process;
+
+#[tokio::test]
+async fn killed_server_process_has_exited() {
+    let mut child = tokio::process::Command::new("sleep")
+        .arg("60")
+        .spawn()
+        .unwrap();
+    kill_server_process(&mut child).await.unwrap();
+    assert!(child.try_wait().unwrap().is_some());
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::*;
+use mcservernap::config::{self, Config};
+use mcservernap::{NapServer, NapServerBuilder};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+fn builder(listen_port: u16, server_port: u16, config: Config) -> NapServerBuilder {
+    NapServer::builder()
+        .listen_addr(format!("127.0.0.1:{}", listen_port).parse().unwrap())
+        .command("java", Vec::new())
+        .server_port(server_port)
+        .rcon(25575, "secret")
+        .config(config)
+}
+
+#[test]
+fn same_listen_and_server_port_is_rejected() {
+    let error = builder(25565, 25565, Config::default())
+        .build()
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("both 25565"));
+}
+
+#[test]
+fn same_port_on_a_remote_server_is_allowed() {
+    let mut config = Config::default();
+    config.server_host = "192.0.2.10".to_string();
+    assert!(builder(25565, 25565, config).build().is_ok());
+}
+
+#[tokio::test]
+async fn port_in_use_is_explained() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let server = builder(port, 25566, Config::default()).build().unwrap();
+    let error = server.run().await.err().unwrap();
+    assert!(error.to_string().contains("already in use"), "{}", error);
+}
+
+#[test]
+fn same_port_on_localhost_is_rejected() {
+    let mut config = Config::default();
+    config.server_host = "localhost".to_string();
+    assert!(builder(25565, 25565, config).build().is_err());
+}
+
+#[test]
+fn same_port_with_handoff_listener_is_allowed() {
+    let mut config = Config::default();
+    config.handoff_listener = true;
+    assert!(builder(25565, 25565, config).build().is_ok());
+}
+
+/// The MOTD a status ping to `port` is answered with, None while nothing accepts connections
+async fn motd(port: u16) -> Option<String> {
+    let mut client = TcpStream::connect(("127.0.0.1", port)).await.ok()?;
+    send(
+        &mut client,
+        &handshake(PROTOCOL_VERSION, "localhost", port, 1),
+    )
+    .await;
+    send(&mut client, &status_request()).await;
+    let (_, json) = read_string_packet(&mut client).await;
+    Some(json)
+}
+
+#[tokio::test]
+async fn reloaded_handoff_config_with_the_same_port_is_applied() {
+    let dir = temp_dir("reload-handoff");
+    let cfg_path = dir.join("cfg.toml");
+    std::fs::write(
+        &cfg_path,
+        "handoff_listener = true\nwatch_config = true\nmotd_text = \"Before reload\"\n",
+    )
+    .unwrap();
+    let config = config::get_config(Some(&dir)).unwrap();
+    let port = {
+        let free = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        free.local_addr().unwrap().port()
+    };
+    let server = builder(port, port, config).build().unwrap();
+    tokio::spawn(server.run());
+
+    let mut before = None;
+    for _ in 0..50 {
+        before = motd(port).await;
+        if before.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(before.unwrap().contains("Before reload"));
+
+    // Coarse file system timestamps would hide the change otherwise
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let contents = std::fs::read_to_string(&cfg_path).unwrap();
+    std::fs::write(&cfg_path, contents.replace("Before reload", "After reload")).unwrap();
+    for _ in 0..50 {
+        if motd(port).await.unwrap().contains("After reload") {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    panic!("The reloaded configuration wasn't applied");
+}
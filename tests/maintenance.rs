@@ -0,0 +1,31 @@
+use mcservernap::config::Config;
+use mcservernap::preserialized_packets::PreserializedPackets;
+use mcservernap::read_varint;
+
+/// The `description` of a status response packet
+fn description_of(packet: &[u8]) -> serde_json::Value {
+    let (_, length_size) = read_varint(packet).unwrap();
+    // Packet ID 0, then the JSON string
+    let payload = &packet[length_size + 1..];
+    let (_, string_length_size) = read_varint(payload).unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&payload[string_length_size..]).unwrap();
+    status["description"].clone()
+}
+
+#[test]
+fn maintenance_motd_replaces_the_motd() {
+    let mut config = Config::default();
+    config.motd_text_line2 = Some("Second line".to_string());
+    config.maintenance_mode = true;
+    let packets = PreserializedPackets::new(&config);
+    let description = description_of(&packets.motd_packet_for(767));
+    assert_eq!(description["text"], "Under maintenance, back soon");
+    assert!(description.get("extra").is_none());
+}
+
+#[test]
+fn motd_is_shown_without_maintenance() {
+    let packets = PreserializedPackets::new(&Config::default());
+    let description = description_of(&packets.motd_packet_for(767));
+    assert_eq!(description["text"], Config::default().motd_text);
+}
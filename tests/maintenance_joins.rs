@@ -0,0 +1,124 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::NapServer;
+use mcservernap::config::{self, Config, ReadinessMode};
+use mcservernap::control::send_control_command;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+async fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+fn launches(path: &Path) -> usize {
+    std::fs::read_to_string(path)
+        .map(|launches| launches.lines().count())
+        .unwrap_or(0)
+}
+
+/// Runs a server with `configure` applied to its configuration and returns its listen port and the file launches
+/// are recorded in
+async fn run_server(name: &str, configure: impl FnOnce(&mut Config)) -> (u16, PathBuf) {
+    let dir = temp_dir(name);
+    let launches_path = dir.join("launches");
+    let mut config = config::get_config(Some(&dir)).unwrap();
+    // Stays starting, it never reports being ready
+    config.readiness_mode = ReadinessMode::Log;
+    config.maintenance_msg_text = "Down for maintenance".to_string();
+    config.detect_running_server = false;
+    configure(&mut config);
+
+    let listen_port = free_port().await;
+    let server = NapServer::builder()
+        .listen_addr(format!("127.0.0.1:{}", listen_port).parse().unwrap())
+        .command(
+            "/bin/sh",
+            vec![
+                "-c".to_string(),
+                format!("echo launched >> {}; sleep 10", launches_path.display()),
+            ],
+        )
+        .server_port(free_port().await)
+        .rcon(free_port().await, "secret")
+        .config(config)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+    sleep(Duration::from_millis(300)).await;
+    (listen_port, launches_path)
+}
+
+/// Joins as Steve and returns the first packet sent back
+async fn join(port: u16) -> (i32, Vec<u8>) {
+    let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", port, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+    timeout(Duration::from_secs(5), read_packet(&mut client))
+        .await
+        .expect("nothing was sent back")
+}
+
+fn is_maintenance_message((id, payload): &(i32, Vec<u8>)) -> bool {
+    *id == 0x00 && String::from_utf8_lossy(payload).contains("Down for maintenance")
+}
+
+#[tokio::test]
+async fn joins_during_maintenance_do_not_wake_the_server() {
+    let (listen_port, launches_path) = run_server("maintenance-join", |config| {
+        config.maintenance_mode = true;
+    })
+    .await;
+
+    for _ in 0..2 {
+        assert!(is_maintenance_message(&join(listen_port).await));
+    }
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(launches(&launches_path), 0);
+}
+
+#[tokio::test]
+async fn maintenance_is_toggled_through_the_control_channel() {
+    let control_port = free_port().await;
+    let (listen_port, launches_path) = run_server("maintenance-control", |config| {
+        config.control_port = control_port;
+    })
+    .await;
+    let command = |command| send_control_command(control_port, command, Duration::from_secs(5));
+
+    assert_eq!(command("maintenance on").await.unwrap(), "maintenance on");
+    assert!(is_maintenance_message(&join(listen_port).await));
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(launches(&launches_path), 0);
+
+    assert_eq!(command("maintenance off").await.unwrap(), "maintenance off");
+    assert!(!is_maintenance_message(&join(listen_port).await));
+    for _ in 0..50 {
+        if launches(&launches_path) == 1 {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(launches(&launches_path), 1);
+}
+
+#[tokio::test]
+async fn transfer_redirect_is_used_during_maintenance() {
+    let (listen_port, launches_path) = run_server("maintenance-redirect", |config| {
+        config.maintenance_mode = true;
+        config.transfer_redirect = Some("lobby.example.org".to_string());
+    })
+    .await;
+
+    // Login Success, the transfer follows once the login is acknowledged
+    let (id, _) = join(listen_port).await;
+    assert_eq!(id, 0x02);
+    sleep(Duration::from_millis(300)).await;
+    assert_eq!(launches(&launches_path), 0);
+}
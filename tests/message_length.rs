@@ -0,0 +1,78 @@
+mod common;
+
+use common::*;
+use mcservernap::config::{self, Config};
+
+/// Loads a `cfg.toml` with `max_message_length` and the given messages
+fn load(
+    name: &str,
+    max_message_length: usize,
+    motd_text: &str,
+    connection_msg_text: &str,
+) -> Config {
+    let dir = temp_dir(name);
+    std::fs::write(
+        dir.join("cfg.toml"),
+        format!(
+            "max_message_length = {}\nmotd_text = \"{}\"\nmotd_text_line2 = \"{}\"\nconnection_msg_text = \"{}\"\n",
+            max_message_length, motd_text, motd_text, connection_msg_text
+        ),
+    )
+    .unwrap();
+    config::get_config(Some(&dir)).unwrap()
+}
+
+#[test]
+fn messages_are_truncated_at_max_message_length() {
+    let config = load(
+        "message-length-ascii",
+        10,
+        "Napping server, join!",
+        "Starting up, please wait",
+    );
+    assert_eq!(config.motd_text, "Napping se");
+    assert_eq!(config.motd_text_line2.as_deref(), Some("Napping se"));
+    assert_eq!(config.connection_msg_text, "Starting u");
+}
+
+#[test]
+fn messages_within_max_message_length_are_kept() {
+    let config = load("message-length-short", 10, "Napping", "0123456789");
+    assert_eq!(config.motd_text, "Napping");
+    assert_eq!(config.connection_msg_text, "0123456789");
+}
+
+#[test]
+fn multibyte_characters_are_counted_and_never_split() {
+    let config = load(
+        "message-length-multibyte",
+        4,
+        "Schläft 💤 noch",
+        "💤💤💤💤💤",
+    );
+    assert_eq!(config.motd_text, "Schl");
+    assert_eq!(config.connection_msg_text, "💤💤💤💤");
+
+    let config = load("message-length-multibyte-edge", 4, "Grüße", "ab💤c");
+    assert_eq!(config.motd_text, "Grüß");
+    assert_eq!(config.connection_msg_text, "ab💤c");
+}
+
+#[test]
+fn config_file_keeps_the_full_text() {
+    let dir = temp_dir("message-length-file");
+    let cfg_path = dir.join("cfg.toml");
+    std::fs::write(
+        &cfg_path,
+        "max_message_length = 3\nmotd_text = \"Napping\"\n",
+    )
+    .unwrap();
+
+    let config = config::get_config(Some(&dir)).unwrap();
+    assert_eq!(config.motd_text, "Nap");
+    assert!(
+        std::fs::read_to_string(&cfg_path)
+            .unwrap()
+            .contains("motd_text = \"Napping\"")
+    );
+}
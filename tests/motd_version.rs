@@ -0,0 +1,50 @@
+use mcservernap::config::Config;
+use mcservernap::preserialized_packets::{PreserializedPackets, expand_version_name};
+use mcservernap::read_varint;
+
+/// The `version` object of a status response packet
+fn version_of(packet: &[u8]) -> serde_json::Value {
+    let (_, length_size) = read_varint(packet).unwrap();
+    // Packet ID 0, then the JSON string
+    let payload = &packet[length_size + 1..];
+    let (_, string_length_size) = read_varint(payload).unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&payload[string_length_size..]).unwrap();
+    status["version"].clone()
+}
+
+#[test]
+fn protocol_placeholder_is_expanded() {
+    assert_eq!(
+        expand_version_name("Napping ({protocol})", 767),
+        "Napping (767)"
+    );
+    assert_eq!(expand_version_name("Napping", 767), "Napping");
+}
+
+#[test]
+fn default_version_name_is_kept() {
+    let packets = PreserializedPackets::new(&Config::default());
+    let version = version_of(&packets.motd_packet_for(767));
+    assert_eq!(version["name"], "MCServerNap (1.20.5)");
+    assert_eq!(version["protocol"], 767);
+}
+
+#[test]
+fn version_name_shows_the_spoofed_protocol() {
+    let mut config = Config::default();
+    config.motd_version_name = "Sleeping server, protocol {protocol}".to_string();
+    let packets = PreserializedPackets::new(&config);
+    let version = version_of(&packets.motd_packet_for(767));
+    assert_eq!(version["name"], "Sleeping server, protocol 767");
+}
+
+#[test]
+fn version_name_shows_the_fixed_protocol_without_spoofing() {
+    let mut config = Config::default();
+    config.motd_spoof_protocol = false;
+    config.motd_version_name = "Sleeping server, protocol {protocol}".to_string();
+    let packets = PreserializedPackets::new(&config);
+    let version = version_of(&packets.motd_packet_for(767));
+    assert_eq!(version["name"], "Sleeping server, protocol 766");
+    assert_eq!(version["protocol"], 766);
+}
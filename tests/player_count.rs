@@ -0,0 +1,149 @@
+use mcservernap::config::Config;
+use mcservernap::{PlayerCountQuery, parse_player_names};
+
+#[test]
+fn vanilla_list_response_is_parsed() {
+    let query = PlayerCountQuery::from_config(&Config::default());
+    let response = "There are 2 of a max of 20 players online: Steve, Alex";
+    assert_eq!(query.parse(response), Some(2));
+    assert_eq!(query.parse_max(response), Some(20));
+}
+
+#[test]
+fn color_codes_are_ignored() {
+    let query = PlayerCountQuery::from_config(&Config::default());
+    let response = "§6There are §c3§6 of a max of §c10§6 players online.";
+    assert_eq!(query.parse(response), Some(3));
+    assert_eq!(query.parse_max(response), Some(10));
+}
+
+#[test]
+fn other_server_software_formats_are_parsed() {
+    let query = PlayerCountQuery::from_config(&Config::default());
+    assert_eq!(query.parse("There are 4/20 players online:"), Some(4));
+    assert_eq!(query.parse_max("There are 4/20 players online:"), Some(20));
+
+    let essentials = "§6There are §c1§6 out of maximum §c50§6 players online.";
+    assert_eq!(query.parse(essentials), Some(1));
+    assert_eq!(query.parse_max(essentials), Some(50));
+
+    assert_eq!(query.parse("There are 0 players online"), Some(0));
+    assert_eq!(query.parse_max("There are 0 players online"), None);
+}
+
+#[test]
+fn unknown_responses_are_no_count() {
+    let query = PlayerCountQuery::from_config(&Config::default());
+    assert_eq!(query.parse("Unknown command"), None);
+}
+
+#[test]
+fn custom_pattern_has_no_fallbacks() {
+    let mut config = Config::default();
+    config.player_count_command = "online".to_string();
+    config.player_count_pattern = r"Online: (\d+)".to_string();
+    let query = PlayerCountQuery::from_config(&config);
+    assert_eq!(query.parse("Online: 7"), Some(7));
+    assert_eq!(query.parse("There are 4/20 players online:"), None);
+}
+
+fn query_ignoring(players: &[&str]) -> PlayerCountQuery {
+    let mut config = Config::default();
+    config.idle_ignore_players = players.iter().map(|player| player.to_string()).collect();
+    PlayerCountQuery::from_config(&config)
+}
+
+#[test]
+fn player_names_are_parsed() {
+    assert_eq!(
+        parse_player_names("There are 2 of a max of 20 players online: Steve, Alex"),
+        ["Steve", "Alex"]
+    );
+    assert_eq!(
+        parse_player_names(
+            "[12:00:00] [Server thread/INFO]: There are 1 of a max of 20 players online: Steve"
+        ),
+        ["Steve"]
+    );
+    assert_eq!(
+        parse_player_names("There are 2 out of maximum 20 players online.\ndefault: Steve, Alex"),
+        ["Steve", "Alex"]
+    );
+    assert!(parse_player_names("There are 0 of a max of 20 players online: ").is_empty());
+}
+
+#[test]
+fn ignored_players_are_not_counted() {
+    let query = query_ignoring(&["MapBot"]);
+    let response = "There are 1 of a max of 20 players online: mapbot";
+    assert_eq!(query.parse_counted(response), Some(0));
+    // `parse` still reports everyone
+    assert_eq!(query.parse(response), Some(1));
+}
+
+#[test]
+fn other_players_are_counted_next_to_ignored_ones() {
+    let query = query_ignoring(&["MapBot", "Camera"]);
+    let response = "There are 2 of a max of 20 players online: MapBot, Steve";
+    assert_eq!(query.parse_counted(response), Some(1));
+    let response = "There are 1 of a max of 20 players online: Steve";
+    assert_eq!(query.parse_counted(response), Some(1));
+}
+
+#[test]
+fn unknown_count_stays_unknown_with_ignored_players() {
+    let query = query_ignoring(&["MapBot"]);
+    assert_eq!(query.parse_counted("Unknown command"), None);
+}
+
+#[test]
+fn custom_pattern_uses_the_configured_group() {
+    let mut config = Config::default();
+    config.player_count_pattern = r"(\d+) of (\d+) slots used".to_string();
+    config.player_count_group = 1;
+    assert_eq!(
+        PlayerCountQuery::from_config(&config).parse("3 of 40 slots used"),
+        Some(3)
+    );
+
+    config.player_count_group = 2;
+    assert_eq!(
+        PlayerCountQuery::from_config(&config).parse("3 of 40 slots used"),
+        Some(40)
+    );
+}
+
+#[test]
+fn custom_pattern_not_matching_is_no_count() {
+    let mut config = Config::default();
+    config.player_count_pattern = r"Online: (\d+)".to_string();
+    let query = PlayerCountQuery::from_config(&config);
+    assert_eq!(query.parse("Online: none"), None);
+    assert_eq!(query.parse(""), None);
+    // A group the pattern doesn't have
+    config.player_count_group = 3;
+    assert_eq!(
+        PlayerCountQuery::from_config(&config).parse("Online: 7"),
+        None
+    );
+}
+
+#[test]
+fn invalid_pattern_falls_back_to_list() {
+    let mut config = Config::default();
+    config.player_count_command = "online".to_string();
+    config.player_count_pattern = r"Online: (\d+".to_string();
+    let query = PlayerCountQuery::from_config(&config);
+    assert_eq!(query.command, "list");
+    assert_eq!(
+        query.parse("There are 2 of a max of 20 players online: Steve, Alex"),
+        Some(2)
+    );
+}
+
+#[test]
+fn ignored_players_are_matched_without_color_codes_and_case() {
+    let query = query_ignoring(&["mapbot"]);
+    let response = "§6There are §c2§6 of a max of §c20§6 players online: §fMAPBOT§r, §fSteve";
+    assert_eq!(query.parse_counted(response), Some(1));
+}
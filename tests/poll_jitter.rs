@@ -0,0 +1,39 @@
+use mcservernap::jittered;
+use std::time::Duration;
+
+#[test]
+fn jitter_stays_within_bounds() {
+    let interval = Duration::from_secs(60);
+    for _ in 0..1000 {
+        let jittered = jittered(interval, 10);
+        assert!(jittered >= Duration::from_secs(54), "{:?}", jittered);
+        assert!(jittered <= Duration::from_secs(66), "{:?}", jittered);
+    }
+}
+
+#[test]
+fn jitter_averages_to_the_interval() {
+    let interval = Duration::from_secs(60);
+    let total: Duration = (0..10_000).map(|_| jittered(interval, 10)).sum();
+    let average = total / 10_000;
+    assert!(
+        average > Duration::from_millis(59_500) && average < Duration::from_millis(60_500),
+        "{:?}",
+        average
+    );
+}
+
+#[test]
+fn ticks_are_spread_out() {
+    let interval = Duration::from_secs(60);
+    let first = jittered(interval, 10);
+    assert!((0..100).any(|_| jittered(interval, 10) != first));
+}
+
+#[test]
+fn zero_jitter_keeps_the_interval() {
+    assert_eq!(
+        jittered(Duration::from_secs(60), 0),
+        Duration::from_secs(60)
+    );
+}
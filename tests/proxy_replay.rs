@@ -0,0 +1,80 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::NapServer;
+use mcservernap::config::{self, ReadinessMode};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+
+#[tokio::test]
+async fn held_login_split_across_reads_reaches_the_backend_unchanged() {
+    let dir = temp_dir("proxy-replay");
+    let mut config = config::get_config(Some(&dir)).unwrap();
+    config.readiness_mode = ReadinessMode::Log;
+    config.hold_connections_during_start = true;
+    // The backend below isn't a server that's already running
+    config.detect_running_server = false;
+
+    // The backend is this test, the "server" process only reports being ready
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let listen_port = {
+        let free = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        free.local_addr().unwrap().port()
+    };
+    let server = NapServer::builder()
+        .listen_addr(format!("127.0.0.1:{}", listen_port).parse().unwrap())
+        .command(
+            "/bin/sh",
+            vec![
+                "-c".to_string(),
+                "echo 'Done (1.0s)! For help, type \"help\"'; sleep 10".to_string(),
+            ],
+        )
+        .server_port(backend.local_addr().unwrap().port())
+        .rcon(25575, "secret")
+        .config(config)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+    sleep(Duration::from_millis(300)).await;
+
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", listen_port, 2);
+    let handshake_len = bytes.len();
+    bytes.extend(login_start("Steve"));
+    let mut client = TcpStream::connect(("127.0.0.1", listen_port))
+        .await
+        .unwrap();
+    // Split inside the handshake and inside the Login Start
+    for chunk in [
+        &bytes[..3],
+        &bytes[3..handshake_len + 2],
+        &bytes[handshake_len + 2..],
+    ] {
+        send(&mut client, chunk).await;
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    let (mut server_side, _) = timeout(Duration::from_secs(10), backend.accept())
+        .await
+        .expect("the held login wasn't forwarded")
+        .unwrap();
+    let mut received = vec![0u8; bytes.len()];
+    timeout(
+        Duration::from_secs(5),
+        server_side.read_exact(&mut received),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(received, bytes);
+
+    // Bytes sent after the login are proxied as they are
+    send(&mut client, &ping_request(7)).await;
+    let mut after = vec![0u8; ping_request(7).len()];
+    server_side.read_exact(&mut after).await.unwrap();
+    assert_eq!(after, ping_request(7));
+}
@@ -0,0 +1,155 @@
+use anyhow::{Result, anyhow};
+use mcservernap::config::Config;
+use mcservernap::notify::StopReason;
+use mcservernap::{IdleTimer, PlayerCountQuery, RconCmd, RconRetry, ServerState, rcon_poll_loop};
+use std::collections::VecDeque;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+const EMPTY: &str = "There are 0 of a max of 20 players online: ";
+const ONE_ONLINE: &str = "There are 1 of a max of 20 players online: Steve";
+
+/// Answers the player count command with scripted responses, repeating the last one once the script ran out
+struct ScriptedRcon {
+    responses: VecDeque<Result<String>>,
+    last: String,
+    sent: Vec<String>,
+}
+
+impl ScriptedRcon {
+    fn new(responses: impl IntoIterator<Item = Result<&'static str>>) -> Self {
+        ScriptedRcon {
+            responses: responses
+                .into_iter()
+                .map(|response| response.map(str::to_string))
+                .collect(),
+            last: EMPTY.to_string(),
+            sent: Vec::new(),
+        }
+    }
+}
+
+impl RconCmd for ScriptedRcon {
+    async fn cmd(&mut self, cmd: &str) -> Result<String> {
+        self.sent.push(cmd.to_string());
+        if cmd.starts_with("say ") {
+            return Ok(String::new());
+        }
+        match self.responses.pop_front() {
+            Some(Ok(response)) => {
+                self.last = response.clone();
+                Ok(response)
+            }
+            Some(Err(e)) => Err(e),
+            None => Ok(self.last.clone()),
+        }
+    }
+}
+
+fn test_config() -> Config {
+    let mut config = Config::default();
+    config.rcon_poll_interval = 60;
+    config.rcon_idle_timeout = 300;
+    config.rcon_poll_jitter_percent = 0;
+    config
+}
+
+async fn run(
+    config: &Config,
+    conn: &mut ScriptedRcon,
+) -> (Result<StopReason>, Duration, ServerState) {
+    let server_state = Mutex::new(ServerState::Running);
+    let start = Instant::now();
+    let result = rcon_poll_loop(
+        conn,
+        &RconRetry::from_config(config),
+        Duration::from_secs(config.rcon_poll_interval),
+        IdleTimer::from_config(config),
+        &PlayerCountQuery::from_config(config),
+        &server_state,
+    )
+    .await;
+    (result, start.elapsed(), *server_state.lock().await)
+}
+
+#[tokio::test(start_paused = true)]
+async fn empty_server_is_stopped_after_the_idle_timeout() {
+    let config = test_config();
+    let mut conn = ScriptedRcon::new([]);
+
+    let (result, elapsed, _) = run(&config, &mut conn).await;
+
+    assert_eq!(result.unwrap(), StopReason::Idle(Duration::from_secs(300)));
+    assert_eq!(elapsed, Duration::from_secs(300));
+    assert!(conn.sent.iter().all(|cmd| cmd == "list"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn returning_player_restarts_the_idle_timeout() {
+    let config = test_config();
+    // Empty at first, a player is online at the polls after 2 and 3 minutes
+    let mut conn = ScriptedRcon::new([
+        Ok(EMPTY),
+        Ok(EMPTY),
+        Ok(ONE_ONLINE),
+        Ok(ONE_ONLINE),
+        Ok(EMPTY),
+    ]);
+
+    let (result, elapsed, _) = run(&config, &mut conn).await;
+
+    assert_eq!(result.unwrap(), StopReason::Idle(Duration::from_secs(300)));
+    assert_eq!(elapsed, Duration::from_secs(180 + 300));
+}
+
+#[tokio::test(start_paused = true)]
+async fn players_are_warned_before_the_stop() {
+    let mut config = test_config();
+    config.idle_warning_seconds = 30;
+    let mut conn = ScriptedRcon::new([]);
+
+    let (result, elapsed, _) = run(&config, &mut conn).await;
+
+    assert!(matches!(result.unwrap(), StopReason::Idle(_)));
+    assert!(elapsed >= Duration::from_secs(300));
+    assert!(conn.sent.iter().any(|cmd| cmd.starts_with("say ")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn ready_broadcast_is_sent_first() {
+    let mut config = test_config();
+    config.ready_broadcast_message = Some("Server is up".to_string());
+    let mut conn = ScriptedRcon::new([]);
+
+    run(&config, &mut conn).await.0.unwrap();
+
+    assert_eq!(conn.sent[0], "say Server is up");
+}
+
+#[tokio::test(start_paused = true)]
+async fn too_many_failed_polls_stop_the_watchdog() {
+    let mut config = test_config();
+    config.rcon_poll_max_errors = 2;
+    let mut conn = ScriptedRcon::new([
+        Ok(ONE_ONLINE),
+        Err(anyhow!("connection reset")),
+        Err(anyhow!("connection reset")),
+        Err(anyhow!("connection reset")),
+    ]);
+
+    let (result, _, state) = run(&config, &mut conn).await;
+
+    assert!(result.is_err());
+    assert_eq!(state, ServerState::Stopped);
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_poll_is_retried() {
+    let config = test_config();
+    let mut conn = ScriptedRcon::new([Err(anyhow!("connection reset")), Ok(EMPTY)]);
+
+    let (result, _, state) = run(&config, &mut conn).await;
+
+    assert!(matches!(result.unwrap(), StopReason::Idle(_)));
+    assert_eq!(state, ServerState::Running);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::*;
+use mcservernap::server_log::{ServerLog, named_log_file, rotated_path};
+
+#[test]
+fn lines_are_appended() {
+    let dir = temp_dir("server-log-append");
+    let path = dir.join("logs/server.log");
+    let mut log = ServerLog::open(&path, 1024, 2).unwrap();
+    log.write_line("Starting minecraft server");
+    drop(log);
+    let mut log = ServerLog::open(&path, 1024, 2).unwrap();
+    log.write_line("Done (1.234s)!");
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "Starting minecraft server\nDone (1.234s)!\n"
+    );
+}
+
+#[test]
+fn full_files_are_rotated_and_the_oldest_dropped() {
+    let dir = temp_dir("server-log-rotate");
+    let path = dir.join("server.log");
+    // 20 bytes per file, each line takes 10
+    let mut log = ServerLog::open(&path, 60, 3).unwrap();
+    for i in 0..8 {
+        log.write_line(&format!("line {:04}", i));
+    }
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "line 0006\nline 0007\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+        "line 0004\nline 0005\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+        "line 0002\nline 0003\n"
+    );
+    assert!(!rotated_path(&path, 3).exists());
+}
+
+#[test]
+fn log_files_are_named_after_the_server() {
+    assert_eq!(
+        named_log_file("logs/server.log", "survival"),
+        "logs/server.survival.log"
+    );
+    assert_eq!(named_log_file("server", "survival"), "server.survival");
+}
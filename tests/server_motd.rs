@@ -0,0 +1,47 @@
+use mcservernap::config::Config;
+use mcservernap::{query_server_motd, write_varint};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Answers the first status request with a status response claiming `json_len` bytes of JSON, followed by `json`
+async fn answer_status(listener: TcpListener, json_len: i32, json: &'static [u8]) {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut request = [0u8; 64];
+    let _ = socket.read(&mut request).await.unwrap();
+
+    let mut body = Vec::new();
+    write_varint(0, &mut body);
+    write_varint(json_len, &mut body);
+    body.extend_from_slice(json);
+    let mut packet = Vec::new();
+    write_varint(body.len() as i32, &mut packet);
+    packet.extend(body);
+    socket.write_all(&packet).await.unwrap();
+}
+
+async fn query(json_len: i32, json: &'static [u8]) -> anyhow::Result<serde_json::Value> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(answer_status(listener, json_len, json));
+    query_server_motd(&Config::default(), port, Duration::from_secs(5)).await
+}
+
+#[tokio::test]
+async fn status_response_is_parsed() {
+    let json = br#"{"description":{"text":"A Minecraft Server"}}"#;
+    let status = query(json.len() as i32, json).await.unwrap();
+    assert_eq!(status["description"]["text"], "A Minecraft Server");
+}
+
+#[tokio::test]
+async fn negative_json_length_is_rejected() {
+    let err = query(-1, b"{}").await.unwrap_err();
+    assert!(err.to_string().contains("Negative JSON length"), "{}", err);
+}
+
+#[tokio::test]
+async fn truncated_status_response_is_rejected() {
+    let err = query(100, b"{}").await.unwrap_err();
+    assert!(err.to_string().contains("Truncated"), "{}", err);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::*;
+use mcservernap::server_properties::{self, ServerProperties, parse_str};
+
+#[test]
+fn ports_and_password_are_read() {
+    let properties = parse_str(
+        "#Minecraft server properties\n\
+         #Sat Oct 10 12:00:00 CEST 2026\n\
+         server-port=25566\n\
+         rcon.port=25575\n\
+         rcon.password=hunter2\n\
+         enable-rcon=true\n\
+         white-list=false\n",
+    );
+    assert_eq!(
+        properties,
+        ServerProperties {
+            server_port: Some(25566),
+            rcon_port: Some(25575),
+            rcon_password: Some("hunter2".to_string()),
+            enable_rcon: Some(true),
+            white_list: Some(false),
+        }
+    );
+}
+
+#[test]
+fn escaped_values_are_unescaped() {
+    let properties = parse_str("rcon.password=p\\=ss\\:w\\\\ord\\#1\nrcon\\.port=25575\n");
+    assert_eq!(properties.rcon_password.as_deref(), Some("p=ss:w\\ord#1"));
+    assert_eq!(properties.rcon_port, Some(25575));
+}
+
+#[test]
+fn colon_separator_and_spaces_are_accepted() {
+    let properties = parse_str("server-port : 25566\n  rcon.port=25575  \n");
+    assert_eq!(properties.server_port, Some(25566));
+    assert_eq!(properties.rcon_port, Some(25575));
+}
+
+#[test]
+fn comments_are_ignored() {
+    let properties = parse_str("#server-port=1\n! rcon.port=2\n  # rcon.password=secret\n");
+    assert_eq!(properties, ServerProperties::default());
+}
+
+#[test]
+fn missing_empty_and_invalid_values_are_none() {
+    let properties = parse_str("server-port=\nrcon.port=not a port\nrcon.password=\n");
+    assert_eq!(properties, ServerProperties::default());
+}
+
+#[test]
+fn missing_file_is_an_error() {
+    let dir = temp_dir("server-properties-missing");
+    assert!(server_properties::parse(dir.join("server.properties")).is_err());
+    assert!(!server_properties::rcon_disabled(
+        dir.join("server.properties")
+    ));
+}
+
+#[test]
+fn explicit_values_take_precedence() {
+    let detected = parse_str("server-port=25566\nrcon.port=25575\nrcon.password=detected\n");
+    let resolved = detected.with_explicit(Some(30000), None, Some("explicit".to_string()));
+    assert_eq!(resolved.server_port, Some(30000));
+    assert_eq!(resolved.rcon_port, Some(25575));
+    assert_eq!(resolved.rcon_password.as_deref(), Some("explicit"));
+}
+
+#[test]
+fn explicit_values_fill_in_missing_properties() {
+    let resolved = parse_str("").with_explicit(None, Some(25575), None);
+    assert_eq!(resolved.server_port, None);
+    assert_eq!(resolved.rcon_port, Some(25575));
+}
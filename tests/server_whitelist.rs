@@ -0,0 +1,67 @@
+mod common;
+
+use common::*;
+use mcservernap::config::Config;
+use mcservernap::server_whitelist;
+use std::path::{Path, PathBuf};
+
+/// A server directory with `white-list` set as given and Steve on the whitelist
+fn server_dir(name: &str, white_list: bool) -> (PathBuf, Config) {
+    let dir = temp_dir(name);
+    std::fs::write(
+        dir.join("server.properties"),
+        format!("server-port=25566\nwhite-list={}\n", white_list),
+    )
+    .unwrap();
+    write_whitelist(&dir.join("whitelist.json"), &["Steve"]);
+    let mut config = Config::default();
+    config.use_server_whitelist = true;
+    config.server_properties_path = dir.join("server.properties").to_string_lossy().into_owned();
+    config.server_whitelist_path = dir.join("whitelist.json").to_string_lossy().into_owned();
+    (dir, config)
+}
+
+fn write_whitelist(path: &Path, names: &[&str]) {
+    let entries: Vec<_> = names
+        .iter()
+        .map(|name| serde_json::json!({"uuid": "00000000-0000-0000-0000-000000000000", "name": name}))
+        .collect();
+    std::fs::write(path, serde_json::to_string(&entries).unwrap()).unwrap();
+}
+
+#[test]
+fn only_whitelisted_players_wake_the_server() {
+    let (_dir, config) = server_dir("server-whitelist", true);
+    assert!(server_whitelist::allows(&config, Some("steve")));
+    assert!(!server_whitelist::allows(&config, Some("Alex")));
+    assert!(!server_whitelist::allows(&config, None));
+}
+
+#[test]
+fn whitelist_is_ignored_unless_the_server_enforces_it() {
+    let (_dir, config) = server_dir("server-whitelist-off", false);
+    assert!(server_whitelist::allows(&config, Some("Alex")));
+}
+
+#[test]
+fn whitelist_is_ignored_unless_enabled() {
+    let (_dir, mut config) = server_dir("server-whitelist-disabled", true);
+    config.use_server_whitelist = false;
+    assert!(server_whitelist::allows(&config, Some("Alex")));
+}
+
+#[test]
+fn players_added_later_and_operators_are_allowed() {
+    let (dir, config) = server_dir("server-whitelist-changes", true);
+    write_whitelist(&dir.join("whitelist.json"), &["Steve", "Alex"]);
+    write_whitelist(&dir.join("ops.json"), &["Admin"]);
+    assert!(server_whitelist::allows(&config, Some("Alex")));
+    assert!(server_whitelist::allows(&config, Some("Admin")));
+}
+
+#[test]
+fn unreadable_whitelist_lets_everyone_through() {
+    let (dir, config) = server_dir("server-whitelist-missing", true);
+    std::fs::remove_file(dir.join("whitelist.json")).unwrap();
+    assert!(server_whitelist::allows(&config, Some("Alex")));
+}
@@ -0,0 +1,54 @@
+use mcservernap::StartBackoff;
+use std::time::Duration;
+
+const BASE: Duration = Duration::from_secs(10);
+const MAX: Duration = Duration::from_secs(600);
+
+#[test]
+fn no_delay_without_failures() {
+    let backoff = StartBackoff::default();
+    assert_eq!(backoff.delay(BASE, MAX), Duration::ZERO);
+    assert_eq!(backoff.remaining(BASE, MAX), Duration::ZERO);
+}
+
+#[test]
+fn delay_doubles_with_every_failure_up_to_the_max() {
+    let mut backoff = StartBackoff::default();
+    let delays: Vec<u64> = (0..8)
+        .map(|_| {
+            backoff.record_failure();
+            backoff.delay(BASE, MAX).as_secs()
+        })
+        .collect();
+    assert_eq!(delays, [10, 20, 40, 80, 160, 320, 600, 600]);
+}
+
+#[test]
+fn delay_never_overflows() {
+    let mut backoff = StartBackoff::default();
+    for _ in 0..100 {
+        backoff.record_failure();
+    }
+    assert_eq!(backoff.delay(BASE, MAX), MAX);
+    assert_eq!(backoff.delay(Duration::MAX, Duration::MAX), Duration::MAX);
+}
+
+#[test]
+fn success_resets_the_backoff() {
+    let mut backoff = StartBackoff::default();
+    assert_eq!(backoff.record_failure(), 1);
+    assert_eq!(backoff.record_failure(), 2);
+    assert!(!backoff.remaining(BASE, MAX).is_zero());
+
+    backoff.record_success();
+    assert_eq!(backoff.remaining(BASE, MAX), Duration::ZERO);
+    assert_eq!(backoff.record_failure(), 1);
+    assert_eq!(backoff.delay(BASE, MAX), BASE);
+}
+
+#[test]
+fn zero_base_disables_the_backoff() {
+    let mut backoff = StartBackoff::default();
+    backoff.record_failure();
+    assert_eq!(backoff.remaining(Duration::ZERO, MAX), Duration::ZERO);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::*;
+use mcservernap::stats::{LifetimeStats, StatsRecorder, is_stats_file_name, stats_file};
+
+#[test]
+fn missing_file_is_empty_stats() {
+    let dir = temp_dir("stats-missing");
+    let stats = LifetimeStats::load(&stats_file(dir.to_str().unwrap(), None)).unwrap();
+    assert_eq!(stats, LifetimeStats::default());
+}
+
+#[test]
+fn wakes_survive_a_restart() {
+    let dir = temp_dir("stats-restart");
+    let path = stats_file(dir.to_str().unwrap(), Some("survival"));
+    assert!(path.ends_with("stats.survival.json"));
+
+    let recorder = StatsRecorder::open(path.clone());
+    recorder.record_wake();
+    recorder.record_stop();
+    drop(recorder);
+
+    let recorder = StatsRecorder::open(path.clone());
+    recorder.record_wake();
+    recorder.flush();
+
+    let stats = LifetimeStats::load(&path).unwrap();
+    assert_eq!(stats.wakes, 2);
+    assert!(stats.last_wake.is_some());
+    assert!(stats.last_stop.is_some());
+    // Written via a temporary file that's renamed into place
+    assert!(!dir.join("stats.survival.json.tmp").exists());
+}
+
+#[test]
+fn corrupt_file_is_started_over() {
+    let dir = temp_dir("stats-corrupt");
+    let path = stats_file(dir.to_str().unwrap(), None);
+    std::fs::write(&path, "{ not json").unwrap();
+    assert!(LifetimeStats::load(&path).is_err());
+
+    let recorder = StatsRecorder::open(path.clone());
+    recorder.record_wake();
+
+    assert_eq!(LifetimeStats::load(&path).unwrap().wakes, 1);
+}
+
+#[test]
+fn stats_files_and_their_temporary_files_are_recognized() {
+    for name in [
+        "stats.json",
+        "stats.survival.json",
+        "stats.json.tmp",
+        "stats.survival.json.tmp",
+    ] {
+        assert!(is_stats_file_name(name), "{}", name);
+    }
+    for name in [
+        "stats.toml",
+        "cfg.toml.bak",
+        "mystats.json",
+        "stats.json.bak",
+    ] {
+        assert!(!is_stats_file_name(name), "{}", name);
+    }
+}
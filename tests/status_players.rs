@@ -0,0 +1,86 @@
+mod common;
+
+use common::*;
+use mcservernap::PlayerCounts;
+use mcservernap::config::Config;
+use mcservernap::preserialized_packets::PreserializedPackets;
+use mcservernap::verify_handshake_packet;
+use std::sync::Arc;
+
+/// Answers a status ping with `packets` and returns the `players` object of the response
+async fn status_players(packets: &PreserializedPackets) -> serde_json::Value {
+    let config = Config::default();
+    let (mut client, mut server, peer) = connected_pair().await;
+
+    let server_side = verify_handshake_packet(&mut server, peer, &config, packets);
+    let client_side = async {
+        send(
+            &mut client,
+            &handshake(PROTOCOL_VERSION, "localhost", 25565, 1),
+        )
+        .await;
+        send(&mut client, &status_request()).await;
+        let (_, json) = read_string_packet(&mut client).await;
+        send(&mut client, &ping_request(1)).await;
+        read_packet(&mut client).await;
+        json
+    };
+    let (_, json) = tokio::join!(server_side, client_side);
+
+    let status: serde_json::Value = serde_json::from_str(&json).unwrap();
+    status["players"].clone()
+}
+
+#[tokio::test]
+async fn nothing_reported_shows_no_players() {
+    let packets = PreserializedPackets::new(&Config::default());
+
+    let players = status_players(&packets).await;
+    assert_eq!(players["online"], 0);
+    assert_eq!(players["max"], 0);
+}
+
+#[tokio::test]
+async fn reported_counts_are_shown() {
+    let player_counts = Arc::new(PlayerCounts::default());
+    let packets =
+        PreserializedPackets::new(&Config::default()).with_player_counts(player_counts.clone());
+    player_counts.record_online(3);
+    player_counts.record_max(20);
+
+    let players = status_players(&packets).await;
+    assert_eq!(players["online"], 3);
+    assert_eq!(players["max"], 20);
+}
+
+#[tokio::test]
+async fn stopped_server_keeps_its_player_limit() {
+    let player_counts = Arc::new(PlayerCounts::default());
+    let packets =
+        PreserializedPackets::new(&Config::default()).with_player_counts(player_counts.clone());
+    player_counts.record_online(3);
+    player_counts.record_max(20);
+    player_counts.server_stopped();
+
+    let players = status_players(&packets).await;
+    assert_eq!(players["online"], 0);
+    assert_eq!(players["max"], 20);
+}
+
+#[tokio::test]
+async fn sample_lines_are_shown_as_hover_text() {
+    let mut config = Config::default();
+    config.motd_sample_lines = Some(vec![
+        "The server is asleep".to_string(),
+        "Join to wake it up".to_string(),
+    ]);
+    let packets = PreserializedPackets::new(&config);
+
+    let players = status_players(&packets).await;
+    let sample = players["sample"].as_array().unwrap();
+    let names: Vec<_> = sample.iter().map(|entry| &entry["name"]).collect();
+    assert_eq!(names, ["The server is asleep", "Join to wake it up"]);
+    for entry in sample {
+        assert_eq!(entry["id"].as_str().unwrap().len(), 36);
+    }
+}
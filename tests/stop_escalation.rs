@@ -0,0 +1,59 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::config::Config;
+use mcservernap::console::ServerConsole;
+use mcservernap::stop::{StopStep, StopStrategy, stop_server};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+
+#[tokio::test]
+async fn server_ignoring_stop_is_terminated_then_killed() {
+    let dir = temp_dir("stop-escalation");
+    let marker = dir.join("terminated");
+    // Ignores `stop` on its console and SIGTERM, only recording that it got the latter
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!(
+            "trap 'echo terminated > {}' TERM; while :; do sleep 0.1; done",
+            marker.display()
+        ))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let console = ServerConsole::attach(&mut child, None).unwrap();
+    // Nothing listens for RCON
+    let rcon_port = {
+        let free = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        free.local_addr().unwrap().port()
+    };
+
+    let mut config = Config::default();
+    config.stop_timeout = 1;
+    config.stop_steps = vec![
+        StopStep::Console,
+        StopStep::Rcon,
+        StopStep::Terminate,
+        StopStep::Kill,
+    ];
+    let mut strategy =
+        StopStrategy::from_config(&config, &format!("127.0.0.1:{}", rcon_port), "secret", None);
+    strategy.console = Some(console);
+
+    let start = Instant::now();
+    stop_server(Some(&mut child), &strategy).await.unwrap();
+
+    assert!(start.elapsed() < config.max_stop_duration());
+    assert!(
+        std::fs::read_to_string(&marker)
+            .unwrap()
+            .contains("terminated")
+    );
+    let status = child.try_wait().unwrap().unwrap();
+    assert_eq!(status.signal(), Some(9));
+}
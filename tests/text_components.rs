@@ -0,0 +1,115 @@
+use mcservernap::config::Config;
+use mcservernap::preserialized_packets::{
+    serialize_motd, serialize_starting_message, validate_color,
+};
+use mcservernap::read_varint;
+use serde_json::{Value, json};
+
+/// Decodes a packet holding a single JSON string, like the status response or a login disconnect
+fn packet_json(packet: &[u8]) -> Value {
+    let (_, length_size) = read_varint(packet).unwrap();
+    let (_, id_size) = read_varint(&packet[length_size..]).unwrap();
+    let payload = &packet[length_size + id_size..];
+    let (text_length, text_length_size) = read_varint(payload).unwrap();
+    let text = &payload[text_length_size..text_length_size + text_length as usize];
+    serde_json::from_slice(text).unwrap()
+}
+
+#[test]
+fn motd_component_replaces_the_description() {
+    let component = json!([
+        { "text": "Nap", "color": "#FF8800" },
+        { "text": "time", "italic": true }
+    ]);
+    let mut config = Config::default();
+    config.motd_component = Some(component.to_string());
+
+    let status = packet_json(&serialize_motd(&config));
+    assert_eq!(status["description"], component);
+}
+
+#[test]
+fn connection_msg_component_is_sent_as_is() {
+    let component = json!({
+        "text": "Starting, ",
+        "extra": [{ "text": "click for the map", "clickEvent": { "action": "open_url", "value": "https://example.com" } }]
+    });
+    let mut config = Config::default();
+    config.connection_msg_component = Some(component.to_string());
+
+    assert_eq!(packet_json(&serialize_starting_message(&config)), component);
+}
+
+#[test]
+fn invalid_component_falls_back_to_simple_fields() {
+    let mut config = Config::default();
+    config.motd_component = Some("{ not json".to_string());
+    config.connection_msg_component = Some("\"just a string\"".to_string());
+
+    let status = packet_json(&serialize_motd(&config));
+    assert_eq!(status["description"]["text"], config.motd_text);
+    let message = packet_json(&serialize_starting_message(&config));
+    assert_eq!(message["text"], config.connection_msg_text);
+}
+
+#[test]
+fn hex_colors_are_accepted() {
+    assert_eq!(validate_color("#ff8800").as_deref(), Some("#FF8800"));
+    assert_eq!(validate_color(" #00AaFf ").as_deref(), Some("#00AAFF"));
+
+    let mut config = Config::default();
+    config.motd_color = "#12ab34".to_string();
+    config.connection_msg_color = "#FFFFFF".to_string();
+    let status = packet_json(&serialize_motd(&config));
+    assert_eq!(status["description"]["color"], "#12AB34");
+    let message = packet_json(&serialize_starting_message(&config));
+    assert_eq!(message["color"], "#FFFFFF");
+}
+
+#[test]
+fn malformed_hex_colors_fall_back_to_white() {
+    for color in ["#12345", "#1234567", "#GG8800", "ff8800", "#"] {
+        assert_eq!(validate_color(color), None, "{}", color);
+    }
+
+    let mut config = Config::default();
+    config.motd_color = "#GG8800".to_string();
+    config.connection_msg_color = "#12345".to_string();
+    let status = packet_json(&serialize_motd(&config));
+    assert_eq!(status["description"]["color"], "white");
+    let message = packet_json(&serialize_starting_message(&config));
+    assert_eq!(message["color"], "white");
+}
+
+#[test]
+fn named_colors_are_accepted_and_unknown_ones_fall_back_to_white() {
+    assert_eq!(
+        validate_color("Dark_Purple").as_deref(),
+        Some("dark_purple")
+    );
+    assert_eq!(validate_color("purple"), None);
+    assert_eq!(validate_color("orange"), None);
+
+    let mut config = Config::default();
+    config.motd_color = "orange".to_string();
+    let status = packet_json(&serialize_motd(&config));
+    assert_eq!(status["description"]["color"], "white");
+}
+
+#[test]
+fn second_motd_line_follows_a_line_break() {
+    let mut config = Config::default();
+    config.motd_text = "Sleeping".to_string();
+    config.motd_text_line2 = Some("Join to wake it up".to_string());
+    config.motd_color_line2 = Some("gold".to_string());
+    config.motd_bold_line2 = Some(true);
+
+    let description = &packet_json(&serialize_motd(&config))["description"];
+    let mut text = description["text"].as_str().unwrap().to_string();
+    for child in description["extra"].as_array().unwrap() {
+        text.push_str(child.as_str().or(child["text"].as_str()).unwrap());
+    }
+    assert_eq!(text, "Sleeping\nJoin to wake it up");
+    assert_eq!(description["extra"][1]["color"], "gold");
+    assert_eq!(description["extra"][1]["bold"], true);
+}
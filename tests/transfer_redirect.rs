@@ -0,0 +1,203 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::NapServer;
+use mcservernap::config::{self, Config, TransferTarget};
+use mcservernap::preserialized_packets::{serialize_login_success, serialize_transfer};
+use mcservernap::read_varint;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, timeout};
+use uuid::Uuid;
+
+fn target(host: &str, port: u16) -> TransferTarget {
+    TransferTarget {
+        host: host.to_string(),
+        port,
+    }
+}
+
+#[test]
+fn transfer_targets_are_parsed() {
+    let parse = |target| TransferTarget::parse(target).unwrap();
+    assert_eq!(
+        parse("lobby.example.org"),
+        target("lobby.example.org", 25565)
+    );
+    assert_eq!(
+        parse("lobby.example.org:25570"),
+        target("lobby.example.org", 25570)
+    );
+    assert_eq!(parse("10.0.0.2:25566"), target("10.0.0.2", 25566));
+    assert_eq!(parse("[::1]:25566"), target("::1", 25566));
+    assert_eq!(parse("[2001:db8::1]"), target("2001:db8::1", 25565));
+    assert_eq!(parse("::1"), target("::1", 25565));
+    assert_eq!(parse("2001:db8::1"), target("2001:db8::1", 25565));
+}
+
+#[test]
+fn invalid_transfer_targets_are_rejected() {
+    for target in [
+        "lobby:abc",
+        "lobby:70000",
+        ":25565",
+        "",
+        "[::1",
+        "[::1]25565",
+        "[lobby]:25565",
+    ] {
+        assert!(TransferTarget::parse(target).is_err(), "{}", target);
+    }
+}
+
+#[test]
+fn transfer_targets_are_displayed_as_addresses() {
+    assert_eq!(target("lobby", 25565).to_string(), "lobby:25565");
+    assert_eq!(target("::1", 25566).to_string(), "[::1]:25566");
+}
+
+#[test]
+fn transfer_redirect_is_parsed_by_validate() {
+    let mut config = Config::default();
+    config.transfer_redirect = Some("[::1]:25566".to_string());
+    config.validate().unwrap();
+    assert_eq!(config.transfer_target, Some(target("::1", 25566)));
+
+    config.transfer_redirect = Some("lobby:abc".to_string());
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn login_success_has_the_strict_error_flag_before_protocol_768() {
+    let uuid = Uuid::nil();
+    let without_flag = serialize_login_success(uuid, "Steve", 768);
+    let with_flag = serialize_login_success(uuid, "Steve", 767);
+
+    // UUID, username, no properties
+    let mut payload = vec![0u8; 16];
+    write_string("Steve", &mut payload);
+    payload.push(0);
+    assert_eq!(without_flag, packet(0x02, &payload));
+    payload.push(0);
+    assert_eq!(with_flag, packet(0x02, &payload));
+}
+
+#[test]
+fn transfer_packet_holds_host_and_port() {
+    let mut payload = Vec::new();
+    write_string("lobby.example.org", &mut payload);
+    mcservernap::write_varint(25570, &mut payload);
+    assert_eq!(
+        serialize_transfer("lobby.example.org", 25570),
+        packet(0x0B, &payload)
+    );
+}
+
+/// Runs a server redirecting to `transfer_redirect` and returns its listen port and the file launches are
+/// recorded in
+async fn redirecting_server(name: &str, transfer_redirect: &str) -> (u16, PathBuf) {
+    let dir = temp_dir(name);
+    let launches_path = dir.join("launches");
+    let mut config = config::get_config(Some(&dir)).unwrap();
+    config.transfer_redirect = Some(transfer_redirect.to_string());
+    config.transfer_unsupported_msg_text = "Please update your game".to_string();
+    config.detect_running_server = false;
+
+    let listen_port = {
+        let free = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        free.local_addr().unwrap().port()
+    };
+    let server = NapServer::builder()
+        .listen_addr(format!("127.0.0.1:{}", listen_port).parse().unwrap())
+        .command(
+            "/bin/sh",
+            vec![
+                "-c".to_string(),
+                format!("echo launched >> {}; sleep 10", launches_path.display()),
+            ],
+        )
+        .server_port(25566)
+        .rcon(25575, "secret")
+        .config(config)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+    sleep(Duration::from_millis(300)).await;
+    (listen_port, launches_path)
+}
+
+/// Reads a string and returns it with the rest of `buf`
+fn read_string(buf: &[u8]) -> (String, &[u8]) {
+    let (length, length_size) = read_varint(buf).unwrap();
+    let end = length_size + length as usize;
+    (
+        String::from_utf8(buf[length_size..end].to_vec()).unwrap(),
+        &buf[end..],
+    )
+}
+
+#[tokio::test]
+async fn joining_player_is_logged_in_and_transferred() {
+    let (listen_port, launches_path) =
+        redirecting_server("transfer-redirect-flow", "[2001:db8::1]:25570").await;
+
+    let mut client = TcpStream::connect(("127.0.0.1", listen_port))
+        .await
+        .unwrap();
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", listen_port, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+
+    let (id, payload) = timeout(Duration::from_secs(5), read_packet(&mut client))
+        .await
+        .unwrap();
+    assert_eq!(id, 0x02);
+    let (username, rest) = read_string(&payload[16..]);
+    assert_eq!(username, "Steve");
+    // No properties, strict error handling disabled
+    assert_eq!(rest, [0, 0]);
+
+    // A plugin response whose payload looks like a Login Acknowledged doesn't count as one
+    send(&mut client, &packet(0x02, &[0x01, 0x01, 0x03])).await;
+    assert!(
+        timeout(Duration::from_millis(300), read_packet(&mut client))
+            .await
+            .is_err(),
+        "transferred before the login was acknowledged"
+    );
+
+    send(&mut client, &packet(0x03, &[])).await;
+    let (id, payload) = timeout(Duration::from_secs(5), read_packet(&mut client))
+        .await
+        .unwrap();
+    assert_eq!(id, 0x0B);
+    let (host, rest) = read_string(&payload);
+    assert_eq!(host, "2001:db8::1");
+    assert_eq!(read_varint(rest), Some((25570, rest.len())));
+
+    assert!(!launches_path.exists(), "the server was launched");
+}
+
+#[tokio::test]
+async fn clients_without_transfers_are_shown_a_message() {
+    let (listen_port, launches_path) =
+        redirecting_server("transfer-redirect-old-client", "lobby:25570").await;
+
+    let mut client = TcpStream::connect(("127.0.0.1", listen_port))
+        .await
+        .unwrap();
+    // Minecraft 1.20.4
+    let mut bytes = handshake(765, "localhost", listen_port, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+
+    let (id, message) = timeout(Duration::from_secs(5), read_string_packet(&mut client))
+        .await
+        .unwrap();
+    assert_eq!(id, 0x00);
+    assert!(message.contains("Please update your game"), "{}", message);
+    assert!(!launches_path.exists(), "the server was launched");
+}
@@ -0,0 +1,57 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::client_stream::{ClientListener, UNIX_PEER};
+use mcservernap::config::Config;
+use mcservernap::preserialized_packets::PreserializedPackets;
+use mcservernap::verify_handshake_packet;
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mcservernap-{}-{}.sock", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn login_over_unix_socket_wakes() {
+    let path = socket_path("login");
+    let listener = ClientListener::bind_unix(&path).unwrap();
+    let mut client = UnixStream::connect(&path).await.unwrap();
+    let (mut server, peer) = listener.accept().await.unwrap();
+    assert_eq!(peer, UNIX_PEER);
+
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", 25565, 2);
+    bytes.extend(login_start("Steve"));
+    client.write_all(&bytes).await.unwrap();
+
+    let config = Config::default();
+    let packets = PreserializedPackets::new(&config);
+    let woken = verify_handshake_packet(&mut server, peer, &config, &packets).await;
+    assert!(woken.unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn stale_socket_is_replaced() {
+    let path = socket_path("stale");
+    drop(ClientListener::bind_unix(&path).unwrap());
+    assert!(path.exists());
+
+    let listener = ClientListener::bind_unix(&path).unwrap();
+    UnixStream::connect(&path).await.unwrap();
+    listener.accept().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn regular_file_is_not_replaced() {
+    let path = socket_path("file");
+    std::fs::write(&path, "keep me").unwrap();
+
+    assert!(ClientListener::bind_unix(&path).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+    std::fs::remove_file(&path).unwrap();
+}
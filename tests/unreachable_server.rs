@@ -0,0 +1,79 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use mcservernap::NapServer;
+use mcservernap::config::{self, ReadinessMode};
+use mcservernap::stop::StopStep;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+async fn free_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+fn launches(path: &Path) -> usize {
+    std::fs::read_to_string(path)
+        .map(|launches| launches.lines().count())
+        .unwrap_or(0)
+}
+
+/// Joins as Steve and waits until the connection is closed
+async fn join(port: u16) {
+    let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let mut bytes = handshake(PROTOCOL_VERSION, "localhost", port, 2);
+    bytes.extend(login_start("Steve"));
+    send(&mut client, &bytes).await;
+    let _ = client.read_to_end(&mut Vec::new()).await;
+}
+
+#[tokio::test]
+async fn unreachable_server_is_not_launched_twice_while_it_runs() {
+    let dir = temp_dir("unreachable-server");
+    let launches_path = dir.join("launches");
+    let mut config = config::get_config(Some(&dir)).unwrap();
+    config.readiness_mode = ReadinessMode::Log;
+    config.proxy_connect_attempts = 1;
+    // The server ignores SIGTERM and is only killed once that step timed out
+    config.stop_steps = vec![StopStep::Terminate, StopStep::Kill];
+    config.stop_timeout = 3;
+
+    // Reports being ready, but never opens its port
+    let script = format!(
+        "trap '' TERM; echo launched >> {}; echo 'Done (1.0s)! For help, type \"help\"'; sleep 10",
+        launches_path.display()
+    );
+    let listen_port = free_port().await;
+    let server = NapServer::builder()
+        .listen_addr(format!("127.0.0.1:{}", listen_port).parse().unwrap())
+        .command("/bin/sh", vec!["-c".to_string(), script])
+        .server_port(free_port().await)
+        .rcon(free_port().await, "secret")
+        .config(config)
+        .build()
+        .unwrap();
+    tokio::spawn(server.run());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    join(listen_port).await;
+    for _ in 0..50 {
+        if launches(&launches_path) == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(launches(&launches_path), 1);
+    // Wait for the Done line to count as running
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Proxied to the server, which can't be reached
+    join(listen_port).await;
+    // The server process is still alive, so this must not launch another one
+    join(listen_port).await;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(launches(&launches_path), 1);
+}
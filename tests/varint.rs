@@ -0,0 +1,98 @@
+use mcservernap::{read_varint, read_varlong, write_varint, write_varlong};
+
+fn varint(value: i32) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varint(value, &mut buf);
+    buf
+}
+
+fn varlong(value: i64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_varlong(value, &mut buf);
+    buf
+}
+
+#[test]
+fn varint_boundary_values_are_encoded_like_minecraft() {
+    assert_eq!(varint(0), [0x00]);
+    assert_eq!(varint(127), [0x7F]);
+    assert_eq!(varint(128), [0x80, 0x01]);
+    assert_eq!(varint(-1), [0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    assert_eq!(varint(i32::MAX), [0xFF, 0xFF, 0xFF, 0xFF, 0x07]);
+    assert_eq!(varint(i32::MIN), [0x80, 0x80, 0x80, 0x80, 0x08]);
+}
+
+#[test]
+fn varlong_boundary_values_are_encoded_like_minecraft() {
+    assert_eq!(varlong(0), [0x00]);
+    assert_eq!(
+        varlong(-1),
+        [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]
+    );
+    assert_eq!(
+        varlong(i64::MAX),
+        [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]
+    );
+    assert_eq!(
+        varlong(i64::MIN),
+        [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]
+    );
+}
+
+#[test]
+fn varints_round_trip() {
+    for value in [0, 1, -1, 127, 128, 255, 25565, -25565, i32::MAX, i32::MIN] {
+        let buf = varint(value);
+        assert_eq!(read_varint(&buf), Some((value, buf.len())), "{}", value);
+    }
+}
+
+#[test]
+fn varlongs_round_trip() {
+    for value in [
+        0,
+        1,
+        -1,
+        2_147_483_648,
+        -2_147_483_649,
+        i64::from(i32::MAX),
+        i64::MAX,
+        i64::MIN,
+    ] {
+        let buf = varlong(value);
+        assert_eq!(read_varlong(&buf), Some((value, buf.len())), "{}", value);
+    }
+}
+
+#[test]
+fn trailing_bytes_are_not_read() {
+    let mut buf = varint(300);
+    buf.extend_from_slice(&[0xAB, 0xCD]);
+    assert_eq!(read_varint(&buf), Some((300, 2)));
+}
+
+#[test]
+fn varint_overflowing_5_bytes_is_rejected() {
+    // Continues past the 5th byte
+    assert_eq!(read_varint(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]), None);
+    // The 5th byte carries more than the 4 remaining bits
+    assert_eq!(read_varint(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]), None);
+    assert_eq!(read_varint(&[0x80, 0x80, 0x80, 0x80, 0x70]), None);
+}
+
+#[test]
+fn varlong_overflowing_10_bytes_is_rejected() {
+    // Continues past the 10th byte
+    assert_eq!(read_varlong(&[0xFF; 11]), None);
+    // The 10th byte carries more than the 1 remaining bit
+    let mut buf = [0xFF; 10];
+    buf[9] = 0x02;
+    assert_eq!(read_varlong(&buf), None);
+}
+
+#[test]
+fn truncated_values_are_rejected() {
+    assert_eq!(read_varint(&[]), None);
+    assert_eq!(read_varint(&[0x80, 0x80]), None);
+    assert_eq!(read_varlong(&[0xFF, 0xFF, 0xFF]), None);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::*;
+use mcservernap::config::Config;
+use mcservernap::verify::{self, Outcome};
+use tokio::net::TcpListener;
+
+#[test]
+fn valid_config_passes() {
+    let dir = temp_dir("verify-valid");
+    std::fs::write(dir.join("cfg.toml"), "rcon_idle_timeout = 600\n").unwrap();
+    let (check, config) = verify::check_config(dir.to_str().unwrap());
+    assert_eq!(check.outcome, Outcome::Passed);
+    assert_eq!(config.unwrap().rcon_idle_timeout, 600);
+}
+
+#[test]
+fn invalid_config_fails_and_is_left_alone() {
+    let dir = temp_dir("verify-invalid");
+    let cfg_path = dir.join("cfg.toml");
+    std::fs::write(&cfg_path, "rcon_idle_timeout = \"ten minutes\"\n").unwrap();
+    let (check, config) = verify::check_config(dir.to_str().unwrap());
+    assert_eq!(check.outcome, Outcome::Failed);
+    assert!(config.is_none());
+    assert_eq!(
+        std::fs::read_to_string(&cfg_path).unwrap(),
+        "rcon_idle_timeout = \"ten minutes\"\n"
+    );
+}
+
+#[tokio::test]
+async fn listen_address_in_use_fails() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    assert_eq!(
+        verify::check_listen_addr(addr).await.outcome,
+        Outcome::Failed
+    );
+    drop(listener);
+    assert_eq!(
+        verify::check_listen_addr(addr).await.outcome,
+        Outcome::Passed
+    );
+}
+
+#[tokio::test]
+async fn rcon_of_a_stopped_server_is_skipped() {
+    // Reserve a free port, then close it so nothing listens there
+    let port = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let check = verify::check_rcon(&Config::default(), port, "secret").await;
+    assert_eq!(check.outcome, Outcome::Skipped);
+}
+
+#[test]
+fn rcon_disabled_in_server_properties_fails() {
+    let dir = temp_dir("verify-rcon-disabled");
+    let properties = dir.join("server.properties");
+    std::fs::write(&properties, "enable-rcon=false\nrcon.port=25575\n").unwrap();
+    let mut config = Config::default();
+    config.server_properties_path = properties.to_string_lossy().into_owned();
+    assert_eq!(verify::check_rcon_enabled(&config).outcome, Outcome::Failed);
+
+    std::fs::write(&properties, "enable-rcon=true\nrcon.port=25575\n").unwrap();
+    assert_eq!(verify::check_rcon_enabled(&config).outcome, Outcome::Passed);
+}
@@ -0,0 +1,43 @@
+use mcservernap::config::Config;
+use mcservernap::wol::{magic_packet, parse_mac, send_magic_packet};
+use tokio::net::UdpSocket;
+
+#[test]
+fn mac_addresses_are_parsed() {
+    let mac = [0x00, 0x11, 0x22, 0xAA, 0xBB, 0xCC];
+    assert_eq!(parse_mac("00:11:22:aa:bb:cc").unwrap(), mac);
+    assert_eq!(parse_mac("00-11-22-AA-BB-CC").unwrap(), mac);
+    assert!(parse_mac("00:11:22:aa:bb").is_err());
+    assert!(parse_mac("00:11:22:aa:bb:zz").is_err());
+    assert!(parse_mac("0:11:22:aa:bb:cc").is_err());
+}
+
+#[test]
+fn magic_packet_repeats_the_mac() {
+    let mac = [0x00, 0x11, 0x22, 0xAA, 0xBB, 0xCC];
+    let packet = magic_packet(mac);
+    assert_eq!(packet[..6], [0xFF; 6]);
+    assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+    assert_eq!(packet[6..].chunks(6).count(), 16);
+}
+
+#[test]
+fn invalid_wol_mac_is_rejected() {
+    let mut config = Config::default();
+    config.wol_mac = Some("not a mac".to_string());
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn magic_packet_is_sent() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = receiver.local_addr().unwrap().to_string();
+    send_magic_packet("00:11:22:aa:bb:cc", &addr).await.unwrap();
+
+    let mut buf = [0u8; 256];
+    let len = receiver.recv(&mut buf).await.unwrap();
+    assert_eq!(
+        buf[..len],
+        magic_packet(parse_mac("00:11:22:aa:bb:cc").unwrap())
+    );
+}